@@ -1,75 +1,57 @@
-import os
 import pathlib
-import uuid
+import sqlite3
+import json
 
 import pandas as pd
-from dotenv import load_dotenv
-from pymongo import MongoClient, errors
+
+import elo_compnat
 
 # get the path of the current directory
 current_dir = pathlib.Path(__file__).parent.absolute()
 
-# construct the path to the .env file in the parent directory (elo_compnat)
-dotenv_path = current_dir.parent / ".env"
-
-ENV_EXISTS = True
-try:
-    # check if the .env file exists
-    if not os.path.exists(dotenv_path):
-        raise FileNotFoundError
+# o cache fica num arquivo sqlite local, em elo_compnat/, sem precisar de servidor nem de .env
+CACHE_PATH = current_dir.parent / "fitness_cache.sqlite"
 
-    # Load variables from .env
-    load_dotenv(dotenv_path)
 
-    MONGODB_URI = os.getenv("MONGODB_URI")
-    DATABASE_NAME = os.getenv("DATABASE_NAME")
-    COLLECTION_NAME = os.getenv("COLLECTION_NAME")
-    try:
-        # create a client connection to your MongoDB instance
-        client = MongoClient(MONGODB_URI)
-    except:
-        print("Connection error")
-        raise FileNotFoundError
+def fitness_function(filename, config_list, hyperparams_list, seed=0, objective="rmse", cache_path=CACHE_PATH):
+    """Same as elo_compnat.fitness_function, reading repeated evaluations back from the cache file"""
+    return elo_compnat.fitness_function(
+        filename, config_list, hyperparams_list, seed=seed, objective=objective, cache=str(cache_path)
+    )
 
-    # connect to your database
-    db = client[DATABASE_NAME]
 
-    # connect to your collection
-    collection = db[COLLECTION_NAME]
-except FileNotFoundError:
-    ENV_EXISTS = False
-    show_message = False
-    if show_message:
-        horizontal_line = "--------------------------------------------------" * 2
+def load_results(cache_path=CACHE_PATH) -> pd.DataFrame:
+    """Every cached evaluation, one row each, with the config and hyperparameters expanded into columns"""
+    if not pathlib.Path(cache_path).exists():
+        return pd.DataFrame()
 
-        print("\n\n\n")
-        print(horizontal_line)
-        print(
-            "The .env file does not exist. Please create one in the parent directory to use the remote db."
+    with sqlite3.connect(cache_path) as connection:
+        df = pd.read_sql(
+            "SELECT key, version, dataset, run_config, hyperparameters, objective, seed, mean_error, created_at "
+            "FROM fitness ORDER BY created_at",
+            connection,
         )
-        print(horizontal_line)
-        print("\n\n\n")
-
 
-def insert_document(df=pd.DataFrame({"A": [1, 2, 3], "B": [4, 5, 6]})):
-    if ENV_EXISTS:
-        # transform the DataFrame to a nested dictionary and convert pandas series to list
-        data = df.to_dict(orient="series")
-        data = {key: value.tolist() for key, value in data.items()}
+    configs = pd.json_normalize(df.pop("run_config").map(json.loads).tolist())
+    hyperparams = pd.json_normalize(df.pop("hyperparameters").map(json.loads).tolist())
+    return pd.concat([df, configs, hyperparams.add_prefix("hp_")], axis=1)
 
-        # todo: usar os parametros pra criar um hash, que sera o uuid
-        data["_id"] = str(uuid.uuid4())
-        try:
-            collection.insert_one(data)
-        except:
-            print("No connection")
-    else:
-        print("Cannot insert document because .env file does not exist.")
 
+def find_by_id(key, cache_path=CACHE_PATH):
+    results = load_results(cache_path)
+    if results.empty:
+        return None
+    found = results[results["key"] == key]
+    return None if found.empty else found.iloc[0]
 
-def find_by_id(id):
-    pass
 
+def find_by_parameters(parameters, cache_path=CACHE_PATH) -> pd.DataFrame:
+    """Cached evaluations whose config and hyperparameter columns match `parameters`, e.g. {"k_factor": 40}"""
+    results = load_results(cache_path)
+    if results.empty:
+        return results
 
-def find_by_parameters(parameters):
-    pass
+    mask = pd.Series(True, index=results.index)
+    for column, value in parameters.items():
+        mask &= results[column].map(lambda cell: cell == value)
+    return results[mask]
@@ -2,10 +2,11 @@ import elo_compnat
 import numpy as np
 import pygad
 import matplotlib.pyplot as plt
-from cache import insert_document
+from cache import CACHE_PATH
 import pyswarms as ps
 import pyswarms.backend.topology as topologies
 import time
+import pathlib
 from pyswarms.utils.plotters import (plot_cost_history, plot_contour, plot_surface)
 from pyswarms.utils.plotters.formatters import Mesher
 from pyswarms.utils.search import RandomSearch
@@ -14,7 +15,11 @@ CORES = 10
 
 
 
-# TODO: mover pro rust e só passar o caminho do arquivo
+# checkpoints do otimizador nativo, um json por execução (elo_compnat.list_runs / inspect_run)
+RUNS_DIR = pathlib.Path(__file__).parent.parent / "runs"
+
+# o csv é lido e indexado uma vez só, e as avaliações repetidas saem do cache
+DATASET = elo_compnat.Dataset("brasileirao", cache=str(CACHE_PATH))
 
 RunHyperparameters = elo_compnat.RunHyperparameters
 
@@ -38,7 +43,9 @@ gene_space_dict = {
 # esse campo deve ser manualmente setado
 #'tie_frequency': {'low': 0, 'high': 1},
 'w_division_0': {'low': 10, 'high': 80},
-'w_division_1': {'low': 10, 'high': 80}
+'w_division_1': {'low': 10, 'high': 80},
+# fica por último porque não entra na lista do RunConfig, vai como keyword
+'season_regression': {'low': 0, 'high': 1}
 }
 
 """
@@ -49,6 +56,7 @@ pub struct RunConfig {
     pub home_field_advantage_weight: f64,
     pub market_value_weight: f64,
     pub tie_frequency: f64,
+    pub season_regression: f64,
     pub w_division: Vec<f64>,
 }
 """
@@ -60,6 +68,10 @@ def inserir_frequencia(x, valor=0.28, posicao=5) -> list:
     #print("Pos: ", pos)
     return  pre + [valor] + pos
 
+def montar_config(x, valor=0.28):
+    *genes, season_regression = inserir_frequencia(x, valor)
+    return RunConfig.from_list(genes, season_regression=season_regression)
+
 
 def run_swarm(options_list):
     low_values = [value['low'] for value in gene_space_dict.values()]
@@ -85,7 +97,8 @@ def run_swarm(options_list):
                                               options=options, bounds=bounds )
     
     #print("Chamando o optimizer")
-    cost, pos = optimizer.optimize(swarm_fitness_function,  n_processes=CORES, iters=10)
+    # o paralelismo já está no evaluate_batch, processos extras só duplicariam o dataset
+    cost, pos = optimizer.optimize(swarm_fitness_function, iters=10)
     print(f"==== Final cost for this swarm: {cost} ==== and parameters = {np.round(pos, 2)}")
     return cost, pos
 
@@ -103,28 +116,13 @@ def meta_swarm_fitness_function(x_list):
     return cost_list
 
 def swarm_fitness_function(x_list_of_lists):
-    aggregated_fitness = np.zeros(len(x_list_of_lists))
+    config_list = [montar_config(x) for x in x_list_of_lists]
 
-    for idx, x in enumerate(x_list_of_lists):
-
-        # Create a config_list of size n+1
-        config_list = inserir_frequencia(x)
-        #print("x: ", config_list)
-        #print("Hyperparams list: ", hyperparams_list)
-        
+    # o enxame inteiro é avaliado de uma vez, em paralelo dentro do rust e sem o GIL
+    errors = DATASET.evaluate_batch(config_list, hyperparams_list)
 
-        start = time.perf_counter()
-        #print("Hip: ", hyperparams_list)
-        err = elo_compnat.fitness_function("brasileirao", config_list, hyperparams_list)
-        fitness = np.sum(np.abs(err))
-
-        #print("Fitness function time: ", time.perf_counter() - start, " for solution ", idx, " with fitness ", fitness)
-        # Append the fitness to the aggregated list
-        aggregated_fitness[idx] = fitness
-    #print("Aggregated fitness: ", aggregated_fitness)
-    #print(aggregated_fitness.shape)
     # Return the aggregated fitness list
-    return aggregated_fitness
+    return np.array([np.sum(np.abs(err)) for err in errors])
 
 # resultado podre:     [ 0.00185522, -0.02959553,  0.83426157, -0.79967734, -0.0774037,   0.02039052  0.05995651]
 
@@ -165,9 +163,6 @@ def run_genetic_algo():
         plt.xlabel(f"Temporada simulada")
         plt.show()
 
-    # usaremos isso aqui pra salvar em nuvem os resultados
-    # insert_document()
-
     fitness_function = fitness_func
 
 
@@ -240,15 +235,12 @@ def run_genetic_algo():
     ga_instance.plot_genes()
 
 def fitness_func(ga_instance, solution, solution_idx):
-    position = 5
-    # Create a config_list of size n+1
-    config_list = solution[:position].tolist() + [0.28] + solution[position:].tolist()
     # global parameters, we dont change them
-    run_config_obj = RunConfig.from_list(config_list)
+    run_config_obj = montar_config(solution)
     # print(run_config_obj.__dict__)
     start = time.perf_counter()
-    err = elo_compnat.fitness_function(
-        "brasileirao", solution, hyperparams_list)
+    # a solução não tem tie_frequency nem season_regression, quem completa é o montar_config
+    err = DATASET.evaluate(run_config_obj, hyperparams_list)
     fitness = np.divide(1, np.sum(np.abs(err)))
     print("Fitness function time: ", time.perf_counter() - start, " for solution ", solution_idx, " with fitness ", fitness)
     return fitness
@@ -259,6 +251,33 @@ def on_gen(ga_instance):
     print("Generation : ", ga_instance.generations_completed)
     print("Fitness of the best solution :", ga_instance.best_solution()[1])
 
+def run_native_optimizer(algorithm="pso", seed=42):
+    # mesmo espaço do gene_space_dict, mas tudo roda no rust e o csv é lido uma vez só
+    bounds = {name: (value['low'], value['high']) for name, value in gene_space_dict.items()}
+    base_config = RunConfig(40, 1, 1, 0.0075, 1, 0.28, [40, 20])
+
+    # se o processo cair, rodar de novo continua do último checkpoint com o mesmo resultado
+    checkpoint = RUNS_DIR / f"{algorithm}_{seed}.json"
+    result = elo_compnat.optimize("brasileirao", hyperparams_list, algorithm=algorithm,
+                                  base_config=base_config, bounds=bounds,
+                                  population=10, iterations=10, seed=seed, cache=str(CACHE_PATH),
+                                  checkpoint=str(checkpoint))
+
+    print(f"==== Best cost ({algorithm}): {result['best_cost']} ==== and parameters = {result['best_values']}")
+
+    plt.plot(result['history']['iteration'], result['history']['best_so_far'])
+    plt.title("Cost history")
+    plt.show()
+    return result
+
+
+def list_native_runs():
+    for run in elo_compnat.list_runs(str(RUNS_DIR)):
+        status = "finished" if run['finished'] else "interrupted"
+        print(f"{run['path']}: {run['algorithm']} {run['iteration']}/{run['iterations']} "
+              f"best = {run['best_cost']} ({status})")
+
+
 def main():
 
     #run_genetic_algo()
@@ -321,7 +340,7 @@ def main():
         
 
         
-        cost, pos = optimizer.optimize(swarm_fitness_function,  n_processes=CORES, iters=50)
+        cost, pos = optimizer.optimize(swarm_fitness_function, iters=50)
 
         print("Best cost: ", cost)
         print("Best position: ", pos)
@@ -331,6 +350,7 @@ def main():
         plt.show()
 
     #optimize_elo()
+    #run_native_optimizer("cmaes")
 
     # TODO: implement grid search pyswarms.utils.search.random_search module
 
@@ -0,0 +1,294 @@
+use crate::util::game::{Game, GameResult};
+
+use super::rating_system::{davidson_probabilities, RatingSystem};
+
+/// Points per game of a team nobody knows anything about, about what an average side makes
+pub const AVERAGE_POINTS_PER_GAME: f64 = 1.35;
+
+/// Same H/D/A probabilities for every match, the shares seen in the training matches
+#[derive(Debug, Clone, Copy)]
+pub struct BaseRates {
+    pub home: f64,
+    pub draw: f64,
+    pub away: f64,
+}
+
+impl BaseRates {
+    /// Shares of home wins, draws and away wins in `games`. Uniform if there are no games
+    pub fn from_games(games: &[Game]) -> Self {
+        if games.is_empty() {
+            return BaseRates {
+                home: 1.0 / 3.0,
+                draw: 1.0 / 3.0,
+                away: 1.0 / 3.0,
+            };
+        }
+
+        let mut counts = [0usize; 3];
+        for game in games {
+            match game.result {
+                GameResult::H => counts[0] += 1,
+                GameResult::D => counts[1] += 1,
+                GameResult::A => counts[2] += 1,
+            }
+        }
+
+        let share = |count: usize| count as f64 / games.len() as f64;
+        BaseRates {
+            home: share(counts[0]),
+            draw: share(counts[1]),
+            away: share(counts[2]),
+        }
+    }
+}
+
+impl RatingSystem for BaseRates {
+    type Rating = ();
+
+    fn name(&self) -> &'static str {
+        "base_rates"
+    }
+
+    fn initial_rating(&self, _starting_elo: f64) {}
+
+    fn rating_value(&self, _rating: &()) -> f64 {
+        0.0
+    }
+
+    fn with_rating_value(&self, _rating: &(), _value: f64) {}
+
+    fn expected_probabilities(&self, _home: &(), _away: &()) -> (f64, f64, f64) {
+        (self.draw, self.home, self.away)
+    }
+
+    fn rate(&self, _home: &(), _away: &(), _game: &Game) -> ((), ()) {
+        ((), ())
+    }
+}
+
+/// Every match is a coin with three equal sides, so any team can win the league
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniformLeague;
+
+impl RatingSystem for UniformLeague {
+    type Rating = ();
+
+    fn name(&self) -> &'static str {
+        "uniform"
+    }
+
+    fn initial_rating(&self, _starting_elo: f64) {}
+
+    fn rating_value(&self, _rating: &()) -> f64 {
+        0.0
+    }
+
+    fn with_rating_value(&self, _rating: &(), _value: f64) {}
+
+    fn expected_probabilities(&self, _home: &(), _away: &()) -> (f64, f64, f64) {
+        (1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0)
+    }
+
+    fn rate(&self, _home: &(), _away: &(), _game: &Game) -> ((), ()) {
+        ((), ())
+    }
+}
+
+/// Points per game of the last finished season, plus what the team is making in the current one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LastSeasonRating {
+    pub previous_points_per_game: f64,
+    pub points: u16,
+    pub played: u16,
+}
+
+/// Repeats last season's table: the strength of a team is its points per game in the previous season,
+/// and `E = ppg_home / (ppg_home + ppg_away)` goes through the Davidson draw model. Promoted teams get
+/// the points per game of the teams they replaced, see [`super::train::start_next_season`]
+#[derive(Debug, Clone, Copy)]
+pub struct LastSeasonTable {
+    pub tie_frequency: f64,
+}
+
+impl RatingSystem for LastSeasonTable {
+    type Rating = LastSeasonRating;
+
+    fn name(&self) -> &'static str {
+        "last_season"
+    }
+
+    fn initial_rating(&self, _starting_elo: f64) -> LastSeasonRating {
+        LastSeasonRating {
+            previous_points_per_game: AVERAGE_POINTS_PER_GAME,
+            points: 0,
+            played: 0,
+        }
+    }
+
+    fn rating_value(&self, rating: &LastSeasonRating) -> f64 {
+        rating.previous_points_per_game
+    }
+
+    fn with_rating_value(&self, rating: &LastSeasonRating, value: f64) -> LastSeasonRating {
+        LastSeasonRating {
+            previous_points_per_game: value,
+            ..*rating
+        }
+    }
+
+    fn new_season_rating(&self, rating: &LastSeasonRating) -> LastSeasonRating {
+        if rating.played == 0 {
+            return *rating;
+        }
+
+        LastSeasonRating {
+            previous_points_per_game: rating.points as f64 / rating.played as f64,
+            points: 0,
+            played: 0,
+        }
+    }
+
+    fn expected_probabilities(&self, home: &LastSeasonRating, away: &LastSeasonRating) -> (f64, f64, f64) {
+        davidson_probabilities(
+            strength_share(home.previous_points_per_game, away.previous_points_per_game),
+            self.tie_frequency,
+        )
+    }
+
+    fn rate(&self, home: &LastSeasonRating, away: &LastSeasonRating, game: &Game) -> (LastSeasonRating, LastSeasonRating) {
+        let (home_points, away_points) = match game.result {
+            GameResult::H => (3, 0),
+            GameResult::D => (1, 1),
+            GameResult::A => (0, 3),
+        };
+
+        (
+            LastSeasonRating {
+                points: home.points + home_points,
+                played: home.played + 1,
+                ..*home
+            },
+            LastSeasonRating {
+                points: away.points + away_points,
+                played: away.played + 1,
+                ..*away
+            },
+        )
+    }
+}
+
+/// Market value of the squad, as in the last match the team played
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MarketValueRating {
+    pub value: f64,
+}
+
+/// Only looks at the market values: `E = value_home / (value_home + value_away)`, through the Davidson
+/// draw model. Teams without a value yet (0) are even with everyone
+#[derive(Debug, Clone, Copy)]
+pub struct MarketValueOnly {
+    pub tie_frequency: f64,
+}
+
+impl RatingSystem for MarketValueOnly {
+    type Rating = MarketValueRating;
+
+    fn name(&self) -> &'static str {
+        "market_value"
+    }
+
+    fn initial_rating(&self, _starting_elo: f64) -> MarketValueRating {
+        MarketValueRating::default()
+    }
+
+    fn rating_value(&self, rating: &MarketValueRating) -> f64 {
+        rating.value
+    }
+
+    fn with_rating_value(&self, _rating: &MarketValueRating, value: f64) -> MarketValueRating {
+        MarketValueRating { value }
+    }
+
+    fn expected_probabilities(&self, home: &MarketValueRating, away: &MarketValueRating) -> (f64, f64, f64) {
+        davidson_probabilities(strength_share(home.value, away.value), self.tie_frequency)
+    }
+
+    fn rate(&self, _home: &MarketValueRating, _away: &MarketValueRating, game: &Game) -> (MarketValueRating, MarketValueRating) {
+        (
+            MarketValueRating { value: game.home_value },
+            MarketValueRating { value: game.away_value },
+        )
+    }
+}
+
+/// Bradley-Terry share of the home side, 0.5 when either strength is unknown
+fn strength_share(home: f64, away: f64) -> f64 {
+    if home <= 0.0 || away <= 0.0 {
+        return 0.5;
+    }
+    home / (home + away)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-12;
+
+    fn game(home_score: u16, away_score: u16) -> Game {
+        Game::fixture(NaiveDate::from_ymd_opt(2020, 6, 1).unwrap(), "A", "B", 1).with_score(home_score, away_score)
+    }
+
+    #[test]
+    fn base_rates_are_the_shares_of_each_result() {
+        let games = [game(1, 0), game(2, 1), game(3, 0), game(1, 1), game(0, 2)];
+        let rates = BaseRates::from_games(&games);
+
+        assert_eq!((rates.home, rates.draw, rates.away), (0.6, 0.2, 0.2));
+        assert_eq!(rates.expected_probabilities(&(), &()), (0.2, 0.6, 0.2));
+
+        let empty = BaseRates::from_games(&[]);
+        assert_eq!((empty.home, empty.draw, empty.away), (1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0));
+    }
+
+    #[test]
+    fn the_uniform_league_gives_a_third_to_each_result() {
+        assert_eq!(UniformLeague.expected_probabilities(&(), &()), (1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0));
+    }
+
+    #[test]
+    fn last_season_points_become_points_per_game() {
+        let system = LastSeasonTable { tie_frequency: 0.25 };
+        let (mut home, mut away) = (system.initial_rating(1000.0), system.initial_rating(1000.0));
+        // vitória, empate e derrota do mandante: 4 pontos contra 4
+        for result in [game(2, 0), game(1, 1), game(0, 1)] {
+            (home, away) = system.rate(&home, &away, &result);
+        }
+        assert_eq!((home.points, home.played), (4, 3));
+
+        let next = system.new_season_rating(&home);
+        assert!((next.previous_points_per_game - 4.0 / 3.0).abs() < TOLERANCE);
+        assert_eq!((next.points, next.played), (0, 0));
+
+        // quem não jogou mantém o que tinha
+        let idle = system.initial_rating(1000.0);
+        assert_eq!(system.new_season_rating(&idle), idle);
+        assert_eq!(idle.previous_points_per_game, AVERAGE_POINTS_PER_GAME);
+    }
+
+    #[test]
+    fn unknown_strengths_make_an_even_match() {
+        assert_eq!(strength_share(0.0, 2.0), 0.5);
+        assert_eq!(strength_share(3.0, 0.0), 0.5);
+        assert_eq!(strength_share(-1.0, 1.0), 0.5);
+        assert!((strength_share(3.0, 1.0) - 0.75).abs() < TOLERANCE);
+
+        let system = MarketValueOnly { tie_frequency: 0.25 };
+        let unknown = MarketValueRating::default();
+        let (draw, home, away) = system.expected_probabilities(&unknown, &MarketValueRating { value: 5.0 });
+        assert!((home - away).abs() < TOLERANCE);
+        assert!((draw - 0.25).abs() < TOLERANCE);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod baselines;
+pub mod rating_system;
 pub mod train;
 pub mod util;
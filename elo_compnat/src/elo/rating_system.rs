@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use skillratings::glicko2::{Glicko2Config, Glicko2Rating};
+use skillratings::trueskill::{TrueSkillConfig, TrueSkillRating};
+use skillratings::weng_lin::{WengLinConfig, WengLinRating};
+
+use crate::experimentation::run_config::{CustomElo, CustomRating, RunConfig};
+use crate::util::game::Game;
+
+/// Ratings of every team, keyed by name
+pub type RatingTable<R> = HashMap<String, R>;
+
+/// What a season of simulated matches tells the rating system, used to adapt it before the next one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeasonStats {
+    /// Share of real matches that ended in a draw
+    pub draw_share: f64,
+    /// Sum of the rating changes of the home and away sides
+    pub home_rating_variation: f64,
+    pub away_rating_variation: f64,
+}
+
+/// A rating model that can be trained on matches and used to simulate them. [`CustomElo`] is the one we
+/// tune, the skillratings adapters are there to benchmark it on the same backtest
+pub trait RatingSystem: Clone + Send + Sync {
+    type Rating: Copy + std::fmt::Debug + Send + Sync;
+
+    fn name(&self) -> &'static str;
+
+    /// Rating of a team seen for the first time. `starting_elo` comes from the hyperparameters, systems
+    /// with their own scale may ignore it
+    fn initial_rating(&self, starting_elo: f64) -> Self::Rating;
+
+    /// Single number used to display and compare ratings
+    fn rating_value(&self, rating: &Self::Rating) -> f64;
+
+    /// `rating` moved to `value`, keeping whatever else the system tracks (e.g. the uncertainty)
+    fn with_rating_value(&self, rating: &Self::Rating, value: f64) -> Self::Rating;
+
+    /// Rating a team carries into a new season, before the regression to the mean. Most systems keep it
+    fn new_season_rating(&self, rating: &Self::Rating) -> Self::Rating {
+        *rating
+    }
+
+    /// Fraction of the way to the division mean each rating is moved between seasons
+    fn season_regression(&self) -> f64 {
+        0.0
+    }
+
+    /// Probabilities of (draw, home win, away win), in the same order as [`crate::experimentation::run_config::expected_score`]
+    fn expected_probabilities(&self, home: &Self::Rating, away: &Self::Rating) -> (f64, f64, f64);
+
+    /// New ratings of both teams after `game`, which carries the result and the scores to use
+    fn rate(&self, home: &Self::Rating, away: &Self::Rating, game: &Game) -> (Self::Rating, Self::Rating);
+
+    /// System used for the next season. Stateless systems just carry on unchanged
+    fn after_season(&self, _stats: &SeasonStats) -> Self {
+        self.clone()
+    }
+}
+
+impl RatingSystem for CustomElo {
+    type Rating = CustomRating;
+
+    fn name(&self) -> &'static str {
+        "elo"
+    }
+
+    fn initial_rating(&self, starting_elo: f64) -> CustomRating {
+        CustomRating {
+            rating: starting_elo,
+        }
+    }
+
+    fn rating_value(&self, rating: &CustomRating) -> f64 {
+        rating.rating
+    }
+
+    fn with_rating_value(&self, _rating: &CustomRating, value: f64) -> CustomRating {
+        CustomRating { rating: value }
+    }
+
+    fn season_regression(&self) -> f64 {
+        self.config.season_regression
+    }
+
+    fn expected_probabilities(&self, home: &CustomRating, away: &CustomRating) -> (f64, f64, f64) {
+        self.expected_score(home, away)
+    }
+
+    fn rate(&self, home: &CustomRating, away: &CustomRating, game: &Game) -> (CustomRating, CustomRating) {
+        let absolute_goal_diff: f64 = ((game.home_score as i16) - (game.away_score as i16))
+            .abs()
+            .into();
+
+        let absolute_market_value_diff: f64 = (game.home_value - game.away_value).abs();
+
+        CustomElo::rate(
+            self,
+            home,
+            away,
+            game.result,
+            absolute_goal_diff,
+            absolute_market_value_diff,
+            game.division as usize,
+        )
+    }
+
+    /// The draw frequency follows the last season, and the home advantage moves with the difference
+    /// between what home and away sides gained
+    fn after_season(&self, stats: &SeasonStats) -> Self {
+        let mut next = self.clone();
+
+        next.config.tie_frequency = stats.draw_share;
+
+        if self.components.home_advantage {
+            let delta_elo = stats.home_rating_variation - stats.away_rating_variation;
+            next.config.home_advantage += next.config.home_field_advantage_weight * delta_elo;
+        }
+
+        next
+    }
+}
+
+/// Turns the expected score of the home side into (draw, home, away) probabilities with a Davidson draw
+/// model on the strengths `sqrt(E)` and `sqrt(1 - E)`, so every adapter is scored on H/D/A the same way.
+/// It is not the parameterization of [`expected_score`](crate::experimentation::run_config::expected_score)
+/// used by [`CustomElo`], which compares `10^(±d/400)` with a constant draw term: both give a draw
+/// `tie_frequency` of the time between even sides, but they spread the odds differently as the gap grows
+pub fn davidson_probabilities(expected_home: f64, tie_frequency: f64) -> (f64, f64, f64) {
+    let expected_home = expected_home.clamp(f64::EPSILON, 1.0 - f64::EPSILON);
+    let kappa = 2.0 * tie_frequency / (1.0 - tie_frequency);
+
+    let home = expected_home.sqrt();
+    let away = (1.0 - expected_home).sqrt();
+    let denominator = home + away + kappa * (home * away).sqrt();
+
+    let p_home = home / denominator;
+    let p_away = away / denominator;
+    (1.0 - p_home - p_away, p_home, p_away)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Glicko2System {
+    pub config: Glicko2Config,
+    pub tie_frequency: f64,
+    pub season_regression: f64,
+}
+
+impl Glicko2System {
+    pub fn from_run_config(run_config: &RunConfig) -> Self {
+        Glicko2System {
+            config: Glicko2Config::new(),
+            tie_frequency: run_config.tie_frequency,
+            season_regression: run_config.season_regression,
+        }
+    }
+}
+
+impl RatingSystem for Glicko2System {
+    type Rating = Glicko2Rating;
+
+    fn name(&self) -> &'static str {
+        "glicko2"
+    }
+
+    /// Glicko-2 shares the elo scale, so teams start at `starting_elo` with the default deviation
+    fn initial_rating(&self, starting_elo: f64) -> Glicko2Rating {
+        Glicko2Rating {
+            rating: starting_elo,
+            ..Glicko2Rating::new()
+        }
+    }
+
+    fn rating_value(&self, rating: &Glicko2Rating) -> f64 {
+        rating.rating
+    }
+
+    fn with_rating_value(&self, rating: &Glicko2Rating, value: f64) -> Glicko2Rating {
+        Glicko2Rating {
+            rating: value,
+            ..*rating
+        }
+    }
+
+    fn season_regression(&self) -> f64 {
+        self.season_regression
+    }
+
+    fn expected_probabilities(&self, home: &Glicko2Rating, away: &Glicko2Rating) -> (f64, f64, f64) {
+        let (expected_home, _) = skillratings::glicko2::expected_score(home, away);
+        davidson_probabilities(expected_home, self.tie_frequency)
+    }
+
+    fn rate(&self, home: &Glicko2Rating, away: &Glicko2Rating, game: &Game) -> (Glicko2Rating, Glicko2Rating) {
+        let (outcome, _) = game.get_match_outcome();
+        skillratings::glicko2::glicko2(home, away, &outcome, &self.config)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrueSkillSystem {
+    pub config: TrueSkillConfig,
+    pub tie_frequency: f64,
+    pub season_regression: f64,
+}
+
+impl TrueSkillSystem {
+    pub fn from_run_config(run_config: &RunConfig) -> Self {
+        TrueSkillSystem {
+            config: TrueSkillConfig {
+                draw_probability: run_config.tie_frequency,
+                ..TrueSkillConfig::new()
+            },
+            tie_frequency: run_config.tie_frequency,
+            season_regression: run_config.season_regression,
+        }
+    }
+}
+
+impl RatingSystem for TrueSkillSystem {
+    type Rating = TrueSkillRating;
+
+    fn name(&self) -> &'static str {
+        "trueskill"
+    }
+
+    fn initial_rating(&self, _starting_elo: f64) -> TrueSkillRating {
+        TrueSkillRating::new()
+    }
+
+    fn rating_value(&self, rating: &TrueSkillRating) -> f64 {
+        rating.rating
+    }
+
+    fn with_rating_value(&self, rating: &TrueSkillRating, value: f64) -> TrueSkillRating {
+        TrueSkillRating {
+            rating: value,
+            ..*rating
+        }
+    }
+
+    fn season_regression(&self) -> f64 {
+        self.season_regression
+    }
+
+    fn expected_probabilities(&self, home: &TrueSkillRating, away: &TrueSkillRating) -> (f64, f64, f64) {
+        let (expected_home, _) = skillratings::trueskill::expected_score(home, away, &self.config);
+        davidson_probabilities(expected_home, self.tie_frequency)
+    }
+
+    fn rate(&self, home: &TrueSkillRating, away: &TrueSkillRating, game: &Game) -> (TrueSkillRating, TrueSkillRating) {
+        let (outcome, _) = game.get_match_outcome();
+        skillratings::trueskill::trueskill(home, away, &outcome, &self.config)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WengLinSystem {
+    pub config: WengLinConfig,
+    pub tie_frequency: f64,
+    pub season_regression: f64,
+}
+
+impl WengLinSystem {
+    pub fn from_run_config(run_config: &RunConfig) -> Self {
+        WengLinSystem {
+            config: WengLinConfig::new(),
+            tie_frequency: run_config.tie_frequency,
+            season_regression: run_config.season_regression,
+        }
+    }
+}
+
+impl RatingSystem for WengLinSystem {
+    type Rating = WengLinRating;
+
+    fn name(&self) -> &'static str {
+        "weng_lin"
+    }
+
+    fn initial_rating(&self, _starting_elo: f64) -> WengLinRating {
+        WengLinRating::new()
+    }
+
+    fn rating_value(&self, rating: &WengLinRating) -> f64 {
+        rating.rating
+    }
+
+    fn with_rating_value(&self, rating: &WengLinRating, value: f64) -> WengLinRating {
+        WengLinRating {
+            rating: value,
+            ..*rating
+        }
+    }
+
+    fn season_regression(&self) -> f64 {
+        self.season_regression
+    }
+
+    fn expected_probabilities(&self, home: &WengLinRating, away: &WengLinRating) -> (f64, f64, f64) {
+        let (expected_home, _) = skillratings::weng_lin::expected_score(home, away, &self.config);
+        davidson_probabilities(expected_home, self.tie_frequency)
+    }
+
+    fn rate(&self, home: &WengLinRating, away: &WengLinRating, game: &Game) -> (WengLinRating, WengLinRating) {
+        let (outcome, _) = game.get_match_outcome();
+        skillratings::weng_lin::weng_lin(home, away, &outcome, &self.config)
+    }
+}
+
+/// The rating systems that can be picked by name from python. The last four are the baselines of
+/// [`crate::elo::baselines`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RatingSystemKind {
+    Elo,
+    Glicko2,
+    TrueSkill,
+    WengLin,
+    BaseRates,
+    LastSeason,
+    MarketValue,
+    Uniform,
+}
+
+impl RatingSystemKind {
+    pub const ALL: [RatingSystemKind; 8] = [
+        RatingSystemKind::Elo,
+        RatingSystemKind::Glicko2,
+        RatingSystemKind::TrueSkill,
+        RatingSystemKind::WengLin,
+        RatingSystemKind::BaseRates,
+        RatingSystemKind::LastSeason,
+        RatingSystemKind::MarketValue,
+        RatingSystemKind::Uniform,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RatingSystemKind::Elo => "elo",
+            RatingSystemKind::Glicko2 => "glicko2",
+            RatingSystemKind::TrueSkill => "trueskill",
+            RatingSystemKind::WengLin => "weng_lin",
+            RatingSystemKind::BaseRates => "base_rates",
+            RatingSystemKind::LastSeason => "last_season",
+            RatingSystemKind::MarketValue => "market_value",
+            RatingSystemKind::Uniform => "uniform",
+        }
+    }
+}
+
+impl FromStr for RatingSystemKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RatingSystemKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s.to_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "Unknown rating system '{}'. Options are {:?}",
+                    s,
+                    RatingSystemKind::ALL.map(|kind| kind.name())
+                )
+            })
+    }
+}
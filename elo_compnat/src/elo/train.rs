@@ -1,39 +1,152 @@
-use std::collections::HashMap;
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::experimentation::run_config;
 use crate::util::game::Game;
+use crate::util::math::mean;
 
-use super::util::season::{construct_seasons, get_seasons_in_season_map, SeasonMap};
+use super::rating_system::{RatingSystem, RatingTable};
+use super::util::season::{construct_seasons, get_seasons_in_season_map, Season, SeasonMap};
 
-use super::super::{CustomElo, CustomRating, RunConfig};
+use super::super::CustomRating;
 
-pub type RankedMatch = (CustomRating, skillratings::Outcomes);
-pub type EloTable = HashMap<String, CustomRating>;
+pub type RankedMatch<R = CustomRating> = (R, skillratings::Outcomes);
+pub type EloTable = RatingTable<CustomRating>;
 
 const DEBUG_INFO: bool = false;
 
-pub fn construct_elo_table_for_year(
-    partidas: &Vec<Game>,
-    starting_elos: Option<EloTable>,
-    elo_config: Option<&RunConfig>,
+/// A match together with the ratings around it. `game.home_elo` and `game.away_elo` hold the ratings
+/// before kick-off, the `_after` fields the ratings once the result is applied
+#[derive(Debug, Clone)]
+pub struct RatedGame {
+    pub game: Game,
+    pub home_elo_after: f64,
+    pub away_elo_after: f64,
+    pub prob_home: f64,
+    pub prob_draw: f64,
+    pub prob_away: f64,
+}
+
+pub fn construct_elo_table_for_year<S: RatingSystem>(
+    rating_system: &S,
+    partidas: &[Game],
+    starting_elos: Option<RatingTable<S::Rating>>,
     run_hyperparameters: &run_config::RunHyperparameters,
-) -> EloTable {
-    // Construir tabela de elo se vier vazia
-    let mut elo_table = match starting_elos {
-        Some(elos) => elos,
-        None => HashMap::new(),
-    };
+) -> RatingTable<S::Rating> {
+    rate_games_for_year(rating_system, partidas, starting_elos, run_hyperparameters, None)
+}
+
+/// Same walk as [`construct_elo_table_for_year`], also returning every match with its pre and post-match
+/// ratings and the expected H/D/A probabilities
+pub fn construct_rated_games_for_year<S: RatingSystem>(
+    rating_system: &S,
+    partidas: &[Game],
+    starting_elos: Option<RatingTable<S::Rating>>,
+    run_hyperparameters: &run_config::RunHyperparameters,
+) -> (RatingTable<S::Rating>, Vec<RatedGame>) {
+    let mut rated_games = Vec::with_capacity(partidas.len());
+    let elo_table = rate_games_for_year(
+        rating_system,
+        partidas,
+        starting_elos,
+        run_hyperparameters,
+        Some(&mut rated_games),
+    );
+    (elo_table, rated_games)
+}
+
+/// Season boundary step, applied to the ratings at the end of `finished` before `next` starts.
+///
+/// Every team that played `finished` is pulled towards the mean rating of its division by
+/// [`RatingSystem::season_regression`]. Teams of `next` that did not play `finished` (promoted from a
+/// division outside the data, new to it, or back after some seasons away) start at the mean rating of
+/// the teams that dropped out of the division they enter. When there are none, returning teams keep their
+/// old rating and new ones get `starting_elo`
+pub fn start_next_season<S: RatingSystem>(
+    rating_system: &S,
+    mut elo_table: RatingTable<S::Rating>,
+    finished: &Season,
+    next: &Season,
+    run_hyperparameters: &run_config::RunHyperparameters,
+) -> RatingTable<S::Rating> {
+    // ordenado por nome, para as médias somarem sempre na mesma ordem
+    let before: BTreeMap<String, u8> = finished.team_divisions().into_iter().collect();
+    let after = next.team_divisions();
+
+    for rating in elo_table.values_mut() {
+        *rating = rating_system.new_season_rating(rating);
+    }
 
-    let default_config = RunConfig::default();
-    let elo_config = match elo_config {
-        Some(config) => config,
-        None => &default_config,
+    let division_mean = |table: &RatingTable<S::Rating>, keep: &dyn Fn(&str, u8) -> bool| {
+        let mut values: HashMap<u8, Vec<f64>> = HashMap::new();
+        for (team, &division) in &before {
+            if let Some(rating) = table.get(team).filter(|_| keep(team, division)) {
+                values.entry(division).or_default().push(rating_system.rating_value(rating));
+            }
+        }
+        values
+            .into_iter()
+            .filter_map(|(division, values)| mean(&values).map(|m| (division, m)))
+            .collect::<HashMap<u8, f64>>()
     };
 
-    let mut results_table: HashMap<String, Vec<RankedMatch>> = HashMap::new();
+    let regression = rating_system.season_regression();
+    if regression != 0.0 {
+        let means = division_mean(&elo_table, &|_, _| true);
+        for (team, division) in &before {
+            if let (Some(rating), Some(&target)) = (elo_table.get_mut(team), means.get(division)) {
+                let value = rating_system.rating_value(rating);
+                *rating = rating_system.with_rating_value(rating, value + regression * (target - value));
+            }
+        }
+    }
+
+    // quem saiu da divisão por baixo (rebaixado ou fora dos dados) define o rating de quem entra
+    let dropped_mean = division_mean(&elo_table, &|team, division| {
+        after.get(team).is_none_or(|&next_division| next_division > division)
+    });
+
+    let starting_rating = rating_system.initial_rating(run_hyperparameters.starting_elo as f64);
+    for (team, division) in &after {
+        if before.contains_key(team) {
+            continue;
+        }
+
+        // o rating antigo de quem volta depois de anos fora não diz mais nada sobre o time
+        match dropped_mean.get(division) {
+            Some(&value) => {
+                elo_table.insert(team.clone(), rating_system.with_rating_value(&starting_rating, value));
+            }
+            None => {
+                elo_table.entry(team.clone()).or_insert(starting_rating);
+            }
+        }
+    }
+
+    elo_table
+}
+
+fn rate_games_for_year<S: RatingSystem>(
+    rating_system: &S,
+    partidas: &[Game],
+    starting_elos: Option<RatingTable<S::Rating>>,
+    run_hyperparameters: &run_config::RunHyperparameters,
+    mut rated_games: Option<&mut Vec<RatedGame>>,
+) -> RatingTable<S::Rating> {
+    // Construir tabela de elo se vier vazia
+    let mut elo_table = starting_elos.unwrap_or_default();
+
+    let mut results_table: HashMap<String, Vec<RankedMatch<S::Rating>>> = HashMap::new();
+
+    let components = run_hyperparameters.components();
+    let starting_rating = rating_system.initial_rating(run_hyperparameters.starting_elo as f64);
 
     // Salvar histórico de elo desses times
     for partida in partidas {
+        if !components.uses_division(partida.division) {
+            continue;
+        }
+
         let home_team = partida.home.clone();
         let away_team = partida.away.clone();
 
@@ -42,55 +155,56 @@ pub fn construct_elo_table_for_year(
         let current_elo = |team_name: &String| {
             elo_table
                 .get(team_name)
-                .cloned()
-                .unwrap_or_else(|| CustomRating {rating: run_hyperparameters.starting_elo as f64})
+                .copied()
+                .unwrap_or(starting_rating)
         };
 
         let home_team_elo = current_elo(&home_team);
         let away_team_elo = current_elo(&away_team);
 
 
-        if home_team_elo.rating.is_nan()  {
+        if rating_system.rating_value(&home_team_elo).is_nan()  {
             println!("Elo is NaN for home team: {} at match {}", &home_team, partida.week);
             //let results_home = results_table.get(&home_team).unwrap();
            // println!("Results: {:?}", results_home);
         }
-        if away_team_elo.rating.is_nan() {
+        if rating_system.rating_value(&away_team_elo).is_nan() {
             println!("Elo is NaN for away_team: {} at match {}", &away_team, partida.week);
             //let results_home = results_table.get(&away_team).unwrap();
            // println!("Results: {:?}", results_home);
         }
 
         // Salvar histórico de resultados desses times e elos
-        let mut insert_result = |team_name: &String, current_elo: &CustomRating, outcome| {
+        let mut insert_result = |team_name: &String, current_elo: &S::Rating, outcome| {
             results_table
                 .entry(team_name.clone())
-                .or_insert(Vec::new())
+                .or_default()
                 .push((*current_elo, outcome));
         };
 
         insert_result(&home_team, &home_team_elo, home_outcome);
         insert_result(&away_team, &away_team_elo, away_outcome);
 
-        let custom_elo = CustomElo {
-            config: elo_config.clone(),
-        };
-
-        let absolute_goal_diff: f64 = ((partida.home_score as i16) - (partida.away_score as i16))
-            .abs()
-            .into();
-
-        let absolute_market_value_diff: f64 = (partida.home_value - partida.away_value)
-        .abs();
-
-        let (new_player_home, new_player_away) = custom_elo.rate(
-            &home_team_elo,
-            &away_team_elo,
-            partida.result,
-            absolute_goal_diff,
-            absolute_market_value_diff,
-            partida.division as usize
-        );
+        let (new_player_home, new_player_away) =
+            rating_system.rate(&home_team_elo, &away_team_elo, partida);
+
+        if let Some(rated_games) = rated_games.as_deref_mut() {
+            let (prob_draw, prob_home, prob_away) =
+                rating_system.expected_probabilities(&home_team_elo, &away_team_elo);
+
+            let mut game = partida.clone();
+            game.home_elo = Some(rating_system.rating_value(&home_team_elo));
+            game.away_elo = Some(rating_system.rating_value(&away_team_elo));
+
+            rated_games.push(RatedGame {
+                game,
+                home_elo_after: rating_system.rating_value(&new_player_home),
+                away_elo_after: rating_system.rating_value(&new_player_away),
+                prob_home,
+                prob_draw,
+                prob_away,
+            });
+        }
 
         elo_table.insert(home_team, new_player_home);
         elo_table.insert(away_team, new_player_away);
@@ -98,6 +212,26 @@ pub fn construct_elo_table_for_year(
     elo_table
 }
 
+/// Error message when a season between `start_year` and `end_year` isn't in `all_matches`, the case
+/// that makes the time series functions panic
+pub fn validate_time_series_interval(all_matches: &[Game], start_year: u16, end_year: u16) -> Result<(), String> {
+    let years: std::collections::BTreeSet<u16> = all_matches.iter().map(|game| game.year).collect();
+    let (Some(&first), Some(&last)) = (years.first(), years.last()) else {
+        return Err(String::from("The dataset has no matches"));
+    };
+
+    if start_year > end_year {
+        return Err(format!("end_year {} is before the starting year {}", end_year, start_year));
+    }
+    match (start_year..=end_year).find(|year| !years.contains(year)) {
+        Some(year) => Err(format!(
+            "Season {} is not in the data, which goes from {} to {}",
+            year, first, last
+        )),
+        None => Ok(()),
+    }
+}
+
 fn check_time_series_interval(
     match_years_vector: &Vec<u16>,
     desired_range: &std::ops::RangeInclusive<u16>,
@@ -111,45 +245,150 @@ fn check_time_series_interval(
     }
 }
 
-pub fn construct_elo_table_for_time_series(
+pub fn construct_elo_table_for_time_series<S: RatingSystem>(
+    rating_system: &S,
     all_matches: &[Game],
-    elo_config: Option<&RunConfig>,
     run_hyperparameters: &run_config::RunHyperparameters,
     start_year: u16,
     end_year: u16,
-) -> EloTable {
-    let default_config = RunConfig::default();
-    let elo_config = match elo_config {
-        Some(config) => config,
-        None => &default_config,
-    };
-
+) -> RatingTable<S::Rating> {
     let seasons_map: SeasonMap = construct_seasons(all_matches);
 
-    let years_in_season_map = get_seasons_in_season_map(&seasons_map);
+    construct_elo_table_for_seasons(rating_system, &seasons_map, run_hyperparameters, start_year, end_year)
+}
+
+/// Same as [`construct_elo_table_for_time_series`], for matches already split into seasons
+pub fn construct_elo_table_for_seasons<S: RatingSystem>(
+    rating_system: &S,
+    seasons_map: &SeasonMap,
+    run_hyperparameters: &run_config::RunHyperparameters,
+    start_year: u16,
+    end_year: u16,
+) -> RatingTable<S::Rating> {
+    let years_in_season_map = get_seasons_in_season_map(seasons_map);
 
     //verificar se o vetor é contíguo
     let desired_range: std::ops::RangeInclusive<u16> = start_year..=end_year;
 
     check_time_series_interval(&years_in_season_map, &desired_range);
 
-    let mut starting_elo_table: Option<EloTable> = None;
+    let mut starting_elo_table: Option<RatingTable<S::Rating>> = None;
+    let mut previous_season: Option<&Season> = None;
     for year in desired_range.into_iter() {
         let season = seasons_map.get(&year).unwrap();
+        if let (Some(table), Some(previous)) = (starting_elo_table.take(), previous_season) {
+            starting_elo_table =
+                Some(start_next_season(rating_system, table, previous, season, run_hyperparameters));
+        }
+        previous_season = Some(season);
+
         let partidas = &season.matches;
         let elo_table =
-            construct_elo_table_for_year(partidas, starting_elo_table, Some(elo_config), &run_hyperparameters );
+            construct_elo_table_for_year(rating_system, partidas, starting_elo_table, run_hyperparameters);
         starting_elo_table = Some(elo_table.clone());
 
         if DEBUG_INFO {
             println!("Elo table for year {}", year);
-            print_elo_table(&elo_table, false);
+            print_elo_table(&rating_values(rating_system, &elo_table), false);
         }
     }
 
     starting_elo_table.unwrap()
 }
 
+/// Ratings as of a given date: trains from `start_year` onwards using only the matches played strictly
+/// before `date`, so the result can be used as the pre-match ratings for games on that day
+pub fn construct_elo_table_at_date<S: RatingSystem>(
+    rating_system: &S,
+    all_matches: &[Game],
+    run_hyperparameters: &run_config::RunHyperparameters,
+    start_year: u16,
+    date: NaiveDate,
+) -> RatingTable<S::Rating> {
+    let seasons_map: SeasonMap = construct_seasons(all_matches);
+
+    let mut elo_table = RatingTable::new();
+    let mut previous_season: Option<&Season> = None;
+    for year in get_seasons_in_season_map(&seasons_map) {
+        if year < start_year {
+            continue;
+        }
+
+        let season = seasons_map.get(&year).unwrap();
+        if let Some(previous) = previous_season {
+            elo_table = start_next_season(rating_system, elo_table, previous, season, run_hyperparameters);
+        }
+        previous_season = Some(season);
+
+        let partidas: Vec<Game> = season
+            .matches
+            .iter()
+            .take_while(|game| game.date < date)
+            .cloned()
+            .collect();
+
+        if partidas.is_empty() {
+            break;
+        }
+
+        elo_table =
+            construct_elo_table_for_year(rating_system, &partidas, Some(elo_table), run_hyperparameters);
+    }
+
+    elo_table
+}
+
+/// Trains over `start_year..=end_year` like [`construct_elo_table_for_time_series`], keeping every rated match
+pub fn construct_rated_games_for_time_series<S: RatingSystem>(
+    rating_system: &S,
+    all_matches: &[Game],
+    run_hyperparameters: &run_config::RunHyperparameters,
+    start_year: u16,
+    end_year: u16,
+) -> (RatingTable<S::Rating>, Vec<RatedGame>) {
+    let seasons_map: SeasonMap = construct_seasons(all_matches);
+
+    let desired_range: std::ops::RangeInclusive<u16> = start_year..=end_year;
+    check_time_series_interval(&get_seasons_in_season_map(&seasons_map), &desired_range);
+
+    let mut elo_table = RatingTable::new();
+    let mut all_rated_games = Vec::new();
+    let mut previous_season: Option<&Season> = None;
+    for year in desired_range {
+        let season = seasons_map.get(&year).unwrap();
+        if let Some(previous) = previous_season {
+            elo_table = start_next_season(rating_system, elo_table, previous, season, run_hyperparameters);
+        }
+        previous_season = Some(season);
+
+        let (season_table, rated_games) = construct_rated_games_for_year(
+            rating_system,
+            &season.matches,
+            Some(elo_table),
+            run_hyperparameters,
+        );
+        elo_table = season_table;
+        all_rated_games.extend(rated_games);
+    }
+
+    (elo_table, all_rated_games)
+}
+
+/// Collapses any rating table into plain values, for printing and for comparing systems
+pub fn rating_values<S: RatingSystem>(rating_system: &S, table: &RatingTable<S::Rating>) -> EloTable {
+    table
+        .iter()
+        .map(|(team, rating)| {
+            (
+                team.clone(),
+                CustomRating {
+                    rating: rating_system.rating_value(rating),
+                },
+            )
+        })
+        .collect()
+}
+
 pub fn print_elo_table(elo_table: &EloTable, order_elos: bool) {
     let max_team_length = elo_table.keys().map(|team| team.len()).max().unwrap_or(0);
 
@@ -1,8 +1,6 @@
 use crate::{util::game::{Game, GameResult}, experimentation::run_config::CustomRating};
 use std::collections::HashMap;
 
-use skillratings::elo::EloRating;
-
 #[derive(Default, Debug)]
 pub struct TeamStats {
     pub goals_scored: u16,
@@ -79,6 +77,9 @@ impl<'a> LeagueTable<'a> {
     pub fn rank(&self) -> Vec<(&'a str, &TeamStats)> {
         let mut teams: Vec<_> = self.table.iter().map(|(a, b)| (*a, b)).collect();
 
+        // the sort below is stable, so teams still tied end up in name order instead of the map's random one
+        teams.sort_by_key(|(team, _)| *team);
+
         teams.sort_by(|(_, a_stats), (_, b_stats)| {
             let a_goal_diff = a_stats.goals_scored as i32 - a_stats.goals_conceded as i32;
             let b_goal_diff = b_stats.goals_scored as i32 - b_stats.goals_conceded as i32;
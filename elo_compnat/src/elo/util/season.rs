@@ -1,28 +1,338 @@
 use crate::util::game::Game;
+use chrono::{Datelike, NaiveDate};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
+use super::league::LeagueTable;
+
+/// Whether a league plays within a calendar year (Brasileirão) or from August to May (European leagues).
+/// Seasons are always keyed by the year they start in, so `1999` is the 1999-2000 season of a split-year league
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SeasonKind {
+    #[default]
+    CalendarYear,
+    SplitYear,
+}
+
+impl SeasonKind {
+    /// Share of a season's matches played after its starting year needed to call it split-year.
+    /// The 2020 Brasileirão ran into 2021 because of the pandemic, but less than a quarter of its games did
+    const SPLIT_SHARE: f64 = 0.25;
+
+    /// Looks at the match dates and decides how the dataset organizes its seasons
+    pub fn detect(games: &[Game]) -> SeasonKind {
+        let mut played: HashMap<u16, (usize, usize)> = HashMap::new();
+
+        for game in games {
+            let (total, next_year) = played.entry(game.year).or_default();
+            *total += 1;
+            if game.date.year() > game.year as i32 {
+                *next_year += 1;
+            }
+        }
+
+        let split_seasons = played
+            .values()
+            .filter(|(total, next_year)| *next_year as f64 / *total as f64 >= Self::SPLIT_SHARE)
+            .count();
+
+        if split_seasons * 2 > played.len() {
+            SeasonKind::SplitYear
+        } else {
+            SeasonKind::CalendarYear
+        }
+    }
+
+    /// `2003` for calendar-year leagues, `1999-2000` for split-year ones
+    pub fn label(&self, year: u16) -> String {
+        match self {
+            SeasonKind::CalendarYear => year.to_string(),
+            SeasonKind::SplitYear => format!("{}-{}", year, year + 1),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Season {
     pub year: u16,
-    pub division: u8,
     pub league: String,
+    pub kind: SeasonKind,
+    /// Every match of the season, across all divisions, in chronological order
     pub matches: Vec<Game>,
+    /// The same matches split by division, 1 being the top flight
+    pub divisions: BTreeMap<u8, Vec<Game>>,
 }
 
 impl Season {
-    pub fn new(year: u16, name: &str, division: &u8) -> Self {
+    pub fn new(year: u16, name: &str) -> Self {
         Season {
             year,
-            division: *division,
             league: String::from(name),
+            kind: SeasonKind::default(),
             matches: Vec::new(),
+            divisions: BTreeMap::new(),
         }
     }
+
+    pub fn label(&self) -> String {
+        self.kind.label(self.year)
+    }
+
+    pub fn division_numbers(&self) -> Vec<u8> {
+        self.divisions.keys().copied().collect()
+    }
+
+    /// Standings of a single division, `None` if the season doesn't have it
+    pub fn division_table(&self, division: u8) -> Option<LeagueTable<'_>> {
+        self.divisions
+            .get(&division)
+            .filter(|games| !games.is_empty())
+            .map(|games| LeagueTable::new(games, &self.league, &division))
+    }
+
+    /// Division each team played in. If a team shows up in more than one, the one with the most games wins
+    pub fn team_divisions(&self) -> HashMap<String, u8> {
+        main_divisions(&self.matches)
+    }
+
+    pub fn schedule(&self) -> SeasonSchedule {
+        SeasonSchedule::new(&self.matches)
+    }
+
+    /// Orders the matches by date and then by week, so the elo walk doesn't depend on the csv row order.
+    /// The sort is stable, games on the same day keep their original order
+    pub fn sort_chronologically(&mut self) {
+        self.matches.sort_by(chronological_order);
+
+        for games in self.divisions.values_mut() {
+            games.sort_by(chronological_order);
+        }
+    }
+}
+
+fn chronological_order(a: &Game, b: &Game) -> Ordering {
+    a.date
+        .cmp(&b.date)
+        .then_with(|| a.week.partial_cmp(&b.week).unwrap_or(Ordering::Equal))
+}
+
+/// A team that changed division between two consecutive seasons
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DivisionMove {
+    pub team: String,
+    pub from: u8,
+    pub to: u8,
+}
+
+/// Movement of teams between two consecutive seasons. Teams coming from (or going to) divisions that are
+/// not in the dataset show up as `entered` and `left`
+#[derive(Debug, Clone, Default)]
+pub struct SeasonTransition {
+    pub from_year: u16,
+    pub to_year: u16,
+    pub promoted: Vec<DivisionMove>,
+    pub relegated: Vec<DivisionMove>,
+    pub entered: Vec<(String, u8)>,
+    pub left: Vec<(String, u8)>,
+}
+
+pub fn detect_division_changes(previous: &Season, next: &Season) -> SeasonTransition {
+    let before = previous.team_divisions();
+    let after = next.team_divisions();
+
+    let mut transition = SeasonTransition {
+        from_year: previous.year,
+        to_year: next.year,
+        ..SeasonTransition::default()
+    };
+
+    for (team, &to) in &after {
+        match before.get(team) {
+            Some(&from) if to < from => transition.promoted.push(DivisionMove { team: team.clone(), from, to }),
+            Some(&from) if to > from => transition.relegated.push(DivisionMove { team: team.clone(), from, to }),
+            Some(_) => (),
+            None => transition.entered.push((team.clone(), to)),
+        }
+    }
+
+    for (team, &from) in &before {
+        if !after.contains_key(team) {
+            transition.left.push((team.clone(), from));
+        }
+    }
+
+    transition.promoted.sort_by(|a, b| a.team.cmp(&b.team));
+    transition.relegated.sort_by(|a, b| a.team.cmp(&b.team));
+    transition.entered.sort();
+    transition.left.sort();
+
+    transition
+}
+
+/// Division changes for every pair of consecutive seasons in the map
+pub fn construct_season_transitions(seasons: &SeasonMap) -> Vec<SeasonTransition> {
+    let years = get_seasons_in_season_map(seasons);
+
+    years
+        .windows(2)
+        .map(|pair| detect_division_changes(&seasons[&pair[0]], &seasons[&pair[1]]))
+        .collect()
 }
 
 pub type SeasonMap = HashMap<u16, Season>;
 
+/// Division each team of `games` played the most matches in, the lower one on a tie. See
+/// [`Season::team_divisions`]
+pub fn main_divisions(games: &[Game]) -> HashMap<String, u8> {
+    let mut counts: HashMap<&str, HashMap<u8, usize>> = HashMap::new();
+
+    for game in games {
+        for team in [game.home.as_str(), game.away.as_str()] {
+            *counts.entry(team).or_default().entry(game.division).or_default() += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|(team, per_division)| {
+            let division = per_division
+                .into_iter()
+                .max_by(|(div_a, count_a), (div_b, count_b)| {
+                    count_a.cmp(count_b).then_with(|| div_b.cmp(div_a))
+                })
+                .map(|(division, _)| division)
+                .unwrap();
+            (team.to_string(), division)
+        })
+        .collect()
+}
+
+/// Regular season of each division, to tell the league matches from the promotion/relegation playoffs.
+/// The csv files a playoff under the lower division as week 1, dated after that division's last round and
+/// sometimes against a team of another division
+#[derive(Debug, Clone)]
+pub struct SeasonSchedule {
+    team_divisions: HashMap<String, u8>,
+    /// Last round of each division and the day it ended
+    last_rounds: HashMap<u8, (f32, NaiveDate)>,
+}
+
+impl SeasonSchedule {
+    pub fn new(games: &[Game]) -> Self {
+        let mut last_rounds: HashMap<u8, (f32, NaiveDate)> = HashMap::new();
+        for game in games {
+            let last = last_rounds.entry(game.division).or_insert((game.week, game.date));
+            if game.week > last.0 {
+                *last = (game.week, game.date);
+            } else if game.week == last.0 && game.date > last.1 {
+                last.1 = game.date;
+            }
+        }
+
+        SeasonSchedule {
+            team_divisions: main_divisions(games),
+            last_rounds,
+        }
+    }
+
+    /// A match against a team of another division, or filed under an earlier round but played after the
+    /// last one
+    pub fn is_playoff(&self, game: &Game) -> bool {
+        let in_division = |team: &str| self.team_divisions.get(team) == Some(&game.division);
+        let after_last_round = self
+            .last_rounds
+            .get(&game.division)
+            .is_some_and(|&(week, end)| game.week < week && game.date > end);
+
+        !in_division(&game.home) || !in_division(&game.away) || after_last_round
+    }
+
+    /// Round of `game`, the playoffs counting as one more round after the last
+    pub fn round(&self, game: &Game) -> f32 {
+        match self.last_rounds.get(&game.division) {
+            Some(&(week, _)) if self.is_playoff(game) => week + 1.0,
+            _ => game.week,
+        }
+    }
+
+    /// Splits `games` by division, leaving the playoffs out so a team is only ranked in its own league
+    pub fn league_games(&self, games: &[Game]) -> BTreeMap<u8, Vec<Game>> {
+        let mut divisions: BTreeMap<u8, Vec<Game>> = BTreeMap::new();
+        for game in games.iter().filter(|game| !self.is_playoff(game)) {
+            divisions.entry(game.division).or_default().push(game.clone());
+        }
+        divisions
+    }
+}
+
+/// Shortest break between the end of a season and the start of the next one in a calendar-year league
+const MIN_SEASON_BREAK_DAYS: i64 = 30;
+
+fn has_repeated_pairing(games: &[Game], indices: &[usize]) -> bool {
+    let mut pairings = HashSet::new();
+    !indices
+        .iter()
+        .all(|&i| pairings.insert((games[i].home.as_str(), games[i].away.as_str())))
+}
+
+/// Gives back to their season the matches of a calendar-year league filed under the next one, like the end
+/// of the 2020 Brasileirão, played in January and February 2021 and labelled 2021. A division is only
+/// regrouped when a pairing repeats in it: the matches before its longest break (of at least
+/// `MIN_SEASON_BREAK_DAYS`) move to the previous season if that completes it without repeating a pairing
+/// and leaves none repeated in what stays. Returns how many matches moved
+pub fn regroup_carried_over_matches(games: &mut [Game]) -> usize {
+    if SeasonKind::detect(games) != SeasonKind::CalendarYear {
+        return 0;
+    }
+
+    let mut by_season: BTreeMap<(u16, u8), Vec<usize>> = BTreeMap::new();
+    for (i, game) in games.iter().enumerate() {
+        by_season.entry((game.year, game.division)).or_default().push(i);
+    }
+
+    let mut moved = 0;
+    let keys: Vec<(u16, u8)> = by_season.keys().copied().collect();
+    for (year, division) in keys {
+        let Some(previous_key) = year.checked_sub(1).map(|previous| (previous, division)) else {
+            continue;
+        };
+        let (Some(previous), Some(current)) = (by_season.get(&previous_key), by_season.get(&(year, division))) else {
+            continue;
+        };
+        if !has_repeated_pairing(games, current) {
+            continue;
+        }
+
+        let mut current = current.clone();
+        current.sort_by_key(|&i| games[i].date);
+        let longest_break = current
+            .windows(2)
+            .enumerate()
+            .map(|(i, pair)| (i + 1, (games[pair[1]].date - games[pair[0]].date).num_days()))
+            .max_by_key(|&(_, days)| days);
+        let Some((split, _)) = longest_break.filter(|&(_, days)| days >= MIN_SEASON_BREAK_DAYS) else {
+            continue;
+        };
+
+        let (carried, rest) = current.split_at(split);
+        let completed: Vec<usize> = previous.iter().chain(carried).copied().collect();
+        if has_repeated_pairing(games, &completed) || has_repeated_pairing(games, rest) {
+            continue;
+        }
+
+        for &i in carried {
+            games[i].year = year - 1;
+        }
+        moved += carried.len();
+        by_season.insert(previous_key, completed);
+        by_season.insert((year, division), rest.to_vec());
+    }
+
+    moved
+}
+
 pub fn construct_seasons(games: &[Game]) -> SeasonMap {
     let mut seasons: SeasonMap = HashMap::new();
 
@@ -30,8 +340,19 @@ pub fn construct_seasons(games: &[Game]) -> SeasonMap {
         let year = game.year;
         let season = seasons
             .entry(year)
-            .or_insert(Season::new(year, "Brasileirão", &1));
+            .or_insert_with(|| Season::new(year, &game.league));
         season.matches.push(game.clone());
+        season
+            .divisions
+            .entry(game.division)
+            .or_default()
+            .push(game.clone());
+    }
+
+    let kind = SeasonKind::detect(games);
+    for season in seasons.values_mut() {
+        season.kind = kind;
+        season.sort_chronologically();
     }
 
     seasons
@@ -63,3 +384,55 @@ pub fn get_all_teams_in_season_map(seasons: &SeasonMap) -> Vec<String> {
     teams_vec.sort_unstable();
     teams_vec
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Double round robin of `teams` starting on `start`, one round a week
+    fn double_round_robin(start: NaiveDate, teams: &[&str]) -> Vec<Game> {
+        let mut games = Vec::new();
+        for home in teams {
+            for away in teams.iter().filter(|&away| away != home) {
+                let date = start + chrono::Duration::days(7 * games.len() as i64 / 2);
+                games.push(Game::fixture(date, home, away, 1));
+            }
+        }
+        games
+    }
+
+    #[test]
+    fn the_end_of_a_season_labelled_as_the_next_one_goes_back() {
+        let teams = ["A", "B", "C", "D", "E"];
+        let mut games = double_round_robin(NaiveDate::from_ymd_opt(2020, 8, 1).unwrap(), &teams);
+        // as últimas 6 partidas de 2020 caem em 2021 e vêm rotuladas como 2021
+        let carried = games.len() - 6;
+        for game in &mut games[carried..] {
+            game.date += chrono::Duration::days(120);
+            game.year = 2021;
+        }
+        games.extend(double_round_robin(NaiveDate::from_ymd_opt(2021, 5, 1).unwrap(), &teams));
+
+        assert_eq!(regroup_carried_over_matches(&mut games), 6);
+        let in_2020 = games.iter().filter(|game| game.year == 2020).count();
+        assert_eq!(in_2020, 20);
+        assert!(games[carried..20].iter().all(|game| game.year == 2020));
+        assert!(games[20..].iter().all(|game| game.year == 2021));
+    }
+
+    #[test]
+    fn seasons_without_repeated_pairings_stay_as_labelled() {
+        let teams = ["A", "B", "C", "D"];
+        let mut games = double_round_robin(NaiveDate::from_ymd_opt(2020, 5, 1).unwrap(), &teams);
+        let mut next = double_round_robin(NaiveDate::from_ymd_opt(2021, 1, 10).unwrap(), &teams);
+        // pausa longa no meio da temporada, mas nenhum confronto repetido
+        for game in &mut next[6..] {
+            game.date += chrono::Duration::days(90);
+        }
+        games.extend(next);
+        let labels: Vec<u16> = games.iter().map(|game| game.year).collect();
+
+        assert_eq!(regroup_carried_over_matches(&mut games), 0);
+        assert_eq!(games.iter().map(|game| game.year).collect::<Vec<_>>(), labels);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::elo::baselines::{BaseRates, LastSeasonTable, MarketValueOnly, UniformLeague};
+use crate::elo::rating_system::{Glicko2System, RatingSystem, RatingSystemKind, TrueSkillSystem, WengLinSystem};
+use crate::elo::util::season::SeasonKind;
+use crate::util::game::Game;
+
+use super::evaluation::FitnessObjective;
+use super::run_all_experiments::PreparedExperiment;
+use super::run_config::{CustomElo, RunConfig, RunHyperparameters};
+
+/// Error of each simulated season under `objective`, for any rating system. Fails when the
+/// hyperparameters don't fit the data, see [`PreparedExperiment::new`]
+pub fn run_objective<S: RatingSystem>(
+    rating_system: &S,
+    all_games: &[Game],
+    experiment_config: &RunHyperparameters,
+    objective: FitnessObjective,
+    seed: u64,
+) -> Result<Vec<f64>, String> {
+    let experiment = PreparedExperiment::new(all_games, experiment_config)?;
+    Ok(experiment.errors(rating_system, experiment_config, objective, seed))
+}
+
+/// Builds the system of `kind` and runs it through [`run_objective`]. Only the elo uses every gene of
+/// `run_config`, the others take at most the draw frequency and the season regression. The base rates
+/// are taken from the training seasons, so the backtest doesn't see the future
+pub fn run_rating_system(
+    kind: RatingSystemKind,
+    all_games: &[Game],
+    run_config: &RunConfig,
+    experiment_config: &RunHyperparameters,
+    objective: FitnessObjective,
+    seed: u64,
+) -> Result<Vec<f64>, String> {
+    match kind {
+        RatingSystemKind::Elo => {
+            let system = CustomElo::new(run_config.clone(), experiment_config.components());
+            run_objective(&system, all_games, experiment_config, objective, seed)
+        }
+        RatingSystemKind::Glicko2 => {
+            let system = Glicko2System::from_run_config(run_config);
+            run_objective(&system, all_games, experiment_config, objective, seed)
+        }
+        RatingSystemKind::TrueSkill => {
+            let system = TrueSkillSystem::from_run_config(run_config);
+            run_objective(&system, all_games, experiment_config, objective, seed)
+        }
+        RatingSystemKind::WengLin => {
+            let system = WengLinSystem::from_run_config(run_config);
+            run_objective(&system, all_games, experiment_config, objective, seed)
+        }
+        RatingSystemKind::BaseRates => {
+            let components = experiment_config.components();
+            let end_year = experiment_config.starting_year + experiment_config.backtest_years;
+            let training_games: Vec<Game> = all_games
+                .iter()
+                .filter(|game| components.uses_division(game.division))
+                .filter(|game| game.year >= experiment_config.starting_year && game.year <= end_year)
+                .cloned()
+                .collect();
+            let system = BaseRates::from_games(&training_games);
+            run_objective(&system, all_games, experiment_config, objective, seed)
+        }
+        RatingSystemKind::LastSeason => {
+            let system = LastSeasonTable {
+                tie_frequency: run_config.tie_frequency,
+            };
+            run_objective(&system, all_games, experiment_config, objective, seed)
+        }
+        RatingSystemKind::MarketValue => {
+            let system = MarketValueOnly {
+                tie_frequency: run_config.tie_frequency,
+            };
+            run_objective(&system, all_games, experiment_config, objective, seed)
+        }
+        RatingSystemKind::Uniform => run_objective(&UniformLeague, all_games, experiment_config, objective, seed),
+    }
+}
+
+/// Errors of every system side by side, one row per simulated season and the mean at the bottom
+pub fn print_benchmark(
+    errors_by_system: &[(RatingSystemKind, Vec<f64>)],
+    experiment_config: &RunHyperparameters,
+    season_kind: SeasonKind,
+) {
+    let base_year = experiment_config.starting_year + experiment_config.backtest_years + 1;
+    let seasons = errors_by_system.iter().map(|(_, errors)| errors.len()).max().unwrap_or(0);
+
+    let label_width = season_kind.label(base_year).len().max(6);
+    let column_width = errors_by_system
+        .iter()
+        .map(|(kind, _)| kind.name().len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+
+    print!("{:<1$}", "Season", label_width);
+    for (kind, _) in errors_by_system {
+        print!(" {:>1$}", kind.name(), column_width);
+    }
+    println!();
+
+    for i in 0..seasons {
+        print!("{:<1$}", season_kind.label(base_year + i as u16), label_width);
+        for (_, errors) in errors_by_system {
+            match errors.get(i) {
+                Some(error) => print!(" {:>1$.4}", error, column_width),
+                None => print!(" {:>1$}", "-", column_width),
+            }
+        }
+        println!();
+    }
+
+    print!("{:<1$}", "Mean", label_width);
+    for (_, errors) in errors_by_system {
+        let mean = errors.iter().sum::<f64>() / errors.len().max(1) as f64;
+        print!(" {:>1$.4}", mean, column_width);
+    }
+    println!();
+}
@@ -0,0 +1,271 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::elo::util::season::SeasonKind;
+use crate::experimentation::run_config::ActiveComponents;
+use crate::util::math::{mean, transpose_matrix};
+use crate::util::random::derive_seed;
+
+pub const DEFAULT_CONFIDENCE: f64 = 0.95;
+pub const DEFAULT_RESAMPLES: usize = 1000;
+
+/// Spread of an error over the random variations, with a bootstrap interval for its mean
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorSummary {
+    pub mean: f64,
+    pub std: f64,
+    pub min: f64,
+    pub max: f64,
+    pub ci_low: f64,
+    pub ci_high: f64,
+}
+
+impl ErrorSummary {
+    /// Percentile bootstrap of the mean of `errors`: `resamples` means of `errors.len()` draws with
+    /// replacement, cut at the `confidence` central interval
+    pub fn bootstrap(errors: &[f64], confidence: f64, resamples: usize, seed: u64) -> Self {
+        let mean_error = mean(errors).unwrap_or(f64::NAN);
+        let n = errors.len();
+
+        let std = if n > 1 {
+            (errors.iter().map(|e| (e - mean_error).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+
+        let (ci_low, ci_high) = if n > 1 && resamples > 0 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut means: Vec<f64> = (0..resamples)
+                .map(|_| (0..n).map(|_| errors[rng.gen_range(0..n)]).sum::<f64>() / n as f64)
+                .collect();
+            means.sort_by(|a, b| a.total_cmp(b));
+
+            let tail = (1.0 - confidence.clamp(0.0, 1.0)) / 2.0;
+            (percentile(&means, tail), percentile(&means, 1.0 - tail))
+        } else {
+            (mean_error, mean_error)
+        };
+
+        ErrorSummary {
+            mean: mean_error,
+            std,
+            min: errors.iter().copied().fold(f64::INFINITY, f64::min),
+            max: errors.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            ci_low,
+            ci_high,
+        }
+    }
+
+    fn to_dict<'py>(self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        dict.set_item("mean", self.mean)?;
+        dict.set_item("std", self.std)?;
+        dict.set_item("min", self.min)?;
+        dict.set_item("max", self.max)?;
+        dict.set_item("ci_low", self.ci_low)?;
+        dict.set_item("ci_high", self.ci_high)?;
+        Ok(dict)
+    }
+}
+
+/// Value at fraction `q` of sorted `values`, interpolating between neighbours
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let below = position.floor() as usize;
+    let above = position.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+/// Every error of a backtest, before averaging: one row per random variation, one column per season
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentResult {
+    pub first_season: u16,
+    /// Parts of the model switched on for the run
+    pub components: ActiveComponents,
+    pub errors_by_variation: Vec<Vec<f64>>,
+}
+
+/// [`ExperimentResult`] reduced to a summary per season and one for the whole backtest. The overall
+/// error of a variation is the mean of its seasons, so the interval accounts for seasons moving together
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSummary {
+    pub first_season: u16,
+    pub components: ActiveComponents,
+    pub variations: usize,
+    pub confidence: f64,
+    pub by_season: Vec<ErrorSummary>,
+    pub overall: ErrorSummary,
+}
+
+impl ExperimentResult {
+    /// Mean error of each season over the variations, what `run_experiments` returns
+    pub fn mean_errors(&self) -> Vec<f64> {
+        transpose_matrix(self.errors_by_variation.clone())
+            .iter()
+            .map(|errors| mean(errors).unwrap_or(1000.0))
+            .collect()
+    }
+
+    /// Mean of the seasons of each variation
+    pub fn overall_errors(&self) -> Vec<f64> {
+        self.errors_by_variation
+            .iter()
+            .map(|errors| mean(errors).unwrap_or(1000.0))
+            .collect()
+    }
+
+    /// Bootstraps every season and the overall error. Each one resamples from its own stream of `seed`
+    pub fn summarize(&self, confidence: f64, resamples: usize, seed: u64) -> ResultSummary {
+        let by_season = transpose_matrix(self.errors_by_variation.clone())
+            .iter()
+            .enumerate()
+            .map(|(i, errors)| ErrorSummary::bootstrap(errors, confidence, resamples, derive_seed(seed, &[i as u64])))
+            .collect::<Vec<_>>();
+
+        // a semente do overall fica depois das temporadas
+        let overall_seed = derive_seed(seed, &[by_season.len() as u64]);
+
+        ResultSummary {
+            first_season: self.first_season,
+            components: self.components,
+            variations: self.errors_by_variation.len(),
+            confidence,
+            overall: ErrorSummary::bootstrap(&self.overall_errors(), confidence, resamples, overall_seed),
+            by_season,
+        }
+    }
+}
+
+impl ResultSummary {
+    pub fn print(&self, season_kind: SeasonKind) {
+        let label_width = season_kind.label(self.first_season).len().max(7);
+        let interval = format!("{:.0}% CI", self.confidence * 100.0);
+
+        println!(
+            "Errors over {} variations, bootstrap {} of the mean:",
+            self.variations, interval
+        );
+        println!(
+            "{:<w$} {:>8} {:>8} {:>8} {:>8} {:>19}",
+            "Season",
+            "Mean",
+            "Std",
+            "Min",
+            "Max",
+            interval,
+            w = label_width
+        );
+
+        let print_row = |label: String, summary: &ErrorSummary| {
+            println!(
+                "{:<w$} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>19}",
+                label,
+                summary.mean,
+                summary.std,
+                summary.min,
+                summary.max,
+                format!("[{:.2}, {:.2}]", summary.ci_low, summary.ci_high),
+                w = label_width
+            );
+        };
+
+        for (i, summary) in self.by_season.iter().enumerate() {
+            print_row(season_kind.label(self.first_season + i as u16), summary);
+        }
+        print_row(String::from("Overall"), &self.overall);
+    }
+
+    pub fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("variations", self.variations)?;
+        dict.set_item("confidence", self.confidence)?;
+        dict.set_item("components", Py::new(py, self.components)?)?;
+        dict.set_item("overall", self.overall.to_dict(py)?)?;
+
+        let by_season = PyDict::new(py);
+        for (i, summary) in self.by_season.iter().enumerate() {
+            by_season.set_item(self.first_season + i as u16, summary.to_dict(py)?)?;
+        }
+        dict.set_item("by_season", by_season)?;
+
+        Ok(dict.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components() -> ActiveComponents {
+        ActiveComponents {
+            goals_diff: true,
+            home_advantage: true,
+            market_values: false,
+            divisions: 2,
+        }
+    }
+
+    /// Erros que se repetem de 0 a 4, com média 2
+    fn cycling_errors(n: usize) -> Vec<f64> {
+        (0..n).map(|i| (i % 5) as f64).collect()
+    }
+
+    #[test]
+    fn a_constant_sample_has_no_spread() {
+        let summary = ErrorSummary::bootstrap(&[3.0; 20], 0.95, 500, 1);
+
+        assert_eq!((summary.mean, summary.std), (3.0, 0.0));
+        assert_eq!((summary.min, summary.max), (3.0, 3.0));
+        assert_eq!((summary.ci_low, summary.ci_high), (3.0, 3.0));
+    }
+
+    #[test]
+    fn the_interval_contains_the_mean_and_narrows_with_more_errors() {
+        let widths: Vec<f64> = [10, 100, 1000]
+            .into_iter()
+            .map(|n| {
+                let summary = ErrorSummary::bootstrap(&cycling_errors(n), 0.95, 1000, 3);
+                assert!((summary.mean - 2.0).abs() < 1e-12);
+                assert!(summary.ci_low < summary.mean && summary.mean < summary.ci_high, "{:?}", summary);
+                summary.ci_high - summary.ci_low
+            })
+            .collect();
+
+        assert!(widths[0] > widths[1] && widths[1] > widths[2], "{:?}", widths);
+        // a largura cai com a raiz de n
+        assert!(widths[1] / widths[2] > 2.0);
+    }
+
+    #[test]
+    fn percentiles_interpolate_between_neighbours() {
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0];
+
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 0.5), 3.0);
+        assert_eq!(percentile(&sorted, 1.0), 5.0);
+        assert!((percentile(&sorted, 0.1) - 1.4).abs() < 1e-12);
+        assert!((percentile(&sorted, 0.875) - 4.5).abs() < 1e-12);
+        assert_eq!(percentile(&[10.0, 20.0], 0.25), 12.5);
+        assert_eq!(percentile(&[7.0], 0.3), 7.0);
+    }
+
+    #[test]
+    fn the_summary_only_depends_on_the_seed() {
+        let result = ExperimentResult {
+            first_season: 2019,
+            components: components(),
+            errors_by_variation: (0..6).map(|i| vec![i as f64, 2.0 * i as f64, 10.0 - i as f64]).collect(),
+        };
+
+        let summary = result.summarize(0.9, 200, 9);
+        assert_eq!(summary, result.summarize(0.9, 200, 9));
+        assert_ne!(summary, result.summarize(0.9, 200, 10));
+
+        assert_eq!(summary.variations, 6);
+        assert_eq!(summary.by_season.len(), 3);
+        assert!((summary.by_season[1].mean - 5.0).abs() < 1e-12);
+        assert!((summary.overall.mean - (2.5 + 5.0 + 7.5) / 3.0).abs() < 1e-12);
+    }
+}
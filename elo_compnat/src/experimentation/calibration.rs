@@ -0,0 +1,369 @@
+use std::path::Path;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::elo::rating_system::RatingSystem;
+use crate::elo::train::RatedGame;
+use crate::util::game::{Game, GameResult};
+use crate::util::parsing::DatasetError;
+
+use super::evaluation::backtest_rated_games;
+use super::run_config::RunHyperparameters;
+
+pub const DEFAULT_CALIBRATION_BINS: usize = 10;
+
+/// Which probability of the match a calibration row refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Home,
+    Draw,
+    Away,
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 3] = [Outcome::Home, Outcome::Draw, Outcome::Away];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Home => "home",
+            Outcome::Draw => "draw",
+            Outcome::Away => "away",
+        }
+    }
+
+    fn probability(&self, rated: &RatedGame) -> f64 {
+        match self {
+            Outcome::Home => rated.prob_home,
+            Outcome::Draw => rated.prob_draw,
+            Outcome::Away => rated.prob_away,
+        }
+    }
+
+    fn happened(&self, result: GameResult) -> bool {
+        matches!(
+            (self, result),
+            (Outcome::Home, GameResult::H) | (Outcome::Draw, GameResult::D) | (Outcome::Away, GameResult::A)
+        )
+    }
+}
+
+/// Matches whose binned value fell in `[lower, upper)`, with the mean predicted probability of the
+/// outcome and how often it actually happened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationBin {
+    pub outcome: Outcome,
+    pub lower: f64,
+    pub upper: f64,
+    pub matches: usize,
+    pub mean_predicted: f64,
+    pub observed_frequency: f64,
+}
+
+/// Whether the draw model gets the share of draws right, overall and along the strength gap
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawCalibration {
+    /// `tie_frequency` of the configuration, before the season updates
+    pub tie_frequency: f64,
+    pub mean_predicted: f64,
+    pub observed_frequency: f64,
+    /// Binned by the expected score of the home side (`p_home + p_draw / 2`), so each bin is a strength gap
+    pub by_expected_score: Vec<CalibrationBin>,
+}
+
+/// Reliability table of the H/D/A probabilities over the backtest
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationReport {
+    pub matches: usize,
+    /// Non-empty bins of every outcome, binned by the predicted probability of that outcome
+    pub bins: Vec<CalibrationBin>,
+    /// Expected calibration error of each outcome, in the order of [`Outcome::ALL`]
+    pub ece: [f64; 3],
+    pub draws: DrawCalibration,
+}
+
+impl CalibrationReport {
+    /// Mean of the expected calibration error of the three outcomes
+    pub fn mean_ece(&self) -> f64 {
+        self.ece.iter().sum::<f64>() / 3.0
+    }
+
+    /// Columns of both tables as lists, ready for `pandas.DataFrame`
+    pub fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("matches", self.matches)?;
+        dict.set_item("bins", bins_to_dict(py, &self.bins)?)?;
+
+        let ece = PyDict::new(py);
+        for (outcome, value) in Outcome::ALL.iter().zip(self.ece) {
+            ece.set_item(outcome.name(), value)?;
+        }
+        ece.set_item("mean", self.mean_ece())?;
+        dict.set_item("ece", ece)?;
+
+        let draws = PyDict::new(py);
+        draws.set_item("tie_frequency", self.draws.tie_frequency)?;
+        draws.set_item("mean_predicted", self.draws.mean_predicted)?;
+        draws.set_item("observed_frequency", self.draws.observed_frequency)?;
+        draws.set_item("by_expected_score", bins_to_dict(py, &self.draws.by_expected_score)?)?;
+        dict.set_item("draws", draws)?;
+
+        Ok(dict.into())
+    }
+}
+
+fn bins_to_dict<'py>(py: Python<'py>, bins: &[CalibrationBin]) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item("outcome", bins.iter().map(|bin| bin.outcome.name()).collect::<Vec<_>>())?;
+    dict.set_item("lower", bins.iter().map(|bin| bin.lower).collect::<Vec<_>>())?;
+    dict.set_item("upper", bins.iter().map(|bin| bin.upper).collect::<Vec<_>>())?;
+    dict.set_item("matches", bins.iter().map(|bin| bin.matches).collect::<Vec<_>>())?;
+    dict.set_item(
+        "mean_predicted",
+        bins.iter().map(|bin| bin.mean_predicted).collect::<Vec<_>>(),
+    )?;
+    dict.set_item(
+        "observed_frequency",
+        bins.iter().map(|bin| bin.observed_frequency).collect::<Vec<_>>(),
+    )?;
+    Ok(dict)
+}
+
+/// Groups the matches into `bins` equal-width bins of `key` over [0, 1] and measures how often `outcome`
+/// happened in each one. Empty bins are left out
+fn reliability_bins(
+    rated_games: &[RatedGame],
+    outcome: Outcome,
+    bins: usize,
+    key: impl Fn(&RatedGame) -> f64,
+) -> Vec<CalibrationBin> {
+    let mut counts = vec![(0usize, 0.0, 0usize); bins];
+
+    for rated in rated_games {
+        // p = 1.0 cai no último bin
+        let index = ((key(rated) * bins as f64) as usize).min(bins - 1);
+        let (matches, predicted, happened) = &mut counts[index];
+        *matches += 1;
+        *predicted += outcome.probability(rated);
+        *happened += outcome.happened(rated.game.result) as usize;
+    }
+
+    counts
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (matches, _, _))| *matches > 0)
+        .map(|(i, (matches, predicted, happened))| CalibrationBin {
+            outcome,
+            lower: i as f64 / bins as f64,
+            upper: (i + 1) as f64 / bins as f64,
+            matches,
+            mean_predicted: predicted / matches as f64,
+            observed_frequency: happened as f64 / matches as f64,
+        })
+        .collect()
+}
+
+/// Weighted mean of the gap between predicted and observed frequency of each bin
+fn expected_calibration_error(bins: &[CalibrationBin]) -> f64 {
+    let total: usize = bins.iter().map(|bin| bin.matches).sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    bins.iter()
+        .map(|bin| bin.matches as f64 * (bin.mean_predicted - bin.observed_frequency).abs())
+        .sum::<f64>()
+        / total as f64
+}
+
+/// Calibration of the probabilities of `rated_games`, with `bins` bins per outcome
+pub fn calibration_from_rated_games(rated_games: &[RatedGame], bins: usize, tie_frequency: f64) -> CalibrationReport {
+    let bins = bins.max(1);
+
+    let mut table = Vec::new();
+    let mut ece = [0.0; 3];
+    for (i, outcome) in Outcome::ALL.into_iter().enumerate() {
+        let outcome_bins = reliability_bins(rated_games, outcome, bins, |rated| outcome.probability(rated));
+        ece[i] = expected_calibration_error(&outcome_bins);
+        table.extend(outcome_bins);
+    }
+
+    let whole = reliability_bins(rated_games, Outcome::Draw, 1, |_| 0.0);
+    let (mean_predicted, observed_frequency) = whole
+        .first()
+        .map(|bin| (bin.mean_predicted, bin.observed_frequency))
+        .unwrap_or((0.0, 0.0));
+
+    CalibrationReport {
+        matches: rated_games.len(),
+        bins: table,
+        ece,
+        draws: DrawCalibration {
+            tie_frequency,
+            mean_predicted,
+            observed_frequency,
+            by_expected_score: reliability_bins(rated_games, Outcome::Draw, bins, |rated| {
+                rated.prob_home + rated.prob_draw / 2.0
+            }),
+        },
+    }
+}
+
+/// Calibration of the pre-match probabilities of every match in [`backtest_rated_games`]
+pub fn calibration_report<S: RatingSystem>(
+    rating_system: &S,
+    all_games: &[Game],
+    experiment_config: &RunHyperparameters,
+    bins: usize,
+    tie_frequency: f64,
+) -> CalibrationReport {
+    let rated_games = backtest_rated_games(rating_system, all_games, experiment_config);
+    calibration_from_rated_games(&rated_games, bins, tie_frequency)
+}
+
+/// Both tables in one csv, told apart by the `table` column ("outcome" or "draw_by_expected_score")
+pub fn write_calibration_csv(report: &CalibrationReport, output: &Path) -> Result<(), DatasetError> {
+    let write_error = |source: csv::Error| DatasetError::Io {
+        path: output.to_path_buf(),
+        source,
+    };
+
+    let mut writer = csv::Writer::from_path(output).map_err(write_error)?;
+    writer
+        .write_record([
+            "table",
+            "outcome",
+            "lower",
+            "upper",
+            "matches",
+            "mean_predicted",
+            "observed_frequency",
+        ])
+        .map_err(write_error)?;
+
+    let tables = [
+        ("outcome", &report.bins),
+        ("draw_by_expected_score", &report.draws.by_expected_score),
+    ];
+    for (table, bins) in tables {
+        for bin in bins {
+            writer
+                .write_record([
+                    table.to_string(),
+                    bin.outcome.name().to_string(),
+                    bin.lower.to_string(),
+                    bin.upper.to_string(),
+                    bin.matches.to_string(),
+                    bin.mean_predicted.to_string(),
+                    bin.observed_frequency.to_string(),
+                ])
+                .map_err(write_error)?;
+        }
+    }
+
+    writer.flush().map_err(|e| write_error(e.into()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-12;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < TOLERANCE, "{} != {}", actual, expected);
+    }
+
+    /// Partida com as probabilidades (casa, empate, fora) e o placar dados
+    fn rated((prob_home, prob_draw, prob_away): (f64, f64, f64), (home_score, away_score): (u16, u16)) -> RatedGame {
+        let date = NaiveDate::from_ymd_opt(2020, 6, 1).unwrap();
+        RatedGame {
+            game: Game::fixture(date, "A", "B", 1).with_score(home_score, away_score),
+            home_elo_after: 1000.0,
+            away_elo_after: 1000.0,
+            prob_home,
+            prob_draw,
+            prob_away,
+        }
+    }
+
+    #[test]
+    fn certain_probabilities_fall_in_the_edge_bins() {
+        let games = [rated((1.0, 0.0, 0.0), (1, 0)), rated((0.0, 0.5, 0.5), (0, 1))];
+        let bins = reliability_bins(&games, Outcome::Home, 10, |rated| rated.prob_home);
+
+        assert_eq!(bins.len(), 2);
+        assert_eq!((bins[0].lower, bins[0].upper, bins[0].matches), (0.0, 0.1, 1));
+        assert_eq!(bins[0].observed_frequency, 0.0);
+        assert_close(bins[1].lower, 0.9);
+        assert_eq!((bins[1].upper, bins[1].matches), (1.0, 1));
+        assert_eq!(bins[1].observed_frequency, 1.0);
+    }
+
+    #[test]
+    fn perfectly_calibrated_probabilities_have_no_calibration_error() {
+        // casa e empate em 1 de 4, fora em 2 de 4, como previsto
+        let probabilities = (0.25, 0.25, 0.5);
+        let games: Vec<RatedGame> = [(1, 0), (1, 1), (0, 1), (0, 2)]
+            .into_iter()
+            .map(|score| rated(probabilities, score))
+            .collect();
+        let report = calibration_from_rated_games(&games, 10, 0.25);
+
+        assert_eq!(report.matches, 4);
+        assert_eq!(report.bins.len(), 3);
+        for ece in report.ece {
+            assert_close(ece, 0.0);
+        }
+    }
+
+    #[test]
+    fn calibration_error_of_a_miscalibrated_forecast() {
+        // os favoritos em casa perdem e os azarões em casa ganham
+        let games = [
+            rated((0.8, 0.1, 0.1), (0, 1)),
+            rated((0.8, 0.1, 0.1), (0, 2)),
+            rated((0.2, 0.1, 0.7), (2, 0)),
+            rated((0.2, 0.1, 0.7), (1, 0)),
+        ];
+        let report = calibration_from_rated_games(&games, 10, 0.3);
+
+        assert_close(report.ece[0], 0.8);
+        assert_close(report.ece[1], 0.1);
+        assert_close(report.ece[2], (2.0 * 0.9 + 2.0 * 0.7) / 4.0);
+        assert_close(report.mean_ece(), (0.8 + 0.1 + 0.8) / 3.0);
+    }
+
+    #[test]
+    fn draw_rows_follow_the_expected_score_of_the_home_side() {
+        let games = [
+            rated((0.8, 0.1, 0.1), (1, 1)),
+            rated((0.8, 0.1, 0.1), (1, 0)),
+            rated((0.2, 0.1, 0.7), (0, 1)),
+            rated((0.2, 0.2, 0.6), (2, 2)),
+        ];
+        let draws = calibration_from_rated_games(&games, 10, 0.3).draws;
+
+        assert_eq!(draws.tie_frequency, 0.3);
+        assert_close(draws.mean_predicted, 0.125);
+        assert_close(draws.observed_frequency, 0.5);
+
+        // esperado da casa: 0.85, 0.85, 0.25 e 0.3
+        let rows: Vec<(usize, usize)> = draws
+            .by_expected_score
+            .iter()
+            .map(|bin| ((bin.lower * 10.0).round() as usize, bin.matches))
+            .collect();
+        assert_eq!(rows, vec![(2, 1), (3, 1), (8, 2)]);
+        assert!(draws.by_expected_score.iter().all(|bin| bin.outcome == Outcome::Draw));
+        assert_close(draws.by_expected_score[0].observed_frequency, 0.0);
+        assert_close(draws.by_expected_score[1].mean_predicted, 0.2);
+        assert_close(draws.by_expected_score[1].observed_frequency, 1.0);
+        assert_close(draws.by_expected_score[2].mean_predicted, 0.1);
+        assert_close(draws.by_expected_score[2].observed_frequency, 0.5);
+    }
+}
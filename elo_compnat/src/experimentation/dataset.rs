@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use rayon::prelude::*;
+
+use super::evaluation::FitnessObjective;
+use super::fitness_cache::{dataset_digest, CacheError, FitnessCache, FitnessKey};
+use super::run_all_experiments::PreparedExperiment;
+use super::run_config::{CustomElo, RunConfig, RunHyperparameters};
+use crate::elo::util::season::SeasonKind;
+use crate::util::game::Game;
+use crate::util::parsing::ParseMode;
+
+/// A config from python, either a `RunConfig` or the list `fitness_function` takes
+#[derive(FromPyObject)]
+pub enum ConfigArgument {
+    Config(RunConfig),
+    List(Vec<f64>),
+}
+
+impl ConfigArgument {
+    fn into_config(self) -> PyResult<RunConfig> {
+        match self {
+            ConfigArgument::Config(config) => Ok(config),
+            ConfigArgument::List(list) => RunConfig::from_python_list(list).map_err(PyValueError::new_err),
+        }
+    }
+}
+
+/// Hyperparameters from python, either a `RunHyperparameters` or the list `fitness_function` takes
+#[derive(FromPyObject)]
+pub enum HyperparametersArgument {
+    Hyperparameters(RunHyperparameters),
+    List(Vec<u16>),
+}
+
+impl HyperparametersArgument {
+    fn into_hyperparameters(self) -> PyResult<RunHyperparameters> {
+        match self {
+            HyperparametersArgument::Hyperparameters(hyperparameters) => Ok(hyperparameters),
+            HyperparametersArgument::List(list) => {
+                RunHyperparameters::from_python_list(list).map_err(PyValueError::new_err)
+            }
+        }
+    }
+}
+
+/// Matches of a league loaded, validated and indexed once, to evaluate many configs without going back
+/// to the csv. The seasons and goal models of each set of hyperparameters are built the first time they
+/// are used and kept for the next evaluations.
+///
+/// With a `cache` file, results are also read from and written to the same SQLite cache as
+/// `fitness_function`
+#[pyclass(frozen)]
+pub struct Dataset {
+    name: String,
+    games: Vec<Game>,
+    warnings: Vec<String>,
+    digest: String,
+    kind: SeasonKind,
+    prepared: Mutex<HashMap<RunHyperparameters, Arc<PreparedExperiment>>>,
+    cache: Option<FitnessCache>,
+}
+
+impl Dataset {
+    pub fn from_games(name: &str, games: Vec<Game>, cache: Option<FitnessCache>) -> Self {
+        Dataset {
+            name: name.to_string(),
+            digest: dataset_digest(&games),
+            kind: SeasonKind::detect(&games),
+            games,
+            warnings: Vec::new(),
+            prepared: Mutex::new(HashMap::new()),
+            cache,
+        }
+    }
+
+    pub fn games(&self) -> &[Game] {
+        &self.games
+    }
+
+    /// The prepared backtest of `experiment_config`, built on first use
+    pub fn prepared(&self, experiment_config: &RunHyperparameters) -> Result<Arc<PreparedExperiment>, String> {
+        // a trava fica com quem está montando, e as outras threads do lote esperam por ele
+        let mut prepared = self.prepared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(experiment) = prepared.get(experiment_config) {
+            return Ok(experiment.clone());
+        }
+
+        let experiment = Arc::new(PreparedExperiment::new(&self.games, experiment_config)?);
+        prepared.insert(experiment_config.clone(), experiment.clone());
+        Ok(experiment)
+    }
+
+    /// Same errors as `fitness_function`
+    pub fn errors(
+        &self,
+        experiment: &PreparedExperiment,
+        run_config: &RunConfig,
+        experiment_config: &RunHyperparameters,
+        objective: FitnessObjective,
+        seed: u64,
+    ) -> Result<Vec<f64>, CacheError> {
+        let evaluate = || {
+            let custom_elo = CustomElo::new(run_config.clone(), experiment_config.components());
+            experiment.errors(&custom_elo, experiment_config, objective, seed)
+        };
+
+        match &self.cache {
+            Some(cache) => {
+                let key = FitnessKey::new(&self.digest, run_config, experiment_config, objective, seed);
+                cache.get_or_compute(&key, evaluate)
+            }
+            None => Ok(evaluate()),
+        }
+    }
+}
+
+#[pymethods]
+impl Dataset {
+    #[new]
+    #[pyo3(signature = (filename, strict=true, cache=None))]
+    /// `filename` is a bundled league name or a path to a csv. With `strict=False` invalid rows are
+    /// skipped and listed in `warnings` instead of raising
+    fn new(filename: &str, strict: bool, cache: Option<&str>) -> PyResult<Self> {
+        let mode = if strict { ParseMode::Strict } else { ParseMode::Lenient };
+        let loaded = crate::get_data_with_mode(filename, mode)?;
+        let cache = cache.map(|path| FitnessCache::open(Path::new(path))).transpose()?;
+
+        let mut dataset = Dataset::from_games(filename, loaded.games, cache);
+        dataset.warnings = loaded.warnings.iter().map(|warning| warning.to_string()).collect();
+        Ok(dataset)
+    }
+
+    #[getter]
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Rows skipped while loading
+    #[getter]
+    fn warnings(&self) -> Vec<String> {
+        self.warnings.clone()
+    }
+
+    /// Stable hash of every match, the dataset part of the cache keys
+    #[getter]
+    fn digest(&self) -> String {
+        self.digest.clone()
+    }
+
+    /// Seasons in the data, by the year they start in
+    #[getter]
+    fn seasons(&self) -> Vec<u16> {
+        let mut years: Vec<u16> = self.games.iter().map(|game| game.year).collect();
+        years.sort_unstable();
+        years.dedup();
+        years
+    }
+
+    fn __len__(&self) -> usize {
+        self.games.len()
+    }
+
+    fn __repr__(&self) -> String {
+        let seasons = self.seasons();
+        format!(
+            "Dataset('{}', {} matches, seasons {}..{})",
+            self.name,
+            self.games.len(),
+            self.kind.label(seasons.first().copied().unwrap_or_default()),
+            self.kind.label(seasons.last().copied().unwrap_or_default()),
+        )
+    }
+
+    #[pyo3(signature = (run_config, hyperparameters, seed=0, objective="rmse"))]
+    /// Errors of one config for each simulated season, the same as `fitness_function`. Both arguments take
+    /// the objects or the plain lists
+    fn evaluate(
+        &self,
+        py: Python,
+        run_config: ConfigArgument,
+        hyperparameters: HyperparametersArgument,
+        seed: u64,
+        objective: &str,
+    ) -> PyResult<Vec<f64>> {
+        let objective: FitnessObjective = objective.parse().map_err(PyValueError::new_err)?;
+        let run_config = run_config.into_config()?;
+        let hyperparameters = hyperparameters.into_hyperparameters()?;
+
+        py.allow_threads(|| {
+            let experiment = self.prepared(&hyperparameters).map_err(PyValueError::new_err)?;
+            run_config
+                .check_divisions(experiment.games(), hyperparameters.components())
+                .map_err(PyValueError::new_err)?;
+            Ok(self.errors(&experiment, &run_config, &hyperparameters, objective, seed)?)
+        })
+    }
+
+    #[pyo3(signature = (run_configs, hyperparameters, seed=0, objective="rmse"))]
+    /// [`evaluate`] for a list of configs, all with the same hyperparameters. They run in parallel with the
+    /// GIL released, and the result keeps the order of `run_configs`
+    fn evaluate_batch(
+        &self,
+        py: Python,
+        run_configs: Vec<ConfigArgument>,
+        hyperparameters: HyperparametersArgument,
+        seed: u64,
+        objective: &str,
+    ) -> PyResult<Vec<Vec<f64>>> {
+        let objective: FitnessObjective = objective.parse().map_err(PyValueError::new_err)?;
+        let run_configs = run_configs
+            .into_iter()
+            .map(ConfigArgument::into_config)
+            .collect::<PyResult<Vec<RunConfig>>>()?;
+        let hyperparameters = hyperparameters.into_hyperparameters()?;
+
+        py.allow_threads(|| {
+            let experiment = self.prepared(&hyperparameters).map_err(PyValueError::new_err)?;
+            run_configs
+                .iter()
+                .try_for_each(|run_config| run_config.check_divisions(experiment.games(), hyperparameters.components()))
+                .map_err(PyValueError::new_err)?;
+            let errors = run_configs
+                .par_iter()
+                .map(|run_config| self.errors(&experiment, run_config, &hyperparameters, objective, seed))
+                .collect::<Result<Vec<Vec<f64>>, CacheError>>()?;
+            Ok(errors)
+        })
+    }
+}
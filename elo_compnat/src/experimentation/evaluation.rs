@@ -0,0 +1,374 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::elo::rating_system::RatingSystem;
+use crate::elo::train::{construct_rated_games_for_time_series, validate_time_series_interval, RatedGame};
+use crate::util::game::{Game, GameResult};
+
+use super::run_config::RunHyperparameters;
+use super::standings_metrics::SeasonObjective;
+
+/// Probabilities are clipped to this before taking logs, so a confident miss costs a lot but not infinity
+const MIN_PROBABILITY: f64 = 1e-15;
+
+/// Proper scoring rules for the H/D/A probabilities of a match. Lower is better for all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoringRule {
+    /// Squared distance between the probabilities and the outcome, from 0 to 2
+    Brier,
+    /// Minus the log of the probability given to what happened
+    LogLoss,
+    /// Ranked Probability Score, which treats H > D > A as ordered, from 0 to 1
+    Rps,
+}
+
+impl ScoringRule {
+    pub const ALL: [ScoringRule; 3] = [ScoringRule::Brier, ScoringRule::LogLoss, ScoringRule::Rps];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScoringRule::Brier => "brier",
+            ScoringRule::LogLoss => "log_loss",
+            ScoringRule::Rps => "rps",
+        }
+    }
+
+    /// Score of a single match, from the (home, draw, away) probabilities
+    pub fn score(&self, probabilities: [f64; 3], result: GameResult) -> f64 {
+        let outcome = outcome_vector(result);
+
+        match self {
+            ScoringRule::Brier => probabilities
+                .iter()
+                .zip(outcome)
+                .map(|(p, o)| (p - o).powi(2))
+                .sum(),
+            ScoringRule::LogLoss => {
+                let index = outcome.iter().position(|&o| o == 1.0).unwrap();
+                -probabilities[index].max(MIN_PROBABILITY).ln()
+            }
+            ScoringRule::Rps => {
+                let mut cumulative_p = 0.0;
+                let mut cumulative_o = 0.0;
+                let mut total = 0.0;
+                for i in 0..2 {
+                    cumulative_p += probabilities[i];
+                    cumulative_o += outcome[i];
+                    total += (cumulative_p - cumulative_o).powi(2);
+                }
+                total / 2.0
+            }
+        }
+    }
+}
+
+impl FromStr for ScoringRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ScoringRule::ALL
+            .into_iter()
+            .find(|rule| rule.name() == s.to_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "Unknown scoring rule '{}'. Options are {:?}",
+                    s,
+                    ScoringRule::ALL.map(|rule| rule.name())
+                )
+            })
+    }
+}
+
+/// (home, draw, away) one-hot vector of a result
+fn outcome_vector(result: GameResult) -> [f64; 3] {
+    match result {
+        GameResult::H => [1.0, 0.0, 0.0],
+        GameResult::D => [0.0, 1.0, 0.0],
+        GameResult::A => [0.0, 0.0, 1.0],
+    }
+}
+
+/// What `fitness_function` returns for each simulated season
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitnessObjective {
+    /// Error between the simulated standings and the real ones, see [`SeasonObjective`]
+    Season(SeasonObjective),
+    /// Mean scoring rule of the match probabilities
+    Scoring(ScoringRule),
+}
+
+impl FitnessObjective {
+    /// Name accepted back by `parse`, e.g. "rmse", "top4" or "log_loss"
+    pub fn name(&self) -> String {
+        match self {
+            FitnessObjective::Season(objective) => objective.name(),
+            FitnessObjective::Scoring(rule) => rule.name().to_string(),
+        }
+    }
+}
+
+impl Default for FitnessObjective {
+    fn default() -> Self {
+        FitnessObjective::Season(SeasonObjective::default())
+    }
+}
+
+impl FromStr for FitnessObjective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(FitnessObjective::Scoring)
+            .or_else(|_| s.parse().map(FitnessObjective::Season))
+            .map_err(|_| {
+                format!(
+                    "Unknown objective '{}'. Options are rmse, spearman, kendall, top<k>, bottom<k>, brier, log_loss and rps",
+                    s
+                )
+            })
+    }
+}
+
+/// Mean of every scoring rule over a set of matches
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MatchScores {
+    pub matches: usize,
+    pub brier: f64,
+    pub log_loss: f64,
+    pub rps: f64,
+}
+
+impl MatchScores {
+    pub fn from_rated_games<'a>(rated_games: impl IntoIterator<Item = &'a RatedGame>) -> Self {
+        let mut scores = MatchScores::default();
+
+        for rated in rated_games {
+            let probabilities = [rated.prob_home, rated.prob_draw, rated.prob_away];
+            scores.matches += 1;
+            scores.brier += ScoringRule::Brier.score(probabilities, rated.game.result);
+            scores.log_loss += ScoringRule::LogLoss.score(probabilities, rated.game.result);
+            scores.rps += ScoringRule::Rps.score(probabilities, rated.game.result);
+        }
+
+        if scores.matches > 0 {
+            let n = scores.matches as f64;
+            scores.brier /= n;
+            scores.log_loss /= n;
+            scores.rps /= n;
+        }
+
+        scores
+    }
+
+    pub fn get(&self, rule: ScoringRule) -> f64 {
+        match rule {
+            ScoringRule::Brier => self.brier,
+            ScoringRule::LogLoss => self.log_loss,
+            ScoringRule::Rps => self.rps,
+        }
+    }
+
+    fn to_dict<'py>(self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        dict.set_item("matches", self.matches)?;
+        for rule in ScoringRule::ALL {
+            dict.set_item(rule.name(), self.get(rule))?;
+        }
+        Ok(dict)
+    }
+}
+
+/// Match-level scores of the seasons after the training period, one by season and one for all of them
+#[derive(Debug, Clone, Default)]
+pub struct Evaluation {
+    pub by_season: BTreeMap<u16, MatchScores>,
+    pub overall: MatchScores,
+}
+
+impl Evaluation {
+    pub fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("overall", self.overall.to_dict(py)?)?;
+
+        let by_season = PyDict::new(py);
+        for (year, scores) in &self.by_season {
+            by_season.set_item(year, scores.to_dict(py)?)?;
+        }
+        dict.set_item("by_season", by_season)?;
+
+        Ok(dict.into())
+    }
+
+    /// Score of every season under `rule`, in the same order as the errors of `run_experiments`
+    pub fn season_scores(&self, rule: ScoringRule) -> Vec<f64> {
+        self.by_season.values().map(|scores| scores.get(rule)).collect()
+    }
+}
+
+/// Matches of the divisions in use and the last season among them
+fn backtest_games(all_games: &[Game], experiment_config: &RunHyperparameters) -> (Vec<Game>, u16) {
+    let components = experiment_config.components();
+    let used_games: Vec<Game> = all_games
+        .iter()
+        .filter(|game| components.uses_division(game.division))
+        .cloned()
+        .collect();
+
+    let end_year = experiment_config.starting_year + experiment_config.backtest_years;
+    let last_year = used_games.iter().map(|game| game.year).max().unwrap_or(end_year);
+    (used_games, last_year)
+}
+
+/// Error message when [`backtest_rated_games`] would panic, because a season from `starting_year` to the
+/// end of the data is missing
+pub fn validate_backtest(all_games: &[Game], experiment_config: &RunHyperparameters) -> Result<(), String> {
+    let (used_games, last_year) = backtest_games(all_games, experiment_config);
+    validate_time_series_interval(&used_games, experiment_config.starting_year, last_year)
+}
+
+/// Rated matches of the seasons after the training period (`starting_year + backtest_years`) until the end
+/// of the data. The ratings are updated with the real results as the seasons go, so each prediction only
+/// uses the matches before it
+pub fn backtest_rated_games<S: RatingSystem>(
+    rating_system: &S,
+    all_games: &[Game],
+    experiment_config: &RunHyperparameters,
+) -> Vec<RatedGame> {
+    let end_year = experiment_config.starting_year + experiment_config.backtest_years;
+    let (used_games, last_year) = backtest_games(all_games, experiment_config);
+
+    let (_, rated_games) = construct_rated_games_for_time_series(
+        rating_system,
+        &used_games,
+        experiment_config,
+        experiment_config.starting_year,
+        last_year,
+    );
+
+    rated_games
+        .into_iter()
+        .filter(|rated| rated.game.year > end_year)
+        .collect()
+}
+
+/// Mean absolute rating change of a team in a match, how much the ratings move from game to game. Lower
+/// is steadier
+pub fn rating_volatility(rated_games: &[RatedGame]) -> f64 {
+    let changes: Vec<f64> = rated_games
+        .iter()
+        .flat_map(|rated| {
+            [
+                rated.game.home_elo.map(|before| (rated.home_elo_after - before).abs()),
+                rated.game.away_elo.map(|before| (rated.away_elo_after - before).abs()),
+            ]
+        })
+        .flatten()
+        .collect();
+
+    changes.iter().sum::<f64>() / changes.len().max(1) as f64
+}
+
+/// Scores the pre-match probabilities of every match in [`backtest_rated_games`]
+pub fn evaluate_predictions<S: RatingSystem>(
+    rating_system: &S,
+    all_games: &[Game],
+    experiment_config: &RunHyperparameters,
+) -> Evaluation {
+    let evaluated = backtest_rated_games(rating_system, all_games, experiment_config);
+
+    let mut by_season: BTreeMap<u16, Vec<&RatedGame>> = BTreeMap::new();
+    for rated in &evaluated {
+        by_season.entry(rated.game.year).or_default().push(rated);
+    }
+
+    Evaluation {
+        by_season: by_season
+            .into_iter()
+            .map(|(year, games)| (year, MatchScores::from_rated_games(games)))
+            .collect(),
+        overall: MatchScores::from_rated_games(&evaluated),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-12;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < TOLERANCE, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn scores_of_a_known_forecast() {
+        let probabilities = [0.5, 0.3, 0.2];
+
+        assert_close(ScoringRule::Brier.score(probabilities, GameResult::H), 0.38);
+        assert_close(ScoringRule::Brier.score(probabilities, GameResult::D), 0.78);
+        assert_close(ScoringRule::Brier.score(probabilities, GameResult::A), 0.98);
+
+        assert_close(ScoringRule::LogLoss.score(probabilities, GameResult::H), 2.0_f64.ln());
+        assert_close(ScoringRule::LogLoss.score(probabilities, GameResult::A), 5.0_f64.ln());
+
+        assert_close(ScoringRule::Rps.score(probabilities, GameResult::H), 0.145);
+        assert_close(ScoringRule::Rps.score(probabilities, GameResult::D), 0.145);
+        assert_close(ScoringRule::Rps.score(probabilities, GameResult::A), 0.445);
+    }
+
+    #[test]
+    fn bounds_of_the_scores() {
+        let certain_home = [1.0, 0.0, 0.0];
+        for rule in ScoringRule::ALL {
+            assert_close(rule.score(certain_home, GameResult::H), 0.0);
+        }
+
+        assert_close(ScoringRule::Brier.score(certain_home, GameResult::A), 2.0);
+        assert_close(ScoringRule::Rps.score(certain_home, GameResult::A), 1.0);
+        // a probabilidade zero é cortada, o log-loss fica grande mas finito
+        assert_close(ScoringRule::LogLoss.score(certain_home, GameResult::A), -MIN_PROBABILITY.ln());
+    }
+
+    #[test]
+    fn rps_is_the_only_one_that_sees_the_order_of_the_outcomes() {
+        let draw = [0.0, 1.0, 0.0];
+        let home = [1.0, 0.0, 0.0];
+
+        // prever empate erra "menos" uma vitória do visitante do que prever vitória do mandante
+        assert!(ScoringRule::Rps.score(draw, GameResult::A) < ScoringRule::Rps.score(home, GameResult::A));
+        assert_eq!(
+            ScoringRule::Brier.score(draw, GameResult::A),
+            ScoringRule::Brier.score(home, GameResult::A)
+        );
+    }
+
+    #[test]
+    fn objectives_are_parsed_back_from_their_names() {
+        for rule in ScoringRule::ALL {
+            assert_eq!(rule.name().parse::<ScoringRule>(), Ok(rule));
+            assert_eq!(rule.name().parse::<FitnessObjective>(), Ok(FitnessObjective::Scoring(rule)));
+        }
+        assert_eq!("LOG_LOSS".parse::<ScoringRule>(), Ok(ScoringRule::LogLoss));
+        assert!("accuracy".parse::<FitnessObjective>().is_err());
+    }
+
+    #[test]
+    fn the_backtest_needs_every_season_of_the_divisions_in_use() {
+        let game = |year: i32, division: u8| {
+            Game::fixture(chrono::NaiveDate::from_ymd_opt(year, 6, 1).unwrap(), "A", "B", division)
+        };
+        // 2019 só existe na segunda divisão
+        let games = vec![game(2018, 1), game(2019, 2), game(2020, 1), game(2021, 1)];
+        let hyperparameters = |starting_year: u16, leagues_to_use: u16| {
+            RunHyperparameters::from_python_list(vec![1000, starting_year, 1, 0, 0, 0, 0, leagues_to_use]).unwrap()
+        };
+
+        assert!(validate_backtest(&games, &hyperparameters(2020, 1)).is_ok());
+        assert!(validate_backtest(&games, &hyperparameters(2018, 2)).is_ok());
+        assert!(validate_backtest(&games, &hyperparameters(2018, 1)).is_err());
+        assert!(validate_backtest(&games, &hyperparameters(2030, 1)).is_err());
+    }
+}
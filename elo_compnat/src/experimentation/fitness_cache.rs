@@ -0,0 +1,352 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use pyo3::exceptions::PyIOError;
+use pyo3::PyErr;
+use rusqlite::{params, Connection, OptionalExtension};
+use thiserror::Error;
+
+use super::evaluation::FitnessObjective;
+use super::run_config::{RunConfig, RunHyperparameters};
+use crate::util::game::Game;
+use crate::util::stable_hash::{StableHash, StableHasher};
+
+/// Version of what the errors of a config depend on: the rating update, the simulation and the objectives.
+/// Bump it with every change that can move an error, so cached results and checkpoints computed before
+/// it are never reused. Releases that leave the model alone keep it
+pub const MODEL_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("Fitness cache {}: {source}", path.display())]
+    Sqlite {
+        path: PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+    #[error("Fitness cache {}: {source}", path.display())]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+impl From<CacheError> for PyErr {
+    fn from(err: CacheError) -> PyErr {
+        PyIOError::new_err(err.to_string())
+    }
+}
+
+/// Everything a fitness evaluation depends on. `digest` is the content address of the result: a stable
+/// hash of every field of the config, the hyperparameters and the matches, the objective and the seed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitnessKey {
+    pub digest: String,
+    pub dataset: String,
+    pub run_config: RunConfig,
+    pub hyperparameters: RunHyperparameters,
+    pub objective: String,
+    pub seed: u64,
+}
+
+impl FitnessKey {
+    /// `dataset` is the [`dataset_digest`] of the matches, computed once per dataset
+    pub fn new(
+        dataset: &str,
+        run_config: &RunConfig,
+        hyperparameters: &RunHyperparameters,
+        objective: FitnessObjective,
+        seed: u64,
+    ) -> Self {
+        let objective = objective.name();
+
+        let mut hasher = StableHasher::new();
+        hasher.write_str("fitness");
+        hasher.write_u64(MODEL_VERSION as u64);
+        hasher.write_str(dataset);
+        hasher.write(run_config);
+        hasher.write(hyperparameters);
+        hasher.write_str(&objective);
+        hasher.write_u64(seed);
+
+        FitnessKey {
+            digest: hasher.finish(),
+            dataset: dataset.to_string(),
+            run_config: run_config.clone(),
+            hyperparameters: hyperparameters.clone(),
+            objective,
+            seed,
+        }
+    }
+}
+
+/// Stable hash of every field of every match, in order
+pub fn dataset_digest(games: &[Game]) -> String {
+    games.stable_digest()
+}
+
+/// Fitness results kept in a local SQLite file, one row per [`FitnessKey`]. The connection is behind a
+/// mutex so the parallel evaluations of an optimizer can share it, and the file is in WAL mode so
+/// several processes (the pyswarms workers) can use it at the same time.
+///
+/// The errors are stored as raw `f64` bytes, so a cached result is bit for bit the computed one. The
+/// config and hyperparameters go along as json, to make the table easy to query from python
+pub struct FitnessCache {
+    path: PathBuf,
+    connection: Mutex<Connection>,
+}
+
+impl FitnessCache {
+    pub fn open(path: &Path) -> Result<Self, CacheError> {
+        let sqlite_error = |source| CacheError::Sqlite {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let connection = Connection::open(path).map_err(sqlite_error)?;
+        connection
+            .busy_timeout(std::time::Duration::from_secs(30))
+            .map_err(sqlite_error)?;
+        // em memória o journal_mode volta "memory", então o resultado não interessa
+        connection
+            .query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            .map_err(sqlite_error)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS fitness (
+                    key TEXT PRIMARY KEY,
+                    version TEXT NOT NULL,
+                    dataset TEXT NOT NULL,
+                    run_config TEXT NOT NULL,
+                    hyperparameters TEXT NOT NULL,
+                    objective TEXT NOT NULL,
+                    seed INTEGER NOT NULL,
+                    errors BLOB NOT NULL,
+                    mean_error REAL,
+                    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+                )",
+            )
+            .map_err(sqlite_error)?;
+
+        Ok(FitnessCache {
+            path: path.to_path_buf(),
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// A cache that lives only as long as the value, for runs that shouldn't touch the disk
+    pub fn in_memory() -> Result<Self, CacheError> {
+        FitnessCache::open(Path::new(":memory:"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn sqlite_error(&self, source: rusqlite::Error) -> CacheError {
+        CacheError::Sqlite {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    fn json_error(&self, source: serde_json::Error) -> CacheError {
+        CacheError::Json {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // um panic com a conexão em mãos não deixa o sqlite num estado ruim
+        self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn get(&self, key: &FitnessKey) -> Result<Option<Vec<f64>>, CacheError> {
+        let bytes: Option<Vec<u8>> = self
+            .connection()
+            .query_row("SELECT errors FROM fitness WHERE key = ?1", [&key.digest], |row| row.get(0))
+            .optional()
+            .map_err(|source| self.sqlite_error(source))?;
+
+        Ok(bytes.map(|bytes| {
+            bytes
+                .chunks_exact(8)
+                .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect()
+        }))
+    }
+
+    /// Stores `errors` under `key`, replacing what was there
+    pub fn insert(&self, key: &FitnessKey, errors: &[f64]) -> Result<(), CacheError> {
+        let run_config = serde_json::to_string(&key.run_config).map_err(|source| self.json_error(source))?;
+        let hyperparameters =
+            serde_json::to_string(&key.hyperparameters).map_err(|source| self.json_error(source))?;
+        let bytes: Vec<u8> = errors.iter().flat_map(|error| error.to_le_bytes()).collect();
+        let mean_error = errors.iter().sum::<f64>() / errors.len().max(1) as f64;
+
+        self.connection()
+            .execute(
+                "INSERT OR REPLACE INTO fitness
+                    (key, version, dataset, run_config, hyperparameters, objective, seed, errors, mean_error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    key.digest,
+                    MODEL_VERSION.to_string(),
+                    key.dataset,
+                    run_config,
+                    hyperparameters,
+                    key.objective,
+                    // o sqlite só tem inteiros com sinal, a semente vai com os mesmos bits
+                    key.seed as i64,
+                    bytes,
+                    mean_error,
+                ],
+            )
+            .map_err(|source| self.sqlite_error(source))?;
+        Ok(())
+    }
+
+    /// The cached errors of `key`, or `compute`'s, which are stored before being returned
+    pub fn get_or_compute<F>(&self, key: &FitnessKey, compute: F) -> Result<Vec<f64>, CacheError>
+    where
+        F: FnOnce() -> Vec<f64>,
+    {
+        if let Some(errors) = self.get(key)? {
+            return Ok(errors);
+        }
+
+        let errors = compute();
+        self.insert(key, &errors)?;
+        Ok(errors)
+    }
+
+    /// Number of stored results
+    pub fn len(&self) -> Result<usize, CacheError> {
+        self.connection()
+            .query_row("SELECT COUNT(*) FROM fitness", [], |row| row.get::<_, i64>(0))
+            .map(|count| count as usize)
+            .map_err(|source| self.sqlite_error(source))
+    }
+
+    pub fn is_empty(&self) -> Result<bool, CacheError> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Removes every stored result
+    pub fn clear(&self) -> Result<(), CacheError> {
+        self.connection()
+            .execute("DELETE FROM fitness", [])
+            .map(|_| ())
+            .map_err(|source| self.sqlite_error(source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::experimentation::evaluation::ScoringRule;
+
+    fn key(seed: u64) -> FitnessKey {
+        FitnessKey::new(
+            "dataset",
+            &RunConfig::default(),
+            &RunHyperparameters::default(),
+            FitnessObjective::default(),
+            seed,
+        )
+    }
+
+    #[test]
+    fn stored_errors_come_back_bit_for_bit() {
+        let cache = FitnessCache::in_memory().unwrap();
+        let errors = vec![15.72, -0.0, f64::NAN, f64::MAX, 1e-300];
+
+        assert_eq!(cache.get(&key(1)).unwrap(), None);
+        cache.insert(&key(1), &errors).unwrap();
+
+        let stored = cache.get(&key(1)).unwrap().unwrap();
+        let bits = |values: &[f64]| values.iter().map(|value| value.to_bits()).collect::<Vec<u64>>();
+        assert_eq!(bits(&stored), bits(&errors));
+        assert_eq!(cache.get(&key(2)).unwrap(), None);
+    }
+
+    #[test]
+    fn computes_each_key_once() {
+        let cache = FitnessCache::in_memory().unwrap();
+        let calls = Cell::new(0);
+        let compute = || {
+            calls.set(calls.get() + 1);
+            vec![1.0, 2.0]
+        };
+
+        assert_eq!(cache.get_or_compute(&key(1), compute).unwrap(), vec![1.0, 2.0]);
+        assert_eq!(cache.get_or_compute(&key(1), compute).unwrap(), vec![1.0, 2.0]);
+        assert_eq!(calls.get(), 1);
+
+        cache.get_or_compute(&key(2), compute).unwrap();
+        assert_eq!(calls.get(), 2);
+        assert_eq!(cache.len().unwrap(), 2);
+
+        cache.clear().unwrap();
+        assert!(cache.is_empty().unwrap());
+    }
+
+    #[test]
+    fn insert_replaces_the_stored_errors() {
+        let cache = FitnessCache::in_memory().unwrap();
+        cache.insert(&key(1), &[1.0]).unwrap();
+        cache.insert(&key(1), &[2.0, 3.0]).unwrap();
+
+        assert_eq!(cache.get(&key(1)).unwrap(), Some(vec![2.0, 3.0]));
+        assert_eq!(cache.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn every_input_goes_into_the_key() {
+        let base = key(1);
+        let digest = |dataset: &str, config: &RunConfig, hyperparameters: &RunHyperparameters, objective, seed| {
+            FitnessKey::new(dataset, config, hyperparameters, objective, seed).digest
+        };
+        let (config, hyperparameters, objective) = (&base.run_config, &base.hyperparameters, FitnessObjective::default());
+
+        assert_eq!(digest("dataset", config, hyperparameters, objective, 1), base.digest);
+        assert_ne!(digest("other", config, hyperparameters, objective, 1), base.digest);
+        assert_ne!(digest("dataset", config, hyperparameters, objective, 2), base.digest);
+
+        let other_config = RunConfig {
+            k_factor: 2.0,
+            ..config.clone()
+        };
+        assert_ne!(digest("dataset", &other_config, hyperparameters, objective, 1), base.digest);
+
+        let other_hyperparameters = RunHyperparameters {
+            random_variations: 5,
+            ..hyperparameters.clone()
+        };
+        assert_ne!(digest("dataset", config, &other_hyperparameters, objective, 1), base.digest);
+
+        let other_objective = FitnessObjective::Scoring(ScoringRule::Brier);
+        assert_ne!(digest("dataset", config, hyperparameters, other_objective, 1), base.digest);
+    }
+
+    #[test]
+    fn a_file_keeps_the_results_between_opens() {
+        let path = std::env::temp_dir().join(format!("fitness_cache_test_{}.sqlite", std::process::id()));
+        {
+            let cache = FitnessCache::open(&path).unwrap();
+            cache.insert(&key(1), &[4.0, 5.0]).unwrap();
+        }
+        let reopened = FitnessCache::open(&path).unwrap();
+        let stored = reopened.get(&key(1)).unwrap();
+        drop(reopened);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        assert_eq!(stored, Some(vec![4.0, 5.0]));
+    }
+}
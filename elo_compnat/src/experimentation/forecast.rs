@@ -0,0 +1,559 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use chrono::NaiveDate;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::elo::rating_system::{RatingSystem, RatingTable};
+use crate::elo::train::{
+    construct_elo_table_for_time_series, construct_elo_table_for_year, start_next_season,
+};
+use crate::elo::util::league::LeagueTable;
+use crate::elo::util::season::{Season, SeasonMap, SeasonSchedule};
+use crate::util::game::Game;
+use crate::util::parsing::DatasetError;
+use crate::util::random::derive_seed;
+
+use super::goal_model::GoalModel;
+use super::run_config::RunHyperparameters;
+use super::simulate_season::simulate_season;
+
+/// Table positions that count for each outcome of a forecast. Every division uses the same zones, so in a
+/// second division `top` reads as promotion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[pyclass(frozen)]
+pub struct Zones {
+    /// Positions that win the league, usually just the first
+    #[pyo3(get)]
+    pub title: usize,
+    /// Main continental spots (Champions League, Libertadores)
+    #[pyo3(get)]
+    pub top: usize,
+    /// Every continental spot, secondary competitions included
+    #[pyo3(get)]
+    pub extended_top: usize,
+    /// Positions at the bottom that go down
+    #[pyo3(get)]
+    pub relegation: usize,
+}
+
+impl Default for Zones {
+    fn default() -> Self {
+        Zones {
+            title: 1,
+            top: 4,
+            extended_top: 6,
+            relegation: 4,
+        }
+    }
+}
+
+#[pymethods]
+impl Zones {
+    #[new]
+    #[pyo3(signature = (title=1, top=4, extended_top=6, relegation=4))]
+    fn new(title: usize, top: usize, extended_top: usize, relegation: usize) -> Self {
+        Zones {
+            title,
+            top,
+            extended_top,
+            relegation,
+        }
+    }
+
+    /// Usual zones of a bundled league, by display name (`"Brasileirão"`) or file name (`"brasileirao"`).
+    /// Direct relegation only, the playoffs of Germany and France count as staying up
+    #[staticmethod]
+    pub fn for_league(league: &str) -> Zones {
+        match league.to_lowercase().as_str() {
+            "brasileirão" | "brasileirao" => Zones::default(),
+            "inglaterra" | "espanha" | "itália" | "italia" => Zones {
+                relegation: 3,
+                ..Zones::default()
+            },
+            "alemanha" => Zones {
+                relegation: 2,
+                ..Zones::default()
+            },
+            "frança" | "franca" => Zones {
+                top: 3,
+                extended_top: 5,
+                relegation: 3,
+                ..Zones::default()
+            },
+            _ => Zones::default(),
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+/// Point of a season up to which the real results are kept in a forecast
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cutoff {
+    /// Rounds up to and including this one were played. The playoffs, filed as week 1 but played after
+    /// the season, count as the round after the last
+    Round(u16),
+    /// Matches strictly before this day were played
+    Date(NaiveDate),
+}
+
+impl Cutoff {
+    pub fn is_played(&self, game: &Game, schedule: &SeasonSchedule) -> bool {
+        match self {
+            Cutoff::Round(round) => schedule.round(game) <= *round as f32,
+            Cutoff::Date(date) => game.date < *date,
+        }
+    }
+}
+
+impl std::fmt::Display for Cutoff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cutoff::Round(round) => write!(f, "round {}", round),
+            Cutoff::Date(date) => write!(f, "{}", date),
+        }
+    }
+}
+
+/// Outcome distribution of a team over every simulation of a season
+#[derive(Debug, Clone)]
+pub struct TeamForecast {
+    pub team: String,
+    pub division: u8,
+    /// Rating at the start of the forecast, after the matches before the cutoff
+    pub rating: f64,
+    /// Real table at the cutoff, all zeros when forecasting from the first game
+    pub played: u16,
+    pub current_points: u16,
+    pub current_position: Option<usize>,
+    pub mean_points: f64,
+    pub mean_position: f64,
+    /// `positions[i]` is the probability of finishing in position `i + 1`
+    pub positions: Vec<f64>,
+    /// Probability of each final points total
+    pub points: BTreeMap<u16, f64>,
+    pub title: f64,
+    pub top: f64,
+    pub extended_top: f64,
+    pub relegation: f64,
+}
+
+impl TeamForecast {
+    /// Smallest points total reached with probability at least `quantile`
+    pub fn points_quantile(&self, quantile: f64) -> u16 {
+        let mut accumulated = 0.0;
+        for (points, probability) in &self.points {
+            accumulated += probability;
+            if accumulated >= quantile {
+                return *points;
+            }
+        }
+        self.points.keys().next_back().copied().unwrap_or_default()
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        dict.set_item("division", self.division)?;
+        dict.set_item("rating", self.rating)?;
+        dict.set_item("played", self.played)?;
+        dict.set_item("current_points", self.current_points)?;
+        dict.set_item("current_position", self.current_position)?;
+        dict.set_item("mean_points", self.mean_points)?;
+        dict.set_item("mean_position", self.mean_position)?;
+        dict.set_item("positions", self.positions.clone())?;
+        dict.set_item("points", self.points.clone())?;
+        dict.set_item("title", self.title)?;
+        dict.set_item("top", self.top)?;
+        dict.set_item("extended_top", self.extended_top)?;
+        dict.set_item("relegation", self.relegation)?;
+        Ok(dict)
+    }
+}
+
+/// Monte Carlo forecast of a season, teams ordered by division and expected position
+#[derive(Debug, Clone)]
+pub struct SeasonForecast {
+    pub year: u16,
+    pub league: String,
+    pub simulations: u32,
+    pub zones: Zones,
+    pub cutoff: Option<Cutoff>,
+    pub teams: Vec<TeamForecast>,
+}
+
+impl SeasonForecast {
+    pub fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("year", self.year)?;
+        dict.set_item("league", &self.league)?;
+        dict.set_item("simulations", self.simulations)?;
+        dict.set_item("zones", self.zones.into_py(py))?;
+        dict.set_item("cutoff", self.cutoff.map(|cutoff| cutoff.to_string()))?;
+
+        let teams = PyDict::new(py);
+        for team in &self.teams {
+            teams.set_item(&team.team, team.to_dict(py)?)?;
+        }
+        dict.set_item("teams", teams)?;
+
+        Ok(dict.into())
+    }
+}
+
+/// Ratings and goal model at the start of season `year`, trained on the seasons from
+/// `experiment_config.starting_year` up to the one before it
+pub fn season_start<S: RatingSystem>(
+    rating_system: &S,
+    all_games: &[Game],
+    seasons_map: &SeasonMap,
+    experiment_config: &RunHyperparameters,
+    year: u16,
+) -> (RatingTable<S::Rating>, GoalModel) {
+    let past_games: Vec<Game> = all_games
+        .iter()
+        .filter(|game| game.year >= experiment_config.starting_year && game.year < year)
+        .cloned()
+        .collect();
+    let goal_model = GoalModel::from_games(&past_games);
+
+    if year <= experiment_config.starting_year {
+        return (RatingTable::new(), goal_model);
+    }
+
+    let elo_table = construct_elo_table_for_time_series(
+        rating_system,
+        all_games,
+        experiment_config,
+        experiment_config.starting_year,
+        year - 1,
+    );
+
+    let elo_table = match (seasons_map.get(&(year - 1)), seasons_map.get(&year)) {
+        (Some(previous), Some(season)) => {
+            start_next_season(rating_system, elo_table, previous, season, experiment_config)
+        }
+        _ => elo_table,
+    };
+
+    (elo_table, goal_model)
+}
+
+/// Simulates `season` `simulations` times from `starting_elos` and collects where every team finished.
+///
+/// With a `cutoff`, the real results before it are kept: the ratings are trained on them the same way as
+/// [`construct_elo_table_for_year`] and only the remaining fixtures are simulated, on top of the real
+/// table. Simulation `i` draws from a stream derived from `seed` and `i`, so the same arguments give the
+/// same forecast
+#[allow(clippy::too_many_arguments)]
+pub fn forecast_season<S: RatingSystem>(
+    rating_system: &S,
+    season: &Season,
+    starting_elos: &RatingTable<S::Rating>,
+    goal_model: &GoalModel,
+    experiment_config: &RunHyperparameters,
+    zones: &Zones,
+    simulations: u32,
+    seed: u64,
+    cutoff: Option<Cutoff>,
+) -> SeasonForecast {
+    // cada time entra só na tabela da sua divisão, os playoffs ficam de fora
+    let schedule = season.schedule();
+
+    let (played, remaining): (Vec<Game>, Vec<Game>) = season
+        .matches
+        .iter()
+        .cloned()
+        .partition(|game| cutoff.is_some_and(|cutoff| cutoff.is_played(game, &schedule)));
+
+    let elo_table = if played.is_empty() {
+        starting_elos.clone()
+    } else {
+        construct_elo_table_for_year(rating_system, &played, Some(starting_elos.clone()), experiment_config)
+    };
+
+    // tabela real até o corte: (jogos, pontos, posição)
+    let mut current: HashMap<String, (u16, u16, usize)> = HashMap::new();
+    for (division, division_games) in schedule.league_games(&played) {
+        let table = LeagueTable::new(&division_games, &season.league, &division);
+        for (position, (team, stats)) in table.rank().iter().enumerate() {
+            current.insert(team.to_string(), (stats.played, stats.points, position + 1));
+        }
+    }
+
+    // (posições, pontos) de cada time em cada simulação
+    let mut finishes: HashMap<String, (u8, Vec<usize>, Vec<u16>)> = HashMap::new();
+    let mut division_sizes: HashMap<u8, usize> = HashMap::new();
+
+    for i in 0..simulations {
+        let (_, simulated_games, _) = simulate_season(
+            &remaining,
+            &elo_table,
+            rating_system,
+            goal_model,
+            experiment_config,
+            derive_seed(seed, &[i as u64]),
+        );
+
+        let season_games: Vec<Game> = played.iter().chain(simulated_games.iter()).cloned().collect();
+        for (division, division_games) in schedule.league_games(&season_games) {
+            let table = LeagueTable::new(&division_games, &season.league, &division);
+            let ranked = table.rank();
+            division_sizes.insert(division, ranked.len());
+
+            for (position, (team, stats)) in ranked.iter().enumerate() {
+                let entry = finishes
+                    .entry(team.to_string())
+                    .or_insert_with(|| (division, Vec::new(), Vec::new()));
+                entry.1.push(position + 1);
+                entry.2.push(stats.points);
+            }
+        }
+    }
+
+    let starting_rating = rating_system.initial_rating(experiment_config.starting_elo.into());
+
+    let mut teams: Vec<TeamForecast> = finishes
+        .into_iter()
+        .map(|(team, (division, positions, points))| {
+            let size = division_sizes[&division];
+            let runs = positions.len() as f64;
+            let share = |count: usize| count as f64 / runs;
+
+            let mut position_share = vec![0.0; size];
+            for &position in &positions {
+                position_share[position - 1] += 1.0 / runs;
+            }
+
+            let mut points_share: BTreeMap<u16, f64> = BTreeMap::new();
+            for &p in &points {
+                *points_share.entry(p).or_default() += 1.0 / runs;
+            }
+
+            let rating = elo_table.get(&team).copied().unwrap_or(starting_rating);
+            let (played, current_points, current_position) = match current.get(&team) {
+                Some(&(played, points, position)) => (played, points, Some(position)),
+                None => (0, 0, None),
+            };
+
+            TeamForecast {
+                division,
+                rating: rating_system.rating_value(&rating),
+                played,
+                current_points,
+                current_position,
+                mean_points: points.iter().map(|&p| p as f64).sum::<f64>() / runs,
+                mean_position: positions.iter().sum::<usize>() as f64 / runs,
+                title: share(positions.iter().filter(|&&p| p <= zones.title).count()),
+                top: share(positions.iter().filter(|&&p| p <= zones.top).count()),
+                extended_top: share(positions.iter().filter(|&&p| p <= zones.extended_top).count()),
+                relegation: share(
+                    positions
+                        .iter()
+                        .filter(|&&p| p + zones.relegation > size)
+                        .count(),
+                ),
+                positions: position_share,
+                points: points_share,
+                team,
+            }
+        })
+        .collect();
+
+    teams.sort_by(|a, b| {
+        a.division
+            .cmp(&b.division)
+            .then_with(|| a.mean_position.total_cmp(&b.mean_position))
+    });
+
+    SeasonForecast {
+        year: season.year,
+        league: season.league.clone(),
+        simulations,
+        zones: *zones,
+        cutoff,
+        teams,
+    }
+}
+
+/// Writes one row per team with the zone probabilities, points percentiles and the chance of every position
+pub fn write_forecast_csv(forecast: &SeasonForecast, output: &Path) -> Result<(), DatasetError> {
+    let write_error = |source: csv::Error| DatasetError::Io {
+        path: output.to_path_buf(),
+        source,
+    };
+
+    let positions = forecast
+        .teams
+        .iter()
+        .map(|team| team.positions.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut writer = csv::Writer::from_path(output).map_err(write_error)?;
+
+    let mut header: Vec<String> = [
+        "team",
+        "division",
+        "rating",
+        "played",
+        "current_points",
+        "current_position",
+        "mean_position",
+        "mean_points",
+        "points_p10",
+        "points_median",
+        "points_p90",
+        "title",
+        "top",
+        "extended_top",
+        "relegation",
+    ]
+    .iter()
+    .map(|column| column.to_string())
+    .collect();
+    header.extend((1..=positions).map(|position| format!("position_{}", position)));
+    writer.write_record(&header).map_err(write_error)?;
+
+    for team in &forecast.teams {
+        let mut record = vec![
+            team.team.clone(),
+            team.division.to_string(),
+            format!("{:.2}", team.rating),
+            team.played.to_string(),
+            team.current_points.to_string(),
+            team.current_position.map(|p| p.to_string()).unwrap_or_default(),
+            format!("{:.3}", team.mean_position),
+            format!("{:.3}", team.mean_points),
+            team.points_quantile(0.1).to_string(),
+            team.points_quantile(0.5).to_string(),
+            team.points_quantile(0.9).to_string(),
+            format!("{:.4}", team.title),
+            format!("{:.4}", team.top),
+            format!("{:.4}", team.extended_top),
+            format!("{:.4}", team.relegation),
+        ];
+        record.extend(
+            (0..positions).map(|i| format!("{:.4}", team.positions.get(i).copied().unwrap_or(0.0))),
+        );
+        writer.write_record(&record).map_err(write_error)?;
+    }
+    writer.flush().map_err(|e| write_error(e.into()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elo::util::season::construct_seasons;
+    use crate::experimentation::run_config::{ActiveComponents, CustomElo, RunConfig};
+
+    use super::*;
+
+    fn game(week: f32, day: u32, home: &str, away: &str, division: u8) -> Game {
+        let date = NaiveDate::from_ymd_opt(2021, 8, 1).unwrap() + chrono::Duration::days(day as i64);
+        Game {
+            week,
+            year: 2021,
+            ..Game::fixture(date, home, away, division)
+        }
+    }
+
+    /// Two divisions of four teams playing each other twice, then the playoffs filed as week 1 of the second
+    /// division: one against a team of the first division and one against a team of the third
+    fn season_with_playoff() -> Season {
+        let mut games = Vec::new();
+        for (division, teams) in [(1, ["A", "B", "C", "D"]), (2, ["E", "F", "G", "H"])] {
+            let mut round = 0;
+            for home in teams {
+                for away in teams.iter().filter(|&&away| away != home) {
+                    round += 1;
+                    games.push(game(round as f32, 7 * round, home, away, division));
+                }
+            }
+        }
+        games.push(game(1.0, 100, "D", "E", 2));
+        games.push(game(1.0, 104, "E", "D", 2));
+        games.push(game(1.0, 101, "I", "G", 2));
+        games.push(game(1.0, 105, "G", "I", 2));
+
+        construct_seasons(&games).remove(&2021).unwrap()
+    }
+
+    #[test]
+    fn playoff_teams_are_only_ranked_in_their_own_division() {
+        let season = season_with_playoff();
+        let experiment_config = RunHyperparameters {
+            leagues_to_use: 0,
+            ..RunHyperparameters::default()
+        };
+        let rating_system = CustomElo::new(RunConfig::default(), ActiveComponents::default());
+        let simulations = 50;
+
+        let forecast = forecast_season(
+            &rating_system,
+            &season,
+            &RatingTable::new(),
+            &GoalModel::default(),
+            &experiment_config,
+            &Zones::default(),
+            simulations,
+            3,
+            None,
+        );
+
+        assert_eq!(forecast.teams.len(), 8);
+        for team in &forecast.teams {
+            let expected_division = if "ABCD".contains(team.team.as_str()) { 1 } else { 2 };
+            assert_eq!(team.division, expected_division, "{}", team.team);
+            assert_eq!(team.positions.len(), 4);
+            assert!((team.positions.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            assert!((team.points.values().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn the_current_table_leaves_the_playoff_out() {
+        let season = season_with_playoff();
+        let rating_system = CustomElo::new(RunConfig::default(), ActiveComponents::default());
+
+        let forecast = forecast_season(
+            &rating_system,
+            &season,
+            &RatingTable::new(),
+            &GoalModel::default(),
+            &RunHyperparameters::default(),
+            &Zones::default(),
+            10,
+            3,
+            Some(Cutoff::Date(NaiveDate::from_ymd_opt(2022, 1, 1).unwrap())),
+        );
+
+        for name in ["D", "E"] {
+            let team = forecast.teams.iter().find(|team| team.team == name).unwrap();
+            assert_eq!(team.played, 6);
+            assert!((team.positions.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn the_playoffs_are_played_after_the_last_round() {
+        let season = season_with_playoff();
+        let schedule = season.schedule();
+        let played = |round: u16| {
+            season
+                .matches
+                .iter()
+                .filter(|game| Cutoff::Round(round).is_played(game, &schedule))
+                .count()
+        };
+
+        // as duas divisões têm 12 rodadas, uma partida por rodada
+        assert_eq!(played(1), 2);
+        assert_eq!(played(12), 24);
+        assert_eq!(played(13), 28);
+    }
+}
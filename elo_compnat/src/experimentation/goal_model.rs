@@ -0,0 +1,208 @@
+use rand::Rng;
+
+use crate::util::game::{Game, GameResult};
+
+/// Scores above this are treated as impossible when sampling, their probability is negligible
+pub const MAX_GOALS: u16 = 10;
+
+/// Dixon-Coles goal model used to give simulated matches a scoreline.
+///
+/// A match has `2 * goals_per_team` goals on average, split by how much the rating system favours each
+/// side: `λ_home / λ_away = exp(2 * rating_sensitivity * (E - 0.5))`, where `E` is the expected score of
+/// the home team. The home advantage therefore comes from the rating system itself. `rho` is the
+/// Dixon-Coles correction for the low scores (0-0, 1-0, 0-1, 1-1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GoalModel {
+    pub goals_per_team: f64,
+    pub rating_sensitivity: f64,
+    pub rho: f64,
+}
+
+impl Default for GoalModel {
+    fn default() -> Self {
+        GoalModel {
+            goals_per_team: 1.3,
+            rating_sensitivity: 2.0,
+            rho: -0.1,
+        }
+    }
+}
+
+impl GoalModel {
+    /// Default model with the scoring rate of `games`, which should be matches already played
+    pub fn from_games(games: &[Game]) -> Self {
+        if games.is_empty() {
+            return GoalModel::default();
+        }
+
+        let goals: u64 = games
+            .iter()
+            .map(|game| (game.home_score + game.away_score) as u64)
+            .sum();
+
+        GoalModel {
+            goals_per_team: goals as f64 / (2 * games.len()) as f64,
+            ..GoalModel::default()
+        }
+    }
+
+    /// Expected goals of the home and away sides, given the expected score of the home team
+    pub fn expected_goals(&self, expected_home: f64) -> (f64, f64) {
+        let supremacy = self.rating_sensitivity * (expected_home - 0.5);
+        let home_share = supremacy.exp() / (supremacy.exp() + (-supremacy).exp());
+        let total = 2.0 * self.goals_per_team;
+
+        (total * home_share, total * (1.0 - home_share))
+    }
+
+    /// Probability of every scoreline up to [`MAX_GOALS`], indexed as `[home][away]`. The tail beyond
+    /// the grid is dropped, so the total is slightly below 1
+    pub fn score_matrix(&self, expected_home: f64) -> Vec<Vec<f64>> {
+        let (lambda, mu) = self.expected_goals(expected_home);
+        let home = poisson_pmf(lambda);
+        let away = poisson_pmf(mu);
+
+        home.iter()
+            .enumerate()
+            .map(|(h, p_home)| {
+                away.iter()
+                    .enumerate()
+                    .map(|(a, p_away)| p_home * p_away * self.tau(h, a, lambda, mu))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Samples a scoreline consistent with `result`, so the outcome stays the one drawn from the rating
+    /// system and only the goals come from this model
+    pub fn sample_score<R: Rng>(&self, rng: &mut R, expected_home: f64, result: GameResult) -> (u16, u16) {
+        let matrix = self.score_matrix(expected_home);
+
+        let cells: Vec<(u16, u16, f64)> = matrix
+            .iter()
+            .enumerate()
+            .flat_map(|(h, row)| row.iter().enumerate().map(move |(a, p)| (h as u16, a as u16, *p)))
+            .filter(|(h, a, _)| match result {
+                GameResult::H => h > a,
+                GameResult::A => h < a,
+                GameResult::D => h == a,
+            })
+            .collect();
+
+        let total: f64 = cells.iter().map(|(_, _, p)| p).sum();
+        let mut target = rng.gen::<f64>() * total;
+
+        for (h, a, p) in &cells {
+            if target < *p {
+                return (*h, *a);
+            }
+            target -= p;
+        }
+
+        // só chega aqui por arredondamento
+        match result {
+            GameResult::H => (1, 0),
+            GameResult::A => (0, 1),
+            GameResult::D => (1, 1),
+        }
+    }
+
+    fn tau(&self, home_goals: usize, away_goals: usize, lambda: f64, mu: f64) -> f64 {
+        let correction = match (home_goals, away_goals) {
+            (0, 0) => 1.0 - lambda * mu * self.rho,
+            (0, 1) => 1.0 + lambda * self.rho,
+            (1, 0) => 1.0 + mu * self.rho,
+            (1, 1) => 1.0 - self.rho,
+            _ => 1.0,
+        };
+        correction.max(0.0)
+    }
+}
+
+/// P(X = k) for k in 0..=MAX_GOALS, with X ~ Poisson(lambda)
+fn poisson_pmf(lambda: f64) -> Vec<f64> {
+    let mut pmf = Vec::with_capacity(MAX_GOALS as usize + 1);
+    let mut p = (-lambda).exp();
+    for k in 0..=MAX_GOALS {
+        pmf.push(p);
+        p *= lambda / (k + 1) as f64;
+    }
+    pmf
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-12;
+
+    #[test]
+    fn an_even_match_splits_the_goals_evenly() {
+        let model = GoalModel::default();
+
+        let (home, away) = model.expected_goals(0.5);
+        assert!((home - 1.3).abs() < TOLERANCE);
+        assert!((away - 1.3).abs() < TOLERANCE);
+
+        let (home, away) = model.expected_goals(0.8);
+        assert!(home > away);
+        assert!((home + away - 2.6).abs() < TOLERANCE);
+        // λ_home / λ_away = exp(2 * sensitivity * (E - 0.5))
+        assert!((home / away - (2.0 * 2.0 * 0.3_f64).exp()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn without_rho_the_scores_are_independent_poissons() {
+        let model = GoalModel {
+            rho: 0.0,
+            ..GoalModel::default()
+        };
+        let matrix = model.score_matrix(0.5);
+
+        let p = |goals: i32| (-1.3_f64).exp() * 1.3_f64.powi(goals) / (1..=goals).product::<i32>() as f64;
+        assert!((matrix[0][0] - (-2.6_f64).exp()).abs() < TOLERANCE);
+        assert!((matrix[2][1] - p(2) * p(1)).abs() < TOLERANCE);
+        assert!((matrix[3][3] - p(3) * p(3)).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn rho_only_moves_probability_between_the_low_scores() {
+        let independent = GoalModel {
+            rho: 0.0,
+            ..GoalModel::default()
+        }
+        .score_matrix(0.5);
+        let model = GoalModel::default();
+        let matrix = model.score_matrix(0.5);
+        let (lambda, mu) = (1.3, 1.3);
+
+        assert!((matrix[0][0] - independent[0][0] * (1.0 + lambda * mu * 0.1)).abs() < TOLERANCE);
+        assert!((matrix[0][1] - independent[0][1] * (1.0 - lambda * 0.1)).abs() < TOLERANCE);
+        assert!((matrix[1][0] - independent[1][0] * (1.0 - mu * 0.1)).abs() < TOLERANCE);
+        assert!((matrix[1][1] - independent[1][1] * 1.1).abs() < TOLERANCE);
+        assert_eq!(matrix[2][0], independent[2][0]);
+
+        // a correção de Dixon-Coles não muda a massa total, só o que fica fora da grade
+        let total = |m: &Vec<Vec<f64>>| m.iter().flatten().sum::<f64>();
+        assert!((total(&matrix) - total(&independent)).abs() < TOLERANCE);
+        assert!(total(&matrix) < 1.0 && total(&matrix) > 0.999);
+    }
+
+    #[test]
+    fn sampled_scores_agree_with_the_result() {
+        let model = GoalModel::default();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..200 {
+            let (home, away) = model.sample_score(&mut rng, 0.6, GameResult::H);
+            assert!(home > away);
+            let (home, away) = model.sample_score(&mut rng, 0.6, GameResult::D);
+            assert_eq!(home, away);
+            let (home, away) = model.sample_score(&mut rng, 0.6, GameResult::A);
+            assert!(home < away);
+        }
+    }
+}
@@ -1,5 +1,15 @@
+pub mod benchmark;
+pub mod bootstrap;
+pub mod calibration;
+pub mod dataset;
+pub mod evaluation;
+pub mod fitness_cache;
+pub mod forecast;
+pub mod goal_model;
+pub mod regression_dataset;
 pub mod run_all_experiments;
 pub mod run_config;
 pub mod run_single_experiment;
 pub mod simulate_season;
 pub mod season_standings;
+pub mod standings_metrics;
@@ -0,0 +1,386 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde::Serialize;
+
+use crate::elo::train::{construct_rated_games_for_time_series, RatedGame};
+use crate::util::game::{Game, GameResult};
+use crate::util::parsing::DatasetError;
+
+use super::run_config::{CustomElo, RunConfig, RunHyperparameters};
+
+/// Number of previous matches used for the form and goal averages when nothing else is asked for
+pub const DEFAULT_FORM_WINDOW: usize = 5;
+
+/// One row of the regression dataset. Every feature only looks at what was known before kick-off:
+/// pre-match elos, the market values of the row itself and the teams' previous matches.
+/// Form and averages are `None` until a team has played once, rest days until it has a previous match
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchFeatures {
+    pub season: u16,
+    pub date: NaiveDate,
+    pub division: u8,
+    pub home: String,
+    pub away: String,
+    pub home_elo: f64,
+    pub away_elo: f64,
+    pub elo_diff: f64,
+    /// `elo_diff` plus the home advantage the model gives the home team, zero when that component is off
+    pub elo_diff_with_home: f64,
+    pub market_value_diff: f64,
+    /// Points per game over the last `form_window` matches
+    pub home_form: Option<f64>,
+    pub away_form: Option<f64>,
+    pub home_goal_diff_avg: Option<f64>,
+    pub away_goal_diff_avg: Option<f64>,
+    pub home_rest_days: Option<i32>,
+    pub away_rest_days: Option<i32>,
+    pub result: GameResult,
+    pub home_goals: u16,
+    pub away_goals: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureFormat {
+    Csv,
+    Parquet,
+}
+
+impl FeatureFormat {
+    /// Picks the format from the output file extension, csv unless it ends with `.parquet`
+    pub fn from_path(path: &Path) -> FeatureFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") => FeatureFormat::Parquet,
+            _ => FeatureFormat::Csv,
+        }
+    }
+}
+
+/// What we remember about a team's previous matches
+struct PastMatch {
+    date: NaiveDate,
+    points: f64,
+    goal_diff: f64,
+}
+
+#[derive(Default)]
+struct TeamHistory {
+    matches: VecDeque<PastMatch>,
+}
+
+impl TeamHistory {
+    fn form(&self) -> Option<f64> {
+        average(self.matches.iter().map(|m| m.points))
+    }
+
+    fn goal_diff_avg(&self) -> Option<f64> {
+        average(self.matches.iter().map(|m| m.goal_diff))
+    }
+
+    fn rest_days(&self, date: NaiveDate) -> Option<i32> {
+        self.matches
+            .back()
+            .map(|last| (date - last.date).num_days() as i32)
+    }
+
+    fn push(&mut self, past: PastMatch, window: usize) {
+        self.matches.push_back(past);
+        while self.matches.len() > window {
+            self.matches.pop_front();
+        }
+    }
+}
+
+fn average(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0usize), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Trains the elo from `start_year` to `end_year` and turns every rated match into a feature row.
+/// The rows come out in chronological order, and a match only sees the matches before it
+pub fn build_regression_dataset(
+    all_games: &[Game],
+    run_config: &RunConfig,
+    experiment_config: &RunHyperparameters,
+    start_year: u16,
+    end_year: u16,
+    form_window: usize,
+) -> Vec<MatchFeatures> {
+    let custom_elo = CustomElo::new(run_config.clone(), experiment_config.components());
+
+    let (_, rated_games) = construct_rated_games_for_time_series(
+        &custom_elo,
+        all_games,
+        experiment_config,
+        start_year,
+        end_year,
+    );
+
+    features_from_rated_games(&rated_games, run_config, experiment_config, form_window)
+}
+
+pub fn features_from_rated_games(
+    rated_games: &[RatedGame],
+    run_config: &RunConfig,
+    experiment_config: &RunHyperparameters,
+    form_window: usize,
+) -> Vec<MatchFeatures> {
+    let components = experiment_config.components();
+    let home_advantage = if components.home_advantage {
+        run_config.home_advantage
+    } else {
+        0.0
+    };
+
+    let mut history: HashMap<&str, TeamHistory> = HashMap::new();
+    let mut features = Vec::with_capacity(rated_games.len());
+
+    for rated in rated_games {
+        let game = &rated.game;
+        let home_elo = game.home_elo.unwrap_or_default();
+        let away_elo = game.away_elo.unwrap_or_default();
+
+        let home_history = history.entry(game.home.as_str()).or_default();
+        let (home_form, home_goal_diff_avg, home_rest_days) = (
+            home_history.form(),
+            home_history.goal_diff_avg(),
+            home_history.rest_days(game.date),
+        );
+        let away_history = history.entry(game.away.as_str()).or_default();
+        let (away_form, away_goal_diff_avg, away_rest_days) = (
+            away_history.form(),
+            away_history.goal_diff_avg(),
+            away_history.rest_days(game.date),
+        );
+
+        features.push(MatchFeatures {
+            season: game.year,
+            date: game.date,
+            division: game.division,
+            home: game.home.clone(),
+            away: game.away.clone(),
+            home_elo,
+            away_elo,
+            elo_diff: home_elo - away_elo,
+            elo_diff_with_home: home_elo + home_advantage - away_elo,
+            market_value_diff: game.home_value - game.away_value,
+            home_form,
+            away_form,
+            home_goal_diff_avg,
+            away_goal_diff_avg,
+            home_rest_days,
+            away_rest_days,
+            result: game.result,
+            home_goals: game.home_score,
+            away_goals: game.away_score,
+        });
+
+        // só depois de gerar a linha o resultado entra no histórico, para não vazar o alvo
+        let (home_points, away_points) = match game.result {
+            GameResult::H => (3.0, 0.0),
+            GameResult::D => (1.0, 1.0),
+            GameResult::A => (0.0, 3.0),
+        };
+        let goal_diff = game.home_score as f64 - game.away_score as f64;
+
+        history.entry(game.home.as_str()).or_default().push(
+            PastMatch {
+                date: game.date,
+                points: home_points,
+                goal_diff,
+            },
+            form_window,
+        );
+        history.entry(game.away.as_str()).or_default().push(
+            PastMatch {
+                date: game.date,
+                points: away_points,
+                goal_diff: -goal_diff,
+            },
+            form_window,
+        );
+    }
+
+    features
+}
+
+pub fn write_features(
+    features: &[MatchFeatures],
+    output: &Path,
+    format: FeatureFormat,
+) -> Result<(), DatasetError> {
+    match format {
+        FeatureFormat::Csv => write_features_csv(features, output),
+        FeatureFormat::Parquet => write_features_parquet(features, output),
+    }
+}
+
+fn write_features_csv(features: &[MatchFeatures], output: &Path) -> Result<(), DatasetError> {
+    let write_error = |source: csv::Error| DatasetError::Io {
+        path: output.to_path_buf(),
+        source,
+    };
+
+    let mut writer = csv::Writer::from_path(output).map_err(write_error)?;
+    for row in features {
+        writer.serialize(row).map_err(write_error)?;
+    }
+    writer.flush().map_err(|e| write_error(e.into()))?;
+
+    Ok(())
+}
+
+/// A parquet column, nullable when it holds `Option`s
+enum Column {
+    Int(Vec<Option<i32>>),
+    Double(Vec<Option<f64>>),
+    Text(Vec<String>),
+    Date(Vec<i32>),
+}
+
+impl Column {
+    fn schema(&self, name: &str) -> String {
+        match self {
+            Column::Int(values) if values.iter().all(Option::is_some) => {
+                format!("REQUIRED INT32 {};", name)
+            }
+            Column::Int(_) => format!("OPTIONAL INT32 {};", name),
+            Column::Double(values) if values.iter().all(Option::is_some) => {
+                format!("REQUIRED DOUBLE {};", name)
+            }
+            Column::Double(_) => format!("OPTIONAL DOUBLE {};", name),
+            Column::Text(_) => format!("REQUIRED BYTE_ARRAY {} (UTF8);", name),
+            Column::Date(_) => format!("REQUIRED INT32 {} (DATE);", name),
+        }
+    }
+}
+
+/// Definition levels for a nullable column, `None` when nothing is missing and the column is required
+fn definition_levels<T>(values: &[Option<T>]) -> Option<Vec<i16>> {
+    if values.iter().all(Option::is_some) {
+        None
+    } else {
+        Some(values.iter().map(|v| v.is_some() as i16).collect())
+    }
+}
+
+fn feature_columns(features: &[MatchFeatures]) -> Vec<(&'static str, Column)> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    let int = |f: fn(&MatchFeatures) -> Option<i32>| Column::Int(features.iter().map(f).collect());
+    let double =
+        |f: fn(&MatchFeatures) -> Option<f64>| Column::Double(features.iter().map(f).collect());
+    let text = |f: fn(&MatchFeatures) -> String| Column::Text(features.iter().map(f).collect());
+
+    vec![
+        ("season", int(|r| Some(r.season as i32))),
+        (
+            "date",
+            Column::Date(
+                features
+                    .iter()
+                    .map(|r| (r.date - epoch).num_days() as i32)
+                    .collect(),
+            ),
+        ),
+        ("division", int(|r| Some(r.division as i32))),
+        ("home", text(|r| r.home.clone())),
+        ("away", text(|r| r.away.clone())),
+        ("home_elo", double(|r| Some(r.home_elo))),
+        ("away_elo", double(|r| Some(r.away_elo))),
+        ("elo_diff", double(|r| Some(r.elo_diff))),
+        ("elo_diff_with_home", double(|r| Some(r.elo_diff_with_home))),
+        ("market_value_diff", double(|r| Some(r.market_value_diff))),
+        ("home_form", double(|r| r.home_form)),
+        ("away_form", double(|r| r.away_form)),
+        ("home_goal_diff_avg", double(|r| r.home_goal_diff_avg)),
+        ("away_goal_diff_avg", double(|r| r.away_goal_diff_avg)),
+        ("home_rest_days", int(|r| r.home_rest_days)),
+        ("away_rest_days", int(|r| r.away_rest_days)),
+        ("result", text(|r| format!("{:?}", r.result))),
+        ("home_goals", int(|r| Some(r.home_goals as i32))),
+        ("away_goals", int(|r| Some(r.away_goals as i32))),
+    ]
+}
+
+fn write_features_parquet(features: &[MatchFeatures], output: &Path) -> Result<(), DatasetError> {
+    let parquet_error = |source: parquet::errors::ParquetError| DatasetError::Parquet {
+        path: output.to_path_buf(),
+        source,
+    };
+
+    let columns = feature_columns(features);
+
+    let message = format!(
+        "message match_features {{ {} }}",
+        columns
+            .iter()
+            .map(|(name, column)| column.schema(name))
+            .collect::<Vec<String>>()
+            .join(" ")
+    );
+    let schema = Arc::new(parse_message_type(&message).map_err(parquet_error)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::UNCOMPRESSED)
+            .build(),
+    );
+
+    let file = File::create(output).map_err(|e| parquet_error(e.into()))?;
+    let mut writer = SerializedFileWriter::new(file, schema, properties).map_err(parquet_error)?;
+    let mut row_group = writer.next_row_group().map_err(parquet_error)?;
+
+    for (_, column) in &columns {
+        let mut column_writer = row_group
+            .next_column()
+            .map_err(parquet_error)?
+            .expect("schema and columns have the same length");
+
+        match column {
+            Column::Int(values) => {
+                let present: Vec<i32> = values.iter().flatten().copied().collect();
+                let levels = definition_levels(values);
+                column_writer
+                    .typed::<Int32Type>()
+                    .write_batch(&present, levels.as_deref(), None)
+                    .map_err(parquet_error)?;
+            }
+            Column::Double(values) => {
+                let present: Vec<f64> = values.iter().flatten().copied().collect();
+                let levels = definition_levels(values);
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&present, levels.as_deref(), None)
+                    .map_err(parquet_error)?;
+            }
+            Column::Text(values) => {
+                let values: Vec<ByteArray> =
+                    values.iter().map(|v| ByteArray::from(v.as_str())).collect();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)
+                    .map_err(parquet_error)?;
+            }
+            Column::Date(values) => {
+                column_writer
+                    .typed::<Int32Type>()
+                    .write_batch(values, None, None)
+                    .map_err(parquet_error)?;
+            }
+        }
+
+        column_writer.close().map_err(parquet_error)?;
+    }
+
+    row_group.close().map_err(parquet_error)?;
+    writer.close().map_err(parquet_error)?;
+
+    Ok(())
+}
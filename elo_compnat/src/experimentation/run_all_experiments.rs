@@ -1,106 +1,254 @@
+use rayon::prelude::*;
+
 use crate::{elo::{
+    rating_system::{RatingSystem, RatingTable},
     train::{
-        construct_elo_table_for_time_series,
+        construct_elo_table_for_seasons,
+        start_next_season,
         EloTable,
-        print_elo_table
+        print_elo_table,
+        rating_values,
+        validate_time_series_interval,
     },
-    util::{season, league::LeagueTable},
-}, util::math::{mean, transpose_matrix}};
+    util::season,
+}, util::random::derive_seed};
 
 use crate::{util::game::Game};
 
-use super::{run_config::{RunConfig, RunHyperparameters}};
+use super::{
+    bootstrap::ExperimentResult,
+    evaluation::{evaluate_predictions, FitnessObjective},
+    goal_model::GoalModel,
+    run_config::RunHyperparameters,
+    standings_metrics::SeasonObjective,
+};
 
-/// Performs the backtesting for t years and experiments with the elo metric for n-t remaining years.
+/// Performs the backtesting for t years and experiments with the rating system for n-t remaining years.
 /// Note that the next year is based on the real year, not the simulated one.
-pub fn run_experiments(
+///
+/// The random variations run in parallel. Every (variation, season) pair draws from its own stream,
+/// derived from `master_seed`, so the result is the same whatever the number of threads.
+/// `objective` is the error measured on each simulated season. Returns the mean error of each season, see
+/// [`run_experiments_detailed`] for the error of every variation. Fails when the hyperparameters don't fit
+/// the data, see [`PreparedExperiment::new`]
+pub fn run_experiments<S: RatingSystem>(
     all_games: &[Game],
-    run_config: &RunConfig,
+    rating_system: &S,
     experiment_config: &RunHyperparameters,
     display: bool,
-) -> Vec<f64> {
-
-    // Setup: Configure the required structs
-    let elo_config = run_config.clone();
-    // Pre processing: split the games into seasons, determine start and end years of backtesting
-    let end_year = experiment_config.starting_year + experiment_config.backtest_years;
-
-    let seasons_map = season::construct_seasons(all_games);
-
-    // 1st stage: do the elo training with the desired years of data. this is the backtesting
-    let elo_table_at_start = construct_elo_table_for_time_series(
-        all_games,
-        Some(&elo_config),
-        &experiment_config,
-        experiment_config.starting_year,
-        end_year,
-    );
-
-    //Sanity check: assert the correct range. Later this will be refactored outside the experiment run itself
-    let min_year = all_games.iter().map(|game| game.year).min().unwrap();
-    let max_year = all_games.iter().map(|game| game.year).max().unwrap();
-    assert!(experiment_config.starting_year >= min_year);
-    assert!(end_year < max_year);
-
-    // 2nd stage: simulate the seasons after the training period, until the end of the dataset
-    let start_t = end_year + 1;
-    let end_t = *seasons_map.keys().max().unwrap();
-
-    let mut errors_for_each_run: Vec<Vec<f64>> = Vec::new();
-    //let mut draw_frequency: Vec<Vec<f64>> = Vec::new();
-    let mut elo_table = elo_table_at_start.clone();
-    for i in 0..experiment_config.random_variations {
-        // we set the seed for the random number generator at the simulation function, with i as its seed
-        
-        let mut errors_per_season: Vec<f64> = Vec::new();
-        let mut last_season_config = elo_config.clone();
-
-        elo_table = elo_table_at_start.clone();
-        //let mut tie_frequency = vec![];
-
-        for s_year in start_t..=end_t {
-            //TODO: perform n random variations, with unique seeds
-
-            let season = seasons_map.get(&s_year).unwrap();
-            let season_games = &season.matches;
-            let (rmse, simulated_elo, real_elo, season_config) = super::run_single_experiment::run_season_experiment(
-                season_games,
-                &elo_table,
-                &last_season_config,
-                experiment_config,
-                i as u32,
-            );
-
-            last_season_config = season_config;
-            //tie_frequency.push(last_season_config.tie_frequency);
-
-            elo_table = real_elo;
-
-            errors_per_season.push(rmse);
+    objective: SeasonObjective,
+    master_seed: u64,
+) -> Result<Vec<f64>, String> {
+    run_experiments_detailed(all_games, rating_system, experiment_config, display, objective, master_seed)
+        .map(|result| result.mean_errors())
+}
+
+/// Same as [`run_experiments`], keeping the errors of each variation so their spread can be measured
+pub fn run_experiments_detailed<S: RatingSystem>(
+    all_games: &[Game],
+    rating_system: &S,
+    experiment_config: &RunHyperparameters,
+    display: bool,
+    objective: SeasonObjective,
+    master_seed: u64,
+) -> Result<ExperimentResult, String> {
+    let experiment = PreparedExperiment::new(all_games, experiment_config)?;
+    Ok(experiment.run(rating_system, experiment_config, display, objective, master_seed))
+}
+
+/// The part of the backtest that only depends on the matches and the hyperparameters: the games of the
+/// divisions in use split into seasons, and the goal model of each simulated season. Build it once to run
+/// any number of rating systems on the same data
+pub struct PreparedExperiment {
+    games: Vec<Game>,
+    seasons_map: season::SeasonMap,
+    end_year: u16,
+    /// First and last simulated seasons
+    start_t: u16,
+    end_t: u16,
+    goal_models: Vec<GoalModel>,
+}
+
+impl PreparedExperiment {
+    /// Fails when the training period doesn't start inside the data, leaves no season to simulate or a
+    /// season in between is missing
+    pub fn new(all_games: &[Game], experiment_config: &RunHyperparameters) -> Result<Self, String> {
+        // divisões fora de leagues_to_use não participam nem do treino nem da simulação
+        let components = experiment_config.components();
+        let games: Vec<Game> = all_games
+            .iter()
+            .filter(|game| components.uses_division(game.division))
+            .cloned()
+            .collect();
+
+        // Pre processing: split the games into seasons, determine start and end years of backtesting
+        let end_year = experiment_config.starting_year + experiment_config.backtest_years;
+
+        let seasons_map = season::construct_seasons(&games);
+
+        //Sanity check: the training starts inside the data and leaves at least one season to simulate
+        let (Some(min_year), Some(max_year)) = (
+            games.iter().map(|game| game.year).min(),
+            games.iter().map(|game| game.year).max(),
+        ) else {
+            return Err(String::from("No match in the divisions in use"));
+        };
+        if experiment_config.starting_year < min_year || end_year >= max_year {
+            return Err(format!(
+                "Training from {} to {} doesn't fit the data, which goes from {} to {} and needs a season left to simulate",
+                experiment_config.starting_year, end_year, min_year, max_year
+            ));
         }
-        errors_for_each_run.push(errors_per_season);
-        //draw_frequency.push(tie_frequency);
 
-    }
+        let start_t = end_year + 1;
+        let end_t = *seasons_map.keys().max().unwrap();
+        // uma temporada faltando no meio quebraria o treino ou a simulação
+        validate_time_series_interval(&games, experiment_config.starting_year, end_t)?;
 
-    if display {
-        println!("Elo table at end:");
-        print_elo_table(&elo_table, true);
+        // o modelo de gols de cada temporada só vê as partidas anteriores a ela
+        let goal_models: Vec<GoalModel> = (start_t..=end_t)
+            .map(|s_year| {
+                let past_games: Vec<Game> = games
+                    .iter()
+                    .filter(|game| game.year >= experiment_config.starting_year && game.year < s_year)
+                    .cloned()
+                    .collect();
+                GoalModel::from_games(&past_games)
+            })
+            .collect();
 
-        let last_season = seasons_map.get(&end_t).unwrap();
-        let tabela = LeagueTable::new(&last_season.matches, last_season.league.as_str(), &1);
+        Ok(PreparedExperiment {
+            games,
+            seasons_map,
+            end_year,
+            start_t,
+            end_t,
+            goal_models,
+        })
+    }
+
+    /// Matches of the divisions in use
+    pub fn games(&self) -> &[Game] {
+        &self.games
+    }
 
-        println!("Final elos with standings:");
-        tabela.print_final_table_with_elo(&elo_table);
+    /// Error of each simulated season under `objective`: the mean over the variations of the backtest for
+    /// the season objectives, the mean score of the match probabilities for the scoring rules
+    pub fn errors<S: RatingSystem>(
+        &self,
+        rating_system: &S,
+        experiment_config: &RunHyperparameters,
+        objective: FitnessObjective,
+        seed: u64,
+    ) -> Vec<f64> {
+        match objective {
+            FitnessObjective::Season(objective) => self
+                .run(rating_system, experiment_config, false, objective, seed)
+                .mean_errors(),
+            FitnessObjective::Scoring(rule) => {
+                evaluate_predictions(rating_system, &self.games, experiment_config).season_scores(rule)
+            }
+        }
     }
 
-    let season_errors = transpose_matrix(errors_for_each_run);
+    /// Runs the backtest of `rating_system`. `experiment_config` must be the one the data was prepared with
+    pub fn run<S: RatingSystem>(
+        &self,
+        rating_system: &S,
+        experiment_config: &RunHyperparameters,
+        display: bool,
+        objective: SeasonObjective,
+        master_seed: u64,
+    ) -> ExperimentResult {
+        let PreparedExperiment {
+            seasons_map,
+            end_year,
+            start_t,
+            end_t,
+            goal_models,
+            ..
+        } = self;
+        let (start_t, end_t) = (*start_t, *end_t);
+
+        if display {
+            println!("Rating system: {}", rating_system.name());
+            println!("Active components: {}", experiment_config.components());
+        }
+
+        // 1st stage: do the elo training with the desired years of data. this is the backtesting
+        let elo_table_at_start = construct_elo_table_for_seasons(
+            rating_system,
+            seasons_map,
+            experiment_config,
+            experiment_config.starting_year,
+            *end_year,
+        );
+
+        // 2nd stage: simulate the seasons after the training period, until the end of the dataset
+        // cada variação roda numa thread, e cada par (variação, temporada) tem sua própria semente
+        let runs: Vec<(Vec<f64>, RatingTable<S::Rating>)> = (0..experiment_config.random_variations)
+            .into_par_iter()
+            .map(|i| {
+                let mut errors_per_season: Vec<f64> = Vec::new();
+                let mut last_season_system = rating_system.clone();
+
+                let mut elo_table = elo_table_at_start.clone();
+
+                for (s_year, goal_model) in (start_t..=end_t).zip(goal_models) {
+                    let season = seasons_map.get(&s_year).unwrap();
+                    if let Some(previous) = seasons_map.get(&(s_year - 1)) {
+                        elo_table = start_next_season(&last_season_system, elo_table, previous, season, experiment_config);
+                    }
+                    let season_games = &season.matches;
+                    let (error, _simulated_elo, real_elo, season_system) = super::run_single_experiment::run_season_experiment(
+                        season_games,
+                        &elo_table,
+                        &last_season_system,
+                        goal_model,
+                        experiment_config,
+                        objective,
+                        derive_seed(master_seed, &[i as u64, s_year as u64]),
+                    );
+
+                    last_season_system = season_system;
+
+                    elo_table = real_elo;
+
+                    errors_per_season.push(error);
+                }
 
-    let mean_errors_for_each_season = season_errors
-        .iter()
-        .map(|errors| mean(errors).unwrap_or_else(|| {1000.0}))
-        .collect::<Vec<f64>>();
-    mean_errors_for_each_season
+                (errors_per_season, elo_table)
+            })
+            .collect();
+
+        let elo_table = runs
+            .last()
+            .map(|(_, table)| table.clone())
+            .unwrap_or(elo_table_at_start);
+        let errors_for_each_run: Vec<Vec<f64>> = runs.into_iter().map(|(errors, _)| errors).collect();
+
+        if display {
+            let elo_table = rating_values(rating_system, &elo_table);
+
+            println!("Elo table at end:");
+            print_elo_table(&elo_table, true);
+
+            let last_season = seasons_map.get(&end_t).unwrap();
+
+            println!("Final elos with standings:");
+            for division in last_season.division_numbers() {
+                if let Some(tabela) = last_season.division_table(division) {
+                    tabela.print_final_table_with_elo(&elo_table);
+                }
+            }
+        }
+
+        ExperimentResult {
+            first_season: start_t,
+            components: experiment_config.components(),
+            errors_by_variation: errors_for_each_run,
+        }
+    }
 }
 
 
@@ -118,3 +266,69 @@ fn changed_elos(elo_table: &EloTable, elo_table_after_season: &EloTable) -> u32
 
     changed_elos
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::experimentation::run_config::{CustomElo, RunConfig};
+
+    /// Turno e returno entre quatro times, com placares que variam com o ano e a rodada
+    fn season(year: u16) -> Vec<Game> {
+        let teams = ["A", "B", "C", "D"];
+        let mut games = Vec::new();
+        for home in teams {
+            for away in teams.iter().filter(|&&away| away != home) {
+                let round = games.len() as u16 + 1;
+                let date = NaiveDate::from_ymd_opt(year as i32, 5, 1).unwrap() + chrono::Duration::days(7 * round as i64);
+                let score = ((round * 7 + year) % 4, (round * 5) % 3);
+                games.push(Game {
+                    week: round as f32,
+                    ..Game::fixture(date, home, away, 1).with_score(score.0, score.1)
+                });
+            }
+        }
+        games
+    }
+
+    fn seasons(years: impl IntoIterator<Item = u16>) -> Vec<Game> {
+        years.into_iter().flat_map(season).collect()
+    }
+
+    fn hyperparameters() -> RunHyperparameters {
+        // treino em 2015 e 2016, 8 variações por temporada simulada
+        RunHyperparameters::from_python_list(vec![1000, 2015, 1, 8, 1, 1, 0, 1]).unwrap()
+    }
+
+    #[test]
+    fn a_missing_season_is_an_error() {
+        let games = seasons([2015, 2016, 2018, 2019]);
+
+        assert!(PreparedExperiment::new(&games, &hyperparameters()).is_err());
+        assert!(PreparedExperiment::new(&seasons(2015..=2019), &hyperparameters()).is_ok());
+    }
+
+    #[test]
+    fn the_errors_do_not_depend_on_the_number_of_threads() {
+        let games = seasons(2015..=2019);
+        let hyperparameters = hyperparameters();
+        let custom_elo = CustomElo::new(RunConfig::default(), hyperparameters.components());
+
+        let run_with_threads = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                run_experiments_detailed(&games, &custom_elo, &hyperparameters, false, SeasonObjective::PointsRmse, 7)
+                    .unwrap()
+            })
+        };
+
+        let single = run_with_threads(1);
+        let parallel = run_with_threads(8);
+        assert_eq!(single.errors_by_variation.len(), 8);
+        assert_eq!(single.errors_by_variation[0].len(), 3);
+        assert_eq!(single.errors_by_variation, parallel.errors_by_variation);
+        // as variações não repetem a mesma simulação
+        assert!(single.errors_by_variation.iter().any(|errors| errors != &single.errors_by_variation[0]));
+    }
+}
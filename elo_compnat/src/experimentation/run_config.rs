@@ -1,8 +1,8 @@
-use crate::util::game::{GameResult};
+use crate::elo::util::season::SeasonKind;
+use crate::util::game::{Game, GameResult};
 use pyo3::exceptions::PyValueError;
 use pyo3::{prelude::*, types::PyDict};
 use serde::{Deserialize, Serialize};
-use std::f64::EPSILON;
 
 /// this struct holds the necessary parameters for configuring the runtime of our experiments
 /// It is also used as the genotype, as it holds all the experimentation parameters
@@ -15,20 +15,39 @@ pub struct RunConfig {
     pub home_field_advantage_weight: f64,
     pub market_value_weight: f64,
     pub tie_frequency: f64,
+    /// Fraction of the distance to its division mean that a rating loses between seasons (0 keeps it, 1
+    /// resets every team to the mean)
+    pub season_regression: f64,
     pub w_division: Vec<f64>,
 }
 
+impl RunConfig {
+    /// Every field as bits, so equality and hashing agree (NaN equals itself, 0.0 and -0.0 differ)
+    fn bits(&self) -> ([u64; 7], Vec<u64>) {
+        (
+            [
+                self.k_factor.to_bits(),
+                self.gamma.to_bits(),
+                self.home_advantage.to_bits(),
+                self.home_field_advantage_weight.to_bits(),
+                self.market_value_weight.to_bits(),
+                self.tie_frequency.to_bits(),
+                self.season_regression.to_bits(),
+            ],
+            self.w_division.iter().map(|w| w.to_bits()).collect(),
+        )
+    }
+}
+
 impl PartialEq for RunConfig {
     fn eq(&self, other: &Self) -> bool {
-        (self.k_factor - other.k_factor).abs() < EPSILON
+        self.bits() == other.bits()
     }
 }
 
 impl std::hash::Hash for RunConfig {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        let rounded = (self.k_factor * 1000.0).round() / 1000.0;
-        let s = format!("{:.3}", rounded);
-        s.hash(state);
+        self.bits().hash(state);
     }
 }
 
@@ -43,37 +62,59 @@ impl Default for RunConfig {
             home_field_advantage_weight: 0.075,
             market_value_weight: 1.0,
             tie_frequency: 0.5,
+            season_regression: 0.0,
             w_division: vec![1.0, 1.0],
         }
     }
 }
 
 impl RunConfig {
-    pub fn from_python_list(params: Vec<f64>) -> Self {
+    /// Reads `[k_factor, gamma, home_advantage, home_field_advantage_weight, market_value_weight,
+    /// tie_frequency, w_division...]`. `season_regression` is not part of the list and starts at 0
+    pub fn from_python_list(params: Vec<f64>) -> Result<Self, String> {
         if params.len() < 7 {
-            panic!(
-                "The input list should have at least 6 elements.",
-            );
+            return Err(String::from("The input list should have at least 7 elements."));
         }
 
-        let k_factor = params[0];
-        let gamma = params[1];
-        let home_advantage = params[2];
-        let home_field_advantage_weight = params[3];
-        let market_value_weight = params[4];
-        let tie_frequency = params[5];
-        let w_division: Vec<f64> = params[6..].to_vec();
+        Ok(RunConfig {
+            k_factor: params[0],
+            gamma: params[1],
+            home_advantage: params[2],
+            home_field_advantage_weight: params[3],
+            market_value_weight: params[4],
+            tie_frequency: params[5],
+            season_regression: 0.0,
+            w_division: params[6..].to_vec(),
+        })
+    }
 
-        RunConfig::new(
-            k_factor,
-            gamma,
-            home_advantage,
-            home_field_advantage_weight,
-            market_value_weight,
-            tie_frequency,
-            w_division,
-        )
+    /// Fails when a gene is outside its range
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.season_regression) {
+            return Err(format!(
+                "season_regression must be between 0 and 1, got {}",
+                self.season_regression
+            ));
+        }
+        Ok(())
+    }
 
+    /// Fails when a division of `games` in use has no weight in `w_division`
+    pub fn check_divisions(&self, games: &[Game], components: ActiveComponents) -> Result<(), String> {
+        let highest = games
+            .iter()
+            .map(|game| game.division)
+            .filter(|&division| components.uses_division(division))
+            .max();
+
+        match highest {
+            Some(division) if division as usize > self.w_division.len() => Err(format!(
+                "Division {} is in use, but w_division only has {} entries",
+                division,
+                self.w_division.len()
+            )),
+            _ => Ok(()),
+        }
     }
 }
 
@@ -81,6 +122,8 @@ impl RunConfig {
 #[pymethods]
 impl RunConfig {
     #[new]
+    #[pyo3(signature = (k_factor, gamma, home_advantage, home_field_advantage_weight, market_value_weight, tie_frequency, w_division, season_regression=0.0))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         k_factor: f64,
         gamma: f64,
@@ -89,16 +132,20 @@ impl RunConfig {
         market_value_weight: f64,
         tie_frequency: f64,
         w_division: Vec<f64>,
-    ) -> RunConfig {
-        RunConfig {
+        season_regression: f64,
+    ) -> PyResult<RunConfig> {
+        let config = RunConfig {
             k_factor,
             gamma,
             home_advantage,
             home_field_advantage_weight,
             market_value_weight,
             tie_frequency,
+            season_regression,
             w_division,
-        }
+        };
+        config.validate().map_err(PyValueError::new_err)?;
+        Ok(config)
     }
 
     #[getter]
@@ -114,6 +161,7 @@ impl RunConfig {
             )?;
             dict.set_item("market_value_weight", self.market_value_weight)?;
             dict.set_item("tie_frequency", self.tie_frequency)?;
+            dict.set_item("season_regression", self.season_regression)?;
             dict.set_item("w_division", self.w_division.clone())?;
 
             Ok(dict.to_object(py))
@@ -131,9 +179,14 @@ impl RunConfig {
             .extract()?;
         let market_value_weight = dict.get_item("market_value_weight").unwrap().extract()?;
         let tie_frequency = dict.get_item("tie_frequency").unwrap().extract()?;
+        // dicts salvos antes desse gene não têm a chave
+        let season_regression = match dict.get_item("season_regression") {
+            Some(value) => value.extract()?,
+            None => 0.0,
+        };
         let w_division = dict.get_item("w_division").unwrap().extract()?;
 
-        Ok(RunConfig::new(
+        RunConfig::new(
             k_factor,
             gamma,
             home_advantage,
@@ -141,7 +194,8 @@ impl RunConfig {
             market_value_weight,
             tie_frequency,
             w_division,
-        ))
+            season_regression,
+        )
     }
 
     fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
@@ -155,35 +209,20 @@ impl RunConfig {
         )?;
         dict.set_item("market_value_weight", self.market_value_weight)?;
         dict.set_item("tie_frequency", self.tie_frequency)?;
+        dict.set_item("season_regression", self.season_regression)?;
         dict.set_item("w_division", self.w_division.clone())?;
 
         Ok(dict.into())
     }
     #[staticmethod]
-    fn from_list(params: Vec<f64>) -> PyResult<Self> {
-        if params.len() < 7 {
-            return Err(PyValueError::new_err(
-                "The input list should have at least 6 elements.",
-            ));
-        }
-
-        let k_factor = params[0];
-        let gamma = params[1];
-        let home_advantage = params[2];
-        let home_field_advantage_weight = params[3];
-        let market_value_weight = params[4];
-        let tie_frequency = params[5];
-        let w_division: Vec<f64> = params[6..].to_vec();
-
-        Ok(RunConfig::new(
-            k_factor,
-            gamma,
-            home_advantage,
-            home_field_advantage_weight,
-            market_value_weight,
-            tie_frequency,
-            w_division,
-        ))
+    #[pyo3(signature = (params, season_regression=0.0))]
+    fn from_list(params: Vec<f64>, season_regression: f64) -> PyResult<Self> {
+        let config = RunConfig {
+            season_regression,
+            ..RunConfig::from_python_list(params).map_err(PyValueError::new_err)?
+        };
+        config.validate().map_err(PyValueError::new_err)?;
+        Ok(config)
     }
 }
 
@@ -191,12 +230,15 @@ impl RunConfig {
 #[pyclass(dict, frozen)]
 pub struct RunHyperparameters {
     pub starting_elo: u16,
+    /// First season used for training, identified by the year it starts in (`1999` is 1999-2000 in Europe)
     pub starting_year: u16,
+    /// Number of seasons after `starting_year` used for training before the simulations start
     pub backtest_years: u16,
     pub random_variations: u16,
     pub use_goals_diff: bool,
     pub use_home_advantage: bool,
     pub use_market_values: bool,
+    /// Number of divisions used, counting from the top flight. 0 uses every division in the dataset
     pub leagues_to_use: u16,
 }
 
@@ -226,26 +268,37 @@ impl Default for RunHyperparameters {
 }
 
 impl RunHyperparameters {
-    pub fn print_errors_by_year(&self, errors: &[f64]) {
+    pub fn print_errors_by_year(&self, errors: &[f64], season_kind: SeasonKind) {
         let base_year = self.backtest_years + self.starting_year + 1;
 
-        let horizontal_line = format!("{:-<1$}", "", 19);
+        let label_width = season_kind.label(base_year).len();
+        let horizontal_line = format!("{:-<1$}", "", 15 + label_width);
         println!("{}", &horizontal_line);
-        println!("|Errors by year:  |");
+        println!("|{:<1$}|", "Errors by year:", 13 + label_width);
 
         for (i, error) in errors.iter().enumerate() {
-            println!("|{}  :  {:.2}   |", base_year + i as u16, error);
+            println!("|{}  :  {:.2}   |", season_kind.label(base_year + i as u16), error);
         }
         println!("{}", &horizontal_line);
     }
     
-    pub fn from_python_list(params: Vec<u16>) -> Self {
+    pub fn components(&self) -> ActiveComponents {
+        ActiveComponents {
+            goals_diff: self.use_goals_diff,
+            home_advantage: self.use_home_advantage,
+            market_values: self.use_market_values,
+            divisions: self.leagues_to_use,
+        }
+    }
+
+    /// Reads `[starting_elo, starting_year, backtest_years, random_variations, use_goals_diff,
+    /// use_home_advantage, use_market_values, leagues_to_use]`, the flags as 0 or 1
+    pub fn from_python_list(params: Vec<u16>) -> Result<Self, String> {
         if params.len() != 8 {
-            panic!(
-                "The input list should have exactly 8 elements.",
-            );
+            return Err(String::from("The input list should have exactly 8 elements."));
         }
-        RunHyperparameters::new(
+
+        Ok(RunHyperparameters::new(
             params[0],
             params[1],
             params[2],
@@ -254,12 +307,13 @@ impl RunHyperparameters {
             params[5] != 0,
             params[6] != 0,
             params[7],
-        )
+        ))
     }
 }
 #[pymethods]
 impl RunHyperparameters {
     #[new]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         starting_elo: u16,
         starting_year: u16,
@@ -338,6 +392,11 @@ impl RunHyperparameters {
             leagues_to_use,
         ))
     }
+    /// Components of the model that runs with these hyperparameters will use
+    fn active_components(&self) -> ActiveComponents {
+        self.components()
+    }
+
     fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
         let dict = PyDict::new(py);
         dict.set_item("starting_elo", self.starting_elo)?;
@@ -352,6 +411,64 @@ impl RunHyperparameters {
     }
 }
 
+/// Which parts of the rating model are switched on for a run, taken from the `use_*` flags of
+/// [`RunHyperparameters`]. Turning a component off removes its term from the update, not just its weight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[pyclass(frozen)]
+pub struct ActiveComponents {
+    #[pyo3(get)]
+    pub goals_diff: bool,
+    #[pyo3(get)]
+    pub home_advantage: bool,
+    #[pyo3(get)]
+    pub market_values: bool,
+    /// Divisions taken into account, counting from the top flight. 0 means all of them
+    #[pyo3(get)]
+    pub divisions: u16,
+}
+
+impl Default for ActiveComponents {
+    fn default() -> Self {
+        ActiveComponents {
+            goals_diff: true,
+            home_advantage: true,
+            market_values: true,
+            divisions: 0,
+        }
+    }
+}
+
+impl ActiveComponents {
+    pub fn uses_division(&self, division: u8) -> bool {
+        self.divisions == 0 || (division as u16) <= self.divisions
+    }
+}
+
+impl std::fmt::Display for ActiveComponents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let on_off = |active: bool| if active { "on" } else { "off" };
+        let divisions = match self.divisions {
+            0 => String::from("all"),
+            n => n.to_string(),
+        };
+        write!(
+            f,
+            "goals_diff: {}, home_advantage: {}, market_values: {}, divisions: {}",
+            on_off(self.goals_diff),
+            on_off(self.home_advantage),
+            on_off(self.market_values),
+            divisions
+        )
+    }
+}
+
+#[pymethods]
+impl ActiveComponents {
+    fn __repr__(&self) -> String {
+        format!("ActiveComponents({})", self)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[pyclass]
 pub struct CustomRating {
@@ -376,9 +493,31 @@ impl Default for CustomRating {
 #[pyclass]
 pub struct CustomElo {
     pub config: RunConfig,
+    pub components: ActiveComponents,
 }
 
 impl CustomElo {
+    pub fn new(config: RunConfig, components: ActiveComponents) -> Self {
+        CustomElo { config, components }
+    }
+
+    /// Same as [`expected_score`], leaving the home advantage out when it is switched off
+    pub fn expected_score(
+        &self,
+        player_one: &CustomRating,
+        player_two: &CustomRating,
+    ) -> (f64, f64, f64) {
+        if self.components.home_advantage {
+            expected_score(player_one, player_two, &self.config)
+        } else {
+            let config = RunConfig {
+                home_advantage: 0.0,
+                ..self.config.clone()
+            };
+            expected_score(player_one, player_two, &config)
+        }
+    }
+
     pub fn rate(
         &self,
         player_one: &CustomRating,
@@ -395,8 +534,7 @@ impl CustomElo {
             w_division,
             ..
         } = self.config.clone();
-        let (_, one_expected, two_expected) =
-            expected_score(player_one, player_two, &self.config);
+        let (_, one_expected, two_expected) = self.expected_score(player_one, player_two);
         let real_player_one_score: f64 = match outcome {
             GameResult::H => 1.0,
             GameResult::D => 0.5,
@@ -411,10 +549,22 @@ impl CustomElo {
             _ => 1.0
         };
 
+        // componentes desligados saem da conta, em vez de só zerar o peso
+        let market_value_factor = if self.components.market_values {
+            (1.0 + absolute_market_value_diff).powf(market_value_weight)
+        } else {
+            1.0
+        };
+        let goals_diff_factor = if self.components.goals_diff {
+            (1.0 + normalizazed_goal_diff).powf(gamma)
+        } else {
+            1.0
+        };
+
         let change_p1 = k_factor
             * w_division[division - 1]
-            * ((1.0 + absolute_market_value_diff).powf(market_value_weight))
-            * ((1.0 + normalizazed_goal_diff).powf(gamma))
+            * market_value_factor
+            * goals_diff_factor
             * (real_player_one_score - one_expected);
 
 
@@ -429,8 +579,8 @@ impl CustomElo {
 
         let change_p2 = k_factor
             * w_division[division - 1]
-            * ((1.0 + absolute_market_value_diff).powf(market_value_weight))
-            * ((1.0 + normalizazed_goal_diff).powf(gamma))
+            * market_value_factor
+            * goals_diff_factor
             * (real_player_two_score - two_expected);
 
         let mut player_two_new_rate: f64 = player_two.rating + change_p2;
@@ -469,7 +619,7 @@ pub fn expected_score(
 
     let basis: f64 = 10.0;
     let exponent = (player_one.rating + home_advantage - player_two.rating) / 400.0;
-    let denominator = basis.powf(exponent) + basis.powf(-1.0 * exponent) + kappa;
+    let denominator = basis.powf(exponent) + basis.powf(-exponent) + kappa;
 
     if denominator.is_infinite() {
         if denominator.is_sign_positive() {
@@ -481,7 +631,7 @@ pub fn expected_score(
         return (1.0, 0.0, 0.0);
     }
     let exp_one = basis.powf(exponent) / denominator;
-    let exp_two = basis.powf(-1.0 * exponent) / denominator;
+    let exp_two = basis.powf(-exponent) / denominator;
     let exp_tie = 1.0 - exp_one - exp_two;
 
     let sum = exp_one + exp_two + exp_tie;
@@ -496,3 +646,65 @@ pub fn expected_score(
     probs
 
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn game_in_division(division: u8) -> Game {
+        Game::fixture(NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(), "A", "B", division)
+    }
+
+    #[test]
+    fn the_list_keeps_every_weight_after_tie_frequency() {
+        let config = RunConfig::from_python_list(vec![40.0, 1.0, 1.0, 0.0075, 1.0, 0.5, 40.0, 20.0]).unwrap();
+
+        assert_eq!(config.tie_frequency, 0.5);
+        assert_eq!(config.w_division, vec![40.0, 20.0]);
+        assert_eq!(config.season_regression, 0.0);
+        assert!(RunConfig::from_python_list(vec![40.0, 1.0, 1.0, 0.0075, 1.0, 0.5]).is_err());
+    }
+
+    #[test]
+    fn the_hyperparameters_list_needs_all_eight_values() {
+        let hyperparameters = RunHyperparameters::from_python_list(vec![1000, 2003, 5, 0, 1, 0, 1, 2]).unwrap();
+
+        assert_eq!(hyperparameters.starting_year, 2003);
+        assert!(hyperparameters.use_goals_diff && !hyperparameters.use_home_advantage);
+        assert_eq!(hyperparameters.leagues_to_use, 2);
+        assert!(RunHyperparameters::from_python_list(vec![1000, 2003, 5, 0, 1, 0, 1]).is_err());
+        assert!(RunHyperparameters::from_python_list(vec![1000, 2003, 5, 0, 1, 0, 1, 2, 0]).is_err());
+    }
+
+    #[test]
+    fn season_regression_must_be_a_fraction() {
+        let mut config = RunConfig::default();
+        for valid in [0.0, 0.3, 1.0] {
+            config.season_regression = valid;
+            assert!(config.validate().is_ok());
+        }
+        for invalid in [-0.1, 1.5, f64::NAN] {
+            config.season_regression = invalid;
+            assert!(config.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn every_division_in_use_needs_a_weight() {
+        let config = RunConfig {
+            w_division: vec![40.0],
+            ..RunConfig::default()
+        };
+        let games = vec![game_in_division(1), game_in_division(2)];
+        let components = |divisions| ActiveComponents {
+            divisions,
+            ..ActiveComponents::default()
+        };
+
+        assert!(config.check_divisions(&games, components(1)).is_ok());
+        assert!(config.check_divisions(&games, components(0)).is_err());
+        assert!(config.check_divisions(&games, components(2)).is_err());
+    }
+}
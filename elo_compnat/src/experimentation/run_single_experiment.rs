@@ -1,40 +1,47 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::{
+    elo::rating_system::{RatingSystem, RatingTable},
     elo::train::{construct_elo_table_for_year, EloTable},
+    elo::util::season::SeasonSchedule,
     util::math::calculate_rmse,
 };
 
 use crate::{experimentation::simulate_season::simulate_season, util::game::Game};
 
 use super::{
-    run_config::{self, RunConfig},
+    goal_model::GoalModel,
+    run_config,
     season_standings::calculate_points,
+    standings_metrics::{standings_rank_error, SeasonObjective},
 };
 
-/// Given an starting elo and matches, simulates the season and compares it to the real season and the real match results, returning the elo difference table
-pub fn run_season_experiment(
-    season_games: &Vec<Game>,
-    starting_elo: &EloTable,
-    run_config: &run_config::RunConfig,
+/// Given an starting elo and matches, simulates the season and compares it to the real season and the real match results, returning the elo difference table.
+/// The error returned first is the one picked by `objective`
+#[allow(clippy::too_many_arguments)]
+pub fn run_season_experiment<S: RatingSystem>(
+    season_games: &[Game],
+    starting_elo: &RatingTable<S::Rating>,
+    rating_system: &S,
+    goal_model: &GoalModel,
     experiment_config: &run_config::RunHyperparameters,
-    random_seed: u32,
-) -> (f64, EloTable, EloTable, RunConfig) {
-    let (elo_simulated, simulated_matches, config_after_run) = simulate_season(
+    objective: SeasonObjective,
+    random_seed: u64,
+) -> (f64, RatingTable<S::Rating>, RatingTable<S::Rating>, S) {
+    let (elo_simulated, simulated_matches, system_after_run) = simulate_season(
         season_games,
         starting_elo,
-        run_config,
+        rating_system,
+        goal_model,
         experiment_config,
         random_seed,
     );
 
-    let elo_config = config_after_run.clone();
-
     //TODO: retornar as novas partidas nessa função para usar no python, mas nao vai ser pra usar aqui
     let real_elo = construct_elo_table_for_year(
+        &system_after_run,
         season_games,
         Some(starting_elo.clone()),
-        Some(&elo_config),
         experiment_config,
     );
 
@@ -47,8 +54,8 @@ pub fn run_season_experiment(
     // tabela_fake.print_final_table();
 
     //calculate distance between real and simulated elo
-    let elo_diff = compare_elo_tables(&real_elo, &elo_simulated);
-    let points_diff = compare_standing_tables(&season_games, &simulated_matches, false);
+    let elo_diff = compare_elo_tables(&system_after_run, &real_elo, &elo_simulated);
+    let points_diff = compare_standing_tables(season_games, &simulated_matches, false);
     /*
         println!("--------------- Elo diff ----------- \n");
         for (team, diff) in elo_diff.iter() {
@@ -56,22 +63,29 @@ pub fn run_season_experiment(
         }
     */
     //let games_count = changed_elos(starting_elo, &elo_simulated);
-    let games_count = count_unique_teams(&season_games);
-    let rmse_elo_mean = calculate_rmse(&elo_diff, Some(games_count));
-    let rmse_points = calculate_rmse(&points_diff, Some(games_count));
+    let games_count = count_unique_teams(season_games);
+    let _rmse_elo_mean = calculate_rmse(&elo_diff, Some(games_count));
+    let error = match objective {
+        SeasonObjective::PointsRmse => calculate_rmse(&points_diff, Some(games_count)),
+        rank_objective => standings_rank_error(rank_objective, season_games, &simulated_matches),
+    };
     //println!("RMSE with games: {}", rmse_correct_mean);
     //println!("RMSE: {}", rmse_all_teams);
 
-    (rmse_points, elo_simulated, real_elo, config_after_run)
+    (error, elo_simulated, real_elo, system_after_run)
 }
 
-fn compare_elo_tables(real_elo: &EloTable, simulated_elo: &EloTable) -> HashMap<String, f64> {
+fn compare_elo_tables<S: RatingSystem>(
+    rating_system: &S,
+    real_elo: &RatingTable<S::Rating>,
+    simulated_elo: &RatingTable<S::Rating>,
+) -> HashMap<String, f64> {
     let mut elo_diff: HashMap<String, f64> = HashMap::new();
 
     for (team, elo) in real_elo.iter() {
         let simulated_elo = simulated_elo.get(team);
         let diff = match simulated_elo {
-            Some(sim_elo) => elo.rating - sim_elo.rating,
+            Some(sim_elo) => rating_system.rating_value(elo) - rating_system.rating_value(sim_elo),
             None => {
                 println!("A zero appeared! team: {:?}", &team);
                 0.0
@@ -84,25 +98,30 @@ fn compare_elo_tables(real_elo: &EloTable, simulated_elo: &EloTable) -> HashMap<
 }
 
 /// Compares two given standing tables (what is displayed at the end of
-/// each soccer season, with points and position)
+/// each soccer season, with points and position). The playoffs are left out, as in
+/// [`standings_by_division`](super::standings_metrics::standings_by_division), so only the league matches count
 fn compare_standing_tables(real_games: &[Game], simulated_games: &[Game], show_standings: bool) -> HashMap<String, f64> {
+    // a simulação repete o calendário real, então os playoffs são os mesmos nas duas
+    let schedule = SeasonSchedule::new(real_games);
+    let league_games = |games: &[Game]| -> Vec<Game> {
+        games.iter().filter(|game| !schedule.is_playoff(game)).cloned().collect()
+    };
+    let (real_games, simulated_games) = (&league_games(real_games), &league_games(simulated_games));
+
     let mut elo_diff: HashMap<String, f64> = HashMap::new();
     let real_table = calculate_points(real_games);
     let simulated_table = calculate_points(simulated_games);
 
-    let filter_games_by_division = |games: &Vec<Game>, division: u8| {
+    let _filter_games_by_division = |games: &Vec<Game>, division: u8| {
         games.iter().filter(|game| game.division == division).cloned().collect::<Vec<Game>>()
     };
 
-    let print_standings_for_2_divisions = |games: &[Game], title: &str| {
+    let print_standings_by_division = |games: &[Game], title: &str| {
         // Separate games by division
-        let division1_games = games.iter().filter(|game| game.division == 1).cloned().collect::<Vec<Game>>();
-        let division2_games = games.iter().filter(|game| game.division == 2).cloned().collect::<Vec<Game>>();
-    
-        // Calculate points
-        let division1_table = calculate_points(&division1_games);
-        let division2_table = calculate_points(&division2_games);
-    
+        let mut divisions: Vec<u8> = games.iter().map(|game| game.division).collect();
+        divisions.sort_unstable();
+        divisions.dedup();
+
         // Closure to print the table
         let print_table = |table: &HashMap<String, i32>, name: &str| {
             println!("--------------- {}\n", name);
@@ -113,17 +132,19 @@ fn compare_standing_tables(real_games: &[Game], simulated_games: &[Game], show_s
             }
             println!("--------------- End of {}\n", name)
         };
-    
+
         // Print tables
         println!("============= {} =============  \n", title);
-        print_table(&division1_table, "Division 1");
-        print_table(&division2_table, "Division 2");
+        for division in divisions {
+            let division_games = games.iter().filter(|game| game.division == division).cloned().collect::<Vec<Game>>();
+            print_table(&calculate_points(&division_games), &format!("Division {}", division));
+        }
         println!("============= End of {} =============  \n", title);
     };
 
     if show_standings {
-        print_standings_for_2_divisions(real_games, "Real standings");
-        print_standings_for_2_divisions(simulated_games, "Simulated standings");
+        print_standings_by_division(real_games, "Real standings");
+        print_standings_by_division(simulated_games, "Simulated standings");
     }
     
 
@@ -169,3 +190,42 @@ fn changed_elos(elo_table: &EloTable, elo_table_after_season: &EloTable) -> u32
 
     changed_elos
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn game(week: f32, day: i64, home: &str, away: &str, division: u8) -> Game {
+        let date = NaiveDate::from_ymd_opt(2021, 8, 1).unwrap() + chrono::Duration::days(day);
+        Game {
+            week,
+            year: 2021,
+            ..Game::fixture(date, home, away, division)
+        }
+    }
+
+    #[test]
+    fn the_playoffs_stay_out_of_the_points_error() {
+        let mut real = vec![
+            game(1.0, 0, "A", "B", 1),
+            game(2.0, 7, "B", "A", 1).with_score(2, 0),
+            game(1.0, 0, "C", "D", 2),
+            game(2.0, 7, "D", "C", 2),
+        ];
+        let mut simulated = real.clone();
+        // playoff arquivado como rodada 1 da segunda, com vencedores diferentes no real e no simulado
+        real.push(game(1.0, 20, "B", "C", 2).with_score(0, 2));
+        simulated.push(game(1.0, 20, "B", "C", 2).with_score(3, 0));
+
+        let diff = compare_standing_tables(&real, &simulated, false);
+        assert_eq!(diff.len(), 4);
+        assert!(diff.values().all(|&points| points == 0.0), "{:?}", diff);
+
+        // o resultado de uma partida da liga continua contando
+        simulated[1] = simulated[1].clone().with_score(1, 1);
+        let diff = compare_standing_tables(&real, &simulated, false);
+        assert_eq!((diff["A"], diff["B"]), (-1.0, 2.0));
+    }
+}
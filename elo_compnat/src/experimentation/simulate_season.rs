@@ -1,21 +1,26 @@
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 
-use crate::elo::train::EloTable;
+use crate::elo::rating_system::{RatingSystem, RatingTable, SeasonStats};
 use crate::util::game::{Game, GameResult};
 
-use super::run_config::{expected_score, CustomElo, CustomRating, RunConfig, RunHyperparameters};
+use super::goal_model::GoalModel;
+use super::run_config::RunHyperparameters;
 
-pub fn simulate_season(
+/// Simulates a season with `rating_system`, returning the ratings after it, the simulated matches and
+/// the system adapted for the next season (see [`RatingSystem::after_season`]). Results are drawn from
+/// the rating system and scorelines from `goal_model`
+pub fn simulate_season<S: RatingSystem>(
     games: &[Game],
-    original_elos: &EloTable,
-    run_config: &RunConfig,
+    original_elos: &RatingTable<S::Rating>,
+    rating_system: &S,
+    goal_model: &GoalModel,
     experiment_config: &RunHyperparameters,
-    random_seed: u32,
-) -> (EloTable, Vec<Game>, RunConfig) {
+    random_seed: u64,
+) -> (RatingTable<S::Rating>, Vec<Game>, S) {
     // For each game, simulate the game and update the elo table accordingly. We will also update the games with the results for debugging purposes, so we can
     // print the estimated league table
-    // It's important to note that we use the games for the season only for estimation purposes, the real game outcome is not used in the simulation (maybe the goal difference)
+    // It's important to note that we use the games for the season only for estimation purposes, the real game outcome is not used in the simulation, goals included
 
     let mut acc_home_elo_variation: f64 = 0.0;
     let mut acc_away_elo_variation: f64 = 0.0;
@@ -25,7 +30,9 @@ pub fn simulate_season(
     // TODO: extrair a liga do game e retirar o peso w_i
     let mut simulated_games: Vec<Game> = games.to_vec();
     let mut starting_elos = original_elos.clone();
-    let mut rng = StdRng::seed_from_u64(random_seed as u64);
+    let mut rng = StdRng::seed_from_u64(random_seed);
+
+    let new_elo = rating_system.initial_rating(experiment_config.starting_elo.into());
 
     // loop over the games
     for (i, game) in games.iter().enumerate() {
@@ -33,25 +40,18 @@ pub fn simulate_season(
         let home = game.home.clone();
         let away = game.away.clone();
 
-        let liga = game.division as usize;
-        let _w_liga = run_config.w_division[liga - 1]; // TODO: extrair a liga do game e retirar o peso w_i
-
-
-        let mut new_elo = CustomRating::new();
-        new_elo.rating = experiment_config.starting_elo.into();
-
         let home_elo = match starting_elos.get(&home) {
-            Some(elo) => elo.clone(),
-            None => new_elo.clone(),
+            Some(elo) => *elo,
+            None => new_elo,
         };
 
         let away_elo = match starting_elos.get(&away) {
-            Some(elo) => elo.clone(),
-            None => new_elo.clone(),
+            Some(elo) => *elo,
+            None => new_elo,
         };
 
         // calculate expected scores
-        let (exp_tie, exp_home, _) = expected_score(&home_elo, &away_elo, run_config);
+        let (exp_tie, exp_home, _) = rating_system.expected_probabilities(&home_elo, &away_elo);
 
         let random_result: f64 = rng.gen();
 
@@ -62,13 +62,6 @@ pub fn simulate_season(
         assert!(tie || home_wins || away_wins, "Missing case!" );
 
         let mut simulated_game = game.clone();
-        
-        let absolute_goal_diff: f64 = ((game.home_score as i8) - (game.away_score as i8))
-            .abs()
-            .into();
-
-        let absolute_market_value_diff: f64 = (game.home_value - game.away_value)
-        .abs();
 
         // assign the result to the simulated game according to home team's perspective
         simulated_game.result = match (tie, home_wins, away_wins) {
@@ -78,33 +71,20 @@ pub fn simulate_season(
         };
 
         // checar se isso ta certo
-        match game.result {
-            GameResult::D => acc_tie_count += 1.0,
-            _ => (),
-        };
+        if let GameResult::D = game.result {
+            acc_tie_count += 1.0;
+        }
 
-        // hard coded value as we are not using the real game goal difference
-        (simulated_game.home_score, simulated_game.away_score) = match simulated_game.result {
-            GameResult::H => (1, 0),
-            GameResult::A => (0, 1),
-            GameResult::D => (1, 1),
-        };
+        // o placar é sorteado condicionado ao resultado, e alimenta tanto a tabela quanto o rating
+        let expected_home = exp_home + exp_tie / 2.0;
+        (simulated_game.home_score, simulated_game.away_score) =
+            goal_model.sample_score(&mut rng, expected_home, simulated_game.result);
 
-        let custom_elo = CustomElo {
-            config: run_config.clone(),
-        };
+        let (new_player_home, new_player_away) =
+            rating_system.rate(&home_elo, &away_elo, &simulated_game);
 
-        let (new_player_home, new_player_away) = custom_elo.rate(
-            &home_elo,
-            &away_elo,
-            simulated_game.result,
-            absolute_goal_diff,
-            absolute_market_value_diff,
-            game.division as usize
-        );
-
-        let home_diff = new_player_home.rating - home_elo.rating;
-        let away_diff = new_player_away.rating - away_elo.rating;
+        let home_diff = rating_system.rating_value(&new_player_home) - rating_system.rating_value(&home_elo);
+        let away_diff = rating_system.rating_value(&new_player_away) - rating_system.rating_value(&away_elo);
 
         acc_home_elo_variation += home_diff;
         acc_away_elo_variation += away_diff;
@@ -112,20 +92,19 @@ pub fn simulate_season(
         // update elos
         starting_elos.insert(home, new_player_home);
 
-        simulated_game.home_elo = Some(new_player_home.rating);
-        simulated_game.away_elo = Some(new_player_away.rating);
+        simulated_game.home_elo = Some(rating_system.rating_value(&new_player_home));
+        simulated_game.away_elo = Some(rating_system.rating_value(&new_player_away));
         starting_elos.insert(away, new_player_away);
 
         // update the ith game in the simulated_games vector with the simulated result
         simulated_games[i] = simulated_game;
     }
 
-    let mut config_copy = run_config.clone();
-
-    config_copy.tie_frequency = acc_tie_count / (games.len() as f64);
+    let next_system = rating_system.after_season(&SeasonStats {
+        draw_share: acc_tie_count / (games.len() as f64),
+        home_rating_variation: acc_home_elo_variation,
+        away_rating_variation: acc_away_elo_variation,
+    });
 
-    let delta_elo = acc_home_elo_variation - acc_away_elo_variation;
-    config_copy.home_advantage +=  config_copy.home_field_advantage_weight * delta_elo;
-    
-    (starting_elos, simulated_games.to_vec(), config_copy)
+    (starting_elos, simulated_games.to_vec(), next_system)
 }
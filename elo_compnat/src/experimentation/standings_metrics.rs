@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use crate::elo::util::league::LeagueTable;
+use crate::elo::util::season::SeasonSchedule;
+use crate::util::game::Game;
+
+/// `k` of the overlap objectives when the name doesn't give one
+pub const DEFAULT_OVERLAP_K: usize = 4;
+
+/// What [`super::run_single_experiment::run_season_experiment`] measures between the real season and the
+/// simulated one. All of them are errors, lower is better: the rank metrics are turned into `1 - metric`,
+/// so a perfect order scores 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SeasonObjective {
+    /// Error of the simulated points of each team in the league matches, see
+    /// [`crate::util::math::calculate_rmse`]
+    #[default]
+    PointsRmse,
+    /// `1 - ρ`, from 0 to 2
+    Spearman,
+    /// `1 - τ`, from 0 to 2
+    Kendall,
+    /// Share of the real top `k` missing from the simulated top `k`, from 0 to 1
+    TopOverlap(usize),
+    /// Same as `TopOverlap`, for the bottom `k`
+    BottomOverlap(usize),
+}
+
+impl SeasonObjective {
+    pub fn name(&self) -> String {
+        match self {
+            SeasonObjective::PointsRmse => String::from("rmse"),
+            SeasonObjective::Spearman => String::from("spearman"),
+            SeasonObjective::Kendall => String::from("kendall"),
+            SeasonObjective::TopOverlap(k) => format!("top{}", k),
+            SeasonObjective::BottomOverlap(k) => format!("bottom{}", k),
+        }
+    }
+
+    /// Error of one division, from its real and simulated standings. Not defined for `PointsRmse`,
+    /// which is computed over the whole season
+    fn rank_error(&self, real: &[&str], simulated: &[&str]) -> f64 {
+        match self {
+            SeasonObjective::PointsRmse => unreachable!("the points rmse is not a rank metric"),
+            SeasonObjective::Spearman => 1.0 - spearman_rho(real, simulated),
+            SeasonObjective::Kendall => 1.0 - kendall_tau(real, simulated),
+            SeasonObjective::TopOverlap(k) => 1.0 - top_k_overlap(real, simulated, *k),
+            SeasonObjective::BottomOverlap(k) => 1.0 - bottom_k_overlap(real, simulated, *k),
+        }
+    }
+}
+
+impl FromStr for SeasonObjective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+
+        // "top" e "bottom" aceitam o k colado no nome, ex: top4, bottom_3
+        let overlap_k = |rest: &str| -> Result<usize, String> {
+            let rest = rest.trim_start_matches('_');
+            if rest.is_empty() {
+                return Ok(DEFAULT_OVERLAP_K);
+            }
+            match rest.parse::<usize>() {
+                Ok(k) if k > 0 => Ok(k),
+                _ => Err(format!("Invalid k '{}' in objective '{}'", rest, s)),
+            }
+        };
+
+        match s.as_str() {
+            "rmse" | "points_rmse" => Ok(SeasonObjective::PointsRmse),
+            "spearman" => Ok(SeasonObjective::Spearman),
+            "kendall" => Ok(SeasonObjective::Kendall),
+            other => {
+                if let Some(rest) = other.strip_prefix("top") {
+                    overlap_k(rest).map(SeasonObjective::TopOverlap)
+                } else if let Some(rest) = other.strip_prefix("bottom") {
+                    overlap_k(rest).map(SeasonObjective::BottomOverlap)
+                } else {
+                    Err(format!(
+                        "Unknown season objective '{}'. Options are rmse, spearman, kendall, top<k> and bottom<k>",
+                        s
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Final order of each division of the season `games`, best team first. The playoffs are left out, so a
+/// team is only ranked in its own division, see [`SeasonSchedule`]
+pub fn standings_by_division(games: &[Game]) -> BTreeMap<u8, Vec<String>> {
+    SeasonSchedule::new(games)
+        .league_games(games)
+        .into_iter()
+        .map(|(division, division_games)| {
+            let table = LeagueTable::new(&division_games, &division_games[0].league, &division);
+            let order = table.rank().into_iter().map(|(team, _)| team.to_string()).collect();
+            (division, order)
+        })
+        .collect()
+}
+
+/// Rank error between the real and simulated standings, averaged over the divisions of the season
+pub fn standings_rank_error(objective: SeasonObjective, real_games: &[Game], simulated_games: &[Game]) -> f64 {
+    let real = standings_by_division(real_games);
+    let simulated = standings_by_division(simulated_games);
+
+    let errors: Vec<f64> = real
+        .iter()
+        .filter_map(|(division, real_order)| {
+            let simulated_order = simulated.get(division)?;
+            let real_order: Vec<&str> = real_order.iter().map(String::as_str).collect();
+            let simulated_order: Vec<&str> = simulated_order.iter().map(String::as_str).collect();
+            Some(objective.rank_error(&real_order, &simulated_order))
+        })
+        .collect();
+
+    if errors.is_empty() {
+        return 0.0;
+    }
+    errors.iter().sum::<f64>() / errors.len() as f64
+}
+
+/// Position of every team of `real` in both orders. Teams missing from `simulated` are left out
+fn paired_positions(real: &[&str], simulated: &[&str]) -> Vec<(usize, usize)> {
+    let simulated_positions: HashMap<&str, usize> =
+        simulated.iter().enumerate().map(|(position, team)| (*team, position)).collect();
+
+    real.iter()
+        .enumerate()
+        .filter_map(|(position, team)| simulated_positions.get(team).map(|sim| (position, *sim)))
+        .collect()
+}
+
+/// Pearson correlation of the positions. The standings have no ties, see [`LeagueTable::rank`], but
+/// dropping a team can leave gaps in the positions, so the plain `1 - 6Σd²/n(n²-1)` doesn't apply
+pub fn spearman_rho(real: &[&str], simulated: &[&str]) -> f64 {
+    let pairs = paired_positions(real, simulated);
+    let n = pairs.len() as f64;
+    if pairs.len() < 2 {
+        return 1.0;
+    }
+
+    let mean_real = pairs.iter().map(|(r, _)| *r as f64).sum::<f64>() / n;
+    let mean_simulated = pairs.iter().map(|(_, s)| *s as f64).sum::<f64>() / n;
+
+    let mut covariance = 0.0;
+    let mut variance_real = 0.0;
+    let mut variance_simulated = 0.0;
+    for (r, s) in &pairs {
+        let dr = *r as f64 - mean_real;
+        let ds = *s as f64 - mean_simulated;
+        covariance += dr * ds;
+        variance_real += dr * dr;
+        variance_simulated += ds * ds;
+    }
+
+    covariance / (variance_real * variance_simulated).sqrt()
+}
+
+/// Kendall τ: (concordant - discordant) pairs over all pairs of teams
+pub fn kendall_tau(real: &[&str], simulated: &[&str]) -> f64 {
+    let pairs = paired_positions(real, simulated);
+    let n = pairs.len();
+    if n < 2 {
+        return 1.0;
+    }
+
+    let mut balance: i64 = 0;
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let real_order = pairs[i].0.cmp(&pairs[j].0);
+            let simulated_order = pairs[i].1.cmp(&pairs[j].1);
+            balance += if real_order == simulated_order { 1 } else { -1 };
+        }
+    }
+
+    balance as f64 / (n * (n - 1) / 2) as f64
+}
+
+/// Share of the real first `k` teams that are also among the simulated first `k`
+pub fn top_k_overlap(real: &[&str], simulated: &[&str], k: usize) -> f64 {
+    let k = k.min(real.len()).min(simulated.len());
+    if k == 0 {
+        return 1.0;
+    }
+
+    let simulated_top = &simulated[..k];
+    let shared = real[..k].iter().filter(|team| simulated_top.contains(team)).count();
+    shared as f64 / k as f64
+}
+
+/// Share of the real last `k` teams that are also among the simulated last `k`
+pub fn bottom_k_overlap(real: &[&str], simulated: &[&str], k: usize) -> f64 {
+    let real: Vec<&str> = real.iter().rev().copied().collect();
+    let simulated: Vec<&str> = simulated.iter().rev().copied().collect();
+    top_k_overlap(&real, &simulated, k)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-12;
+
+    const REAL: [&str; 4] = ["A", "B", "C", "D"];
+
+    fn game(week: f32, day: i64, home: &str, away: &str, division: u8) -> Game {
+        let date = NaiveDate::from_ymd_opt(2021, 8, 1).unwrap() + chrono::Duration::days(day);
+        Game {
+            week,
+            year: 2021,
+            ..Game::fixture(date, home, away, division)
+        }
+    }
+
+    #[test]
+    fn rank_correlations_of_known_orders() {
+        let reversed = ["D", "C", "B", "A"];
+        let one_swap = ["B", "A", "C", "D"];
+
+        assert!((spearman_rho(&REAL, &REAL) - 1.0).abs() < TOLERANCE);
+        assert!((spearman_rho(&REAL, &reversed) + 1.0).abs() < TOLERANCE);
+        // 1 - 6 * 2 / (4 * 15)
+        assert!((spearman_rho(&REAL, &one_swap) - 0.8).abs() < TOLERANCE);
+
+        assert!((kendall_tau(&REAL, &REAL) - 1.0).abs() < TOLERANCE);
+        assert!((kendall_tau(&REAL, &reversed) + 1.0).abs() < TOLERANCE);
+        // 5 pares concordantes e 1 discordante
+        assert!((kendall_tau(&REAL, &one_swap) - 4.0 / 6.0).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn teams_missing_from_the_simulation_are_left_out() {
+        let simulated = ["A", "C", "D"];
+
+        // posições reais (0, 2, 3) contra simuladas (0, 1, 2): a ordem é a mesma, mas com um buraco
+        assert!((spearman_rho(&REAL, &simulated) - 9.0 / 84.0_f64.sqrt()).abs() < TOLERANCE);
+        assert!((kendall_tau(&REAL, &simulated) - 1.0).abs() < TOLERANCE);
+        assert_eq!(spearman_rho(&REAL, &["A"]), 1.0);
+    }
+
+    #[test]
+    fn overlap_of_the_top_and_bottom_teams() {
+        let simulated = ["B", "D", "A", "C"];
+
+        assert_eq!(top_k_overlap(&REAL, &simulated, 2), 0.5);
+        assert_eq!(top_k_overlap(&REAL, &simulated, 4), 1.0);
+        assert_eq!(bottom_k_overlap(&REAL, &simulated, 1), 0.0);
+        assert_eq!(bottom_k_overlap(&REAL, &simulated, 2), 0.5);
+        // k maior que a divisão usa a divisão inteira
+        assert_eq!(top_k_overlap(&REAL, &simulated, 10), 1.0);
+
+        assert_eq!(SeasonObjective::TopOverlap(2).rank_error(&REAL, &simulated), 0.5);
+        assert_eq!(SeasonObjective::Spearman.rank_error(&REAL, &REAL), 0.0);
+    }
+
+    #[test]
+    fn objectives_are_parsed_back_from_their_names() {
+        for objective in [
+            SeasonObjective::PointsRmse,
+            SeasonObjective::Spearman,
+            SeasonObjective::Kendall,
+            SeasonObjective::TopOverlap(6),
+            SeasonObjective::BottomOverlap(3),
+        ] {
+            assert_eq!(objective.name().parse::<SeasonObjective>(), Ok(objective));
+        }
+        assert_eq!("top".parse(), Ok(SeasonObjective::TopOverlap(DEFAULT_OVERLAP_K)));
+        assert_eq!("bottom_3".parse(), Ok(SeasonObjective::BottomOverlap(3)));
+        assert!("top0".parse::<SeasonObjective>().is_err());
+    }
+
+    #[test]
+    fn the_playoffs_stay_out_of_the_standings() {
+        let mut games = vec![
+            game(1.0, 0, "A", "B", 1),
+            game(2.0, 7, "B", "A", 1),
+            game(1.0, 0, "C", "D", 2),
+            game(2.0, 7, "D", "C", 2),
+        ];
+        // playoff entre o último da primeira e o primeiro da segunda, arquivado como rodada 1 da segunda
+        games.push(game(1.0, 20, "B", "C", 2));
+
+        let standings = standings_by_division(&games);
+
+        assert_eq!(standings[&1].len(), 2);
+        assert_eq!(standings[&2].len(), 2);
+        assert!(!standings[&2].contains(&String::from("B")));
+    }
+}
@@ -1,132 +1,788 @@
 #![allow(dead_code)]
+// pyo3 0.19 macros expand to impl blocks that newer compilers flag as non-local
+#![allow(non_local_definitions)]
 
+use chrono::NaiveDate;
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 
-mod elo;
+pub mod elo;
 pub mod experimentation;
-mod util;
-
-use experimentation::run_config::{self, CustomElo};
-use experimentation::{run_all_experiments::run_experiments, run_config::CustomRating};
-
-use util::game::Game;
+pub mod optimize;
+pub mod util;
+
+use experimentation::benchmark;
+use experimentation::bootstrap::{DEFAULT_CONFIDENCE, DEFAULT_RESAMPLES};
+use experimentation::calibration::{self, DEFAULT_CALIBRATION_BINS};
+use experimentation::dataset::Dataset;
+use experimentation::evaluation::{self, FitnessObjective};
+use experimentation::fitness_cache::{dataset_digest, CacheError, FitnessCache, FitnessKey};
+use experimentation::forecast::{self, Cutoff, Zones};
+use experimentation::regression_dataset::{self, FeatureFormat, DEFAULT_FORM_WINDOW};
+use experimentation::run_config::{self, ActiveComponents, CustomElo};
+use experimentation::standings_metrics::SeasonObjective;
+use experimentation::run_all_experiments::{run_experiments_detailed, PreparedExperiment};
+use experimentation::run_config::CustomRating;
+
+use elo::rating_system::RatingSystemKind;
+use elo::train::{construct_elo_table_at_date, construct_rated_games_for_time_series, validate_time_series_interval};
+use elo::util::season::SeasonKind;
+use util::game::{Game, GameResult};
+use util::parsing::{DatasetError, LoadedCsv, ParseMode};
 
 //TODO: extrair essas duas structs para arquivos separados
 use crate::experimentation::run_config::{RunConfig, RunHyperparameters};
 
 #[pyfunction]
-pub fn run(parameters: RunHyperparameters, config: Option<&RunConfig>) -> PyResult<()> {
+#[pyo3(signature = (parameters, config=None, dataset=None, seed=0))]
+pub fn run(
+    py: Python,
+    parameters: RunHyperparameters,
+    config: Option<&RunConfig>,
+    dataset: Option<&str>,
+    seed: u64,
+) -> PyResult<()> {
     //println!("\n\nRunning experiments with parameters: {:?}", &parameters);
-    //print current directory
-    let curr_directory: String = match std::env::current_dir() {
-        Ok(path) => path.display().to_string(),
-        Err(e) => panic!("Error getting current directory: {}", e),
-    };
-
-    //println!("Current directory: {}", &curr_directory);
-
-    let mut path = String::from("data/brasileirao.csv");
-
-    // "ProjetoElo" is the last directory in the current_dir path prefix it to path
-    if curr_directory.ends_with("lo") {
-        println!("Current directory ends with ProjetoElo");
-        let path2 = String::from("elo_compnat/");
-        path = path2 + &path;
-    }
-    else if curr_directory.ends_with("python") {
-        println!("Current directory ends with python");
-        panic!("You are running from the python directory. Please run from the lib directory of the project");
-        //let path2 = String::from("../");
-        //path = path2 + &path;
-        //println!("Modified path: {}", &path);
-    }
-
-    //println!("Path to csv: {}", &path);
 
-    let partidas = util::parsing::load_csv(&path)
-        .map_err(|e| {
-            println!("Erro fazendo parse do csv de partidas: {}", e);
-        })
-        .unwrap();
+    let partidas = get_data(dataset.unwrap_or("brasileirao"))?;
 
     let run_config = match config {
         Some(config) => config.clone(),
         None => run_config::RunConfig::default(),
     };
+    check_config(&run_config, &partidas, &parameters)?;
 
-    let errors = run_experiments(&partidas, &run_config, &parameters, true);
+    let custom_elo = CustomElo::new(run_config, parameters.components());
+    let summary = py
+        .allow_threads(|| {
+            run_experiments_detailed(&partidas, &custom_elo, &parameters, true, SeasonObjective::PointsRmse, seed)
+                .map(|result| result.summarize(DEFAULT_CONFIDENCE, DEFAULT_RESAMPLES, seed))
+        })
+        .map_err(PyValueError::new_err)?;
 
-    parameters.print_errors_by_year(&errors);
+    summary.print(SeasonKind::detect(&partidas));
 
     Ok(())
 }
 
+#[pyfunction]
+#[pyo3(signature = (filename, run_config_py, hyperparameters_py, seed=0, objective="rmse", confidence=DEFAULT_CONFIDENCE, resamples=DEFAULT_RESAMPLES))]
+/// Same backtest as `fitness_function`, but instead of the mean error of each season returns its spread
+/// over the random variations and a bootstrap confidence interval of the mean, for each season and for
+/// the whole backtest: `{"overall": {"mean", "std", "min", "max", "ci_low", "ci_high"}, "by_season": {year: {...}}}`,
+/// along with the `components` of the model that were switched on.
+/// Two configurations whose intervals overlap can't be told apart with this many variations.
+/// Only the season objectives ("rmse", "spearman", ...) apply, the scoring rules don't vary between runs
+#[allow(clippy::too_many_arguments)]
+pub fn experiment_summary(
+    py: Python,
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+    seed: u64,
+    objective: &str,
+    confidence: f64,
+    resamples: usize,
+) -> PyResult<Py<PyDict>> {
+    let objective: SeasonObjective = objective.parse().map_err(PyValueError::new_err)?;
+    if !(confidence > 0.0 && confidence < 1.0) {
+        return Err(PyValueError::new_err("confidence must be between 0 and 1"));
+    }
 
-pub fn get_data(filename: &str) -> Vec<Game> {
-    // sim, essa funcao é copiada da run. Ideal seria deixarmos toda a logica de dataset aqui e so passar ele parseado bonitinho pro run
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
 
-    let curr_directory: String = match std::env::current_dir() {
-        Ok(path) => path.display().to_string(),
-        Err(e) => panic!("Error getting current directory: {}", e),
-    };
+    let custom_elo = CustomElo::new(run_config, hyperparameters.components());
+    let summary = py
+        .allow_threads(|| {
+            run_experiments_detailed(&partidas, &custom_elo, &hyperparameters, false, objective, seed)
+                .map(|result| result.summarize(confidence, resamples, seed))
+        })
+        .map_err(PyValueError::new_err)?;
 
-    //println!("Current directory: {}", &curr_directory);
+    summary.to_dict(py)
+}
 
+/// Loads the matches of a league, given its name (`"brasileirao"`, `"inglaterra"`, ...) or an explicit
+/// path to a csv. See [`util::dataset::resolve_dataset_path`] for the lookup rules.
+pub fn get_data(filename: &str) -> Result<Vec<Game>, DatasetError> {
+    let path = util::dataset::resolve_dataset_path(filename)?;
 
-    let mut path = String::from("data/brasileirao.csv");
+    //println!("final Path to csv: {}", path.display());
 
-    // "ProjetoElo" is the last directory in the current_dir path prefix it to path
-    // TODO: melhorar esse crime. Se chamamos dentro de elo_compnat, não precisa do prefixo
-    // mas se é chamado de dentro de test_elo, precisa do prefixo, pq ele usa a pasta data errada
-    // Ideal seria resolver dentro do da função de leitura de maneira invisível, deixei um TODO lá
-    if curr_directory.ends_with("ProjetoElo") {
-        
-        let path2 = String::from("elo_compnat/");
-        path = path2 + &path;
-    }
-    else if curr_directory.ends_with("python") {
-       
-        //panic!("You are running from the python directory. Please run from the lib directory of the project");
-        let path2 = String::from("../");
-        path = path2 + &path;
-        
-    }
+    util::parsing::load_csv(&path)
+}
 
-    //println!("final Path to csv: {}", &path);
+/// Same as [`get_data`], but lets the caller skip invalid rows instead of failing on the first one
+pub fn get_data_with_mode(filename: &str, mode: ParseMode) -> Result<LoadedCsv, DatasetError> {
+    let path = util::dataset::resolve_dataset_path(filename)?;
 
-    let partidas = util::parsing::load_csv(&path)
-        .map_err(|e| {
-            println!("Erro fazendo parse do csv de partidas: {}", e);
-        })
-        .unwrap();
+    util::parsing::load_csv_with_mode(&path, mode)
+}
+
+/// Reads the config list the python functions take, see [`check_config`]
+fn run_config_from_list(
+    run_config_py: Vec<f64>,
+    partidas: &[Game],
+    hyperparameters: &RunHyperparameters,
+) -> PyResult<RunConfig> {
+    let run_config = RunConfig::from_python_list(run_config_py).map_err(PyValueError::new_err)?;
+    check_config(&run_config, partidas, hyperparameters)?;
+    Ok(run_config)
+}
 
-    partidas
+/// Fails when a gene of `run_config` is out of range or `w_division` has no weight for a division of
+/// `partidas` that `hyperparameters` use
+fn check_config(run_config: &RunConfig, partidas: &[Game], hyperparameters: &RunHyperparameters) -> PyResult<()> {
+    run_config
+        .validate()
+        .and_then(|_| run_config.check_divisions(partidas, hyperparameters.components()))
+        .map_err(PyValueError::new_err)
 }
 
 #[pyfunction]
+#[pyo3(signature = (filename, strict=true))]
+/// Loads and validates a dataset, returning the parsed matches and the skipped rows.
+/// With `strict=True` the first invalid row raises a `ValueError` instead
+pub fn load_dataset(filename: &str, strict: bool) -> PyResult<(Vec<Game>, Vec<String>)> {
+    let mode = if strict { ParseMode::Strict } else { ParseMode::Lenient };
+
+    let loaded = get_data_with_mode(filename, mode)?;
+    let warnings = loaded.warnings.iter().map(|w| w.to_string()).collect();
+
+    Ok((loaded.games, warnings))
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, run_config_py, hyperparameters_py, seed=0, objective="rmse", cache=None))]
 /// Wrapper for the run_experiments function, so that it can be called from python and the
-/// data parsed
+/// data parsed. `filename` is either a bundled league name or a path to a csv. The GIL is released
+/// while the variations run, and `seed` fixes the result
 ///
+/// `objective` picks what is returned for each simulated season: "rmse" (error of the simulated points
+/// table), a rank error of the simulated standings ("spearman", "kendall", `top<k>`, `bottom<k>`, e.g.
+/// "top4", as `1 - metric`) or the mean "brier", "log_loss" or "rps" of the match probabilities
+///
+/// `cache` is the path of a SQLite file (created if missing) where results are kept, keyed by every
+/// field of the config, the hyperparameters and the matches, the objective and the seed. Repeated
+/// evaluations are read back from it
 pub fn fitness_function(
+    py: Python,
     filename: &str,
     run_config_py: Vec<f64>,
     hyperparameters_py: Vec<u16>,
+    seed: u64,
+    objective: &str,
+    cache: Option<&str>,
 ) -> PyResult<Vec<f64>> {
+    let objective: FitnessObjective = objective.parse().map_err(PyValueError::new_err)?;
 
-    let partidas: Vec<Game> = get_data(filename);
-    let run_config: RunConfig = RunConfig::from_python_list(run_config_py);
-    let hyperparameters: RunHyperparameters = RunHyperparameters::from_python_list(hyperparameters_py);
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+    let experiment = PreparedExperiment::new(&partidas, &hyperparameters).map_err(PyValueError::new_err)?;
 
     //println!("Genotypes for this run: {:?}", &run_config);
     //println!("1a partida: {:?}", partidas[0]);
 
-    let errors = run_experiments(&partidas, &run_config, &hyperparameters, false);
+    let errors = py.allow_threads(|| -> Result<Vec<f64>, CacheError> {
+        let evaluate = || {
+            let custom_elo = CustomElo::new(run_config.clone(), hyperparameters.components());
+            experiment.errors(&custom_elo, &hyperparameters, objective, seed)
+        };
+
+        match cache {
+            Some(path) => {
+                let cache = FitnessCache::open(Path::new(path))?;
+                let key = FitnessKey::new(&dataset_digest(&partidas), &run_config, &hyperparameters, objective, seed);
+                cache.get_or_compute(&key, evaluate)
+            }
+            None => Ok(evaluate()),
+        }
+    })?;
 
     //println!("Errors: {:?}", &errors);
     // aqui sairia o erro
     Ok(errors)
 }
 
+#[pyfunction]
+/// Brier score, log-loss and RPS of the H/D/A probabilities of every match after the training period,
+/// overall and by season: `{"overall": {...}, "by_season": {year: {...}}}`
+pub fn evaluate_predictions(
+    py: Python,
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+) -> PyResult<Py<PyDict>> {
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+
+    evaluation::validate_backtest(&partidas, &hyperparameters).map_err(PyValueError::new_err)?;
+
+    let custom_elo = CustomElo::new(run_config, hyperparameters.components());
+    let evaluation = py.allow_threads(|| {
+        evaluation::evaluate_predictions(&custom_elo, &partidas, &hyperparameters)
+    });
+
+    evaluation.to_dict(py)
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, run_config_py, hyperparameters_py, bins=DEFAULT_CALIBRATION_BINS, output_path=None))]
+/// Reliability of the H/D/A probabilities after the training period: for each outcome, the matches are
+/// binned by predicted probability and compared with how often it happened. Also returns the expected
+/// calibration error of each outcome and the predicted vs observed draw rate, overall and by expected
+/// score of the home side. The tables are dicts of columns, `output_path` also writes them to a csv
+pub fn calibration_report(
+    py: Python,
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+    bins: usize,
+    output_path: Option<&str>,
+) -> PyResult<Py<PyDict>> {
+    if bins == 0 {
+        return Err(PyValueError::new_err("bins must be at least 1"));
+    }
+
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+
+    evaluation::validate_backtest(&partidas, &hyperparameters).map_err(PyValueError::new_err)?;
+
+    let tie_frequency = run_config.tie_frequency;
+    let custom_elo = CustomElo::new(run_config, hyperparameters.components());
+    let report = py.allow_threads(|| {
+        calibration::calibration_report(&custom_elo, &partidas, &hyperparameters, bins, tie_frequency)
+    });
+
+    if let Some(output_path) = output_path {
+        calibration::write_calibration_csv(&report, Path::new(output_path))?;
+    }
+
+    report.to_dict(py)
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, run_config_py, hyperparameters_py, systems=None, seed=0, objective="rmse", display=false))]
+/// Runs the same backtest with each rating system in `systems` and returns the errors by season of each
+/// one, keyed by name. Besides "elo", "glicko2", "trueskill" and "weng_lin" there are the baselines
+/// "base_rates" (historical H/D/A shares), "last_season" (last season's table repeated), "market_value"
+/// and "uniform" (every result equally likely). All of them run by default.
+/// Only the elo uses the genes of `run_config_py`, the others take just its draw frequency. `objective` is
+/// the same as in `fitness_function`, and `display` prints the errors side by side
+#[allow(clippy::too_many_arguments)]
+pub fn benchmark_rating_systems(
+    py: Python,
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+    systems: Option<Vec<String>>,
+    seed: u64,
+    objective: &str,
+    display: bool,
+) -> PyResult<HashMap<String, Vec<f64>>> {
+    let objective: FitnessObjective = objective.parse().map_err(PyValueError::new_err)?;
+
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+
+    let kinds = match systems {
+        Some(names) => names
+            .iter()
+            .map(|name| name.parse::<RatingSystemKind>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(PyValueError::new_err)?,
+        None => RatingSystemKind::ALL.to_vec(),
+    };
+
+    let errors_by_system: Vec<(RatingSystemKind, Vec<f64>)> = py
+        .allow_threads(|| {
+            kinds
+                .into_iter()
+                .map(|kind| {
+                    benchmark::run_rating_system(kind, &partidas, &run_config, &hyperparameters, objective, seed)
+                        .map(|errors| (kind, errors))
+                })
+                .collect::<Result<_, _>>()
+        })
+        .map_err(PyValueError::new_err)?;
+
+    if display {
+        benchmark::print_benchmark(&errors_by_system, &hyperparameters, SeasonKind::detect(&partidas));
+    }
+
+    Ok(errors_by_system
+        .into_iter()
+        .map(|(kind, errors)| (kind.name().to_string(), errors))
+        .collect())
+}
+
+#[pyfunction]
+#[pyo3(name = "optimize", signature = (filename, hyperparameters_py, algorithm="pso", base_config=None, bounds=None, population=10, iterations=10, options=None, objective="rmse", seed=0, cache=None, checkpoint=None, checkpoint_every=1))]
+/// Tunes the `RunConfig` natively with "pso", "genetic" or "cmaes", loading the matches once and
+/// evaluating each population in parallel. The cost of a config is the mean over the seasons of what
+/// `fitness_function` returns for `objective`.
+///
+/// `bounds` maps field names (`"k_factor"`, `"w_division_0"`, ...) to `(low, high)`. Fields without a bound
+/// keep the value of `base_config`, and without `bounds` the space of `python/main.py` is used. `options`
+/// overrides the settings of the algorithm, e.g. `{"w": 0.7, "c1": 1.5}` for the PSO or `{"sigma": 0.2}`
+/// for the CMA-ES. `cache` is a SQLite file shared with `fitness_function`. Returns the best config, its
+/// cost and the cost history of every iteration
+///
+/// `checkpoint` is a json file where the state of the optimizer (population, rng, evaluations and best so
+/// far) is saved every `checkpoint_every` iterations. If it already exists the run resumes from it, and
+/// ends with the same result as if it had never stopped
+#[allow(clippy::too_many_arguments)]
+pub fn optimize_config(
+    py: Python,
+    filename: &str,
+    hyperparameters_py: Vec<u16>,
+    algorithm: &str,
+    base_config: Option<RunConfig>,
+    bounds: Option<BTreeMap<String, (f64, f64)>>,
+    population: usize,
+    iterations: usize,
+    options: Option<HashMap<String, f64>>,
+    objective: &str,
+    seed: u64,
+    cache: Option<&str>,
+    checkpoint: Option<&str>,
+    checkpoint_every: usize,
+) -> PyResult<Py<PyDict>> {
+    let objective: FitnessObjective = objective.parse().map_err(PyValueError::new_err)?;
+    let algorithm = algorithm
+        .parse::<optimize::Algorithm>()
+        .map_err(PyValueError::new_err)?
+        .with_budget(population, iterations)
+        .with_options(&options.unwrap_or_default())
+        .map_err(PyValueError::new_err)?;
+
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let space = parameter_space(base_config.unwrap_or_default(), bounds);
+
+    run_optimization(
+        py,
+        filename,
+        &hyperparameters,
+        &space,
+        &algorithm,
+        objective,
+        seed,
+        cache,
+        checkpoint.map(|path| (path, checkpoint_every)),
+    )
+}
+
+/// Loads the matches and runs `optimize::optimize_run_config`, shared by `optimize` and
+/// `resume_optimization`
+#[allow(clippy::too_many_arguments)]
+fn run_optimization(
+    py: Python,
+    filename: &str,
+    hyperparameters: &RunHyperparameters,
+    space: &optimize::ParameterSpace,
+    algorithm: &optimize::Algorithm,
+    objective: FitnessObjective,
+    seed: u64,
+    cache: Option<&str>,
+    checkpoint: Option<(&str, usize)>,
+) -> PyResult<Py<PyDict>> {
+    let cache = cache.map(|path| FitnessCache::open(Path::new(path))).transpose()?;
+    let partidas: Vec<Game> = get_data(filename)?;
+    check_config(&space.base, &partidas, hyperparameters)?;
+
+    let checkpointing = checkpoint.map(|(path, every)| optimize::Checkpointing {
+        path: Path::new(path).to_path_buf(),
+        every,
+        context: optimize::RunContext::new(filename, &partidas, hyperparameters, objective),
+    });
+
+    let result = py
+        .allow_threads(|| {
+            optimize::optimize_run_config(
+                &partidas,
+                hyperparameters,
+                space,
+                algorithm,
+                objective,
+                seed,
+                cache.as_ref(),
+                checkpointing.as_ref(),
+            )
+        })
+        .map_err(PyValueError::new_err)?;
+
+    result.to_dict(py, space)
+}
+
+#[pyfunction]
+#[pyo3(signature = (checkpoint, cache=None, checkpoint_every=1))]
+/// Resumes the run saved in `checkpoint` by `optimize`, with the dataset, hyperparameters, algorithm,
+/// bounds, objective and seed stored in the file, and returns what `optimize` would. A finished run is
+/// returned as it is
+pub fn resume_optimization(
+    py: Python,
+    checkpoint: &str,
+    cache: Option<&str>,
+    checkpoint_every: usize,
+) -> PyResult<Py<PyDict>> {
+    let saved = optimize::Checkpoint::load(Path::new(checkpoint))?;
+    let objective: FitnessObjective = saved.context.objective.parse().map_err(PyValueError::new_err)?;
+
+    run_optimization(
+        py,
+        &saved.context.filename,
+        &saved.context.hyperparameters,
+        &saved.space,
+        &saved.algorithm,
+        objective,
+        saved.seed,
+        cache,
+        Some((checkpoint, checkpoint_every)),
+    )
+}
+
+#[pyfunction]
+#[pyo3(signature = (directory="."))]
+/// One summary per checkpoint file in `directory`, oldest first: path, algorithm, dataset, objective,
+/// seed, iterations done out of the total, evaluations, best cost, whether it finished and the creation
+/// and last update times (unix seconds)
+pub fn list_runs(py: Python, directory: &str) -> PyResult<Vec<Py<PyDict>>> {
+    optimize::list_checkpoints(Path::new(directory))?
+        .into_iter()
+        .map(|(path, checkpoint)| {
+            let summary = checkpoint.summary(py)?;
+            summary.as_ref(py).set_item("path", path.display().to_string())?;
+            Ok(summary)
+        })
+        .collect()
+}
+
+#[pyfunction]
+/// Everything saved in a checkpoint: the summary of `list_runs`, the settings of the algorithm, the bounds
+/// and base config, the best config so far and the cost history, as `optimize` returns them
+pub fn inspect_run(py: Python, checkpoint: &str) -> PyResult<Py<PyDict>> {
+    optimize::Checkpoint::load(Path::new(checkpoint))?.to_dict(py)
+}
+
+/// Search space of `optimize` and `optimize_pareto`: the given bounds around `base_config`, or the space
+/// of `python/main.py`
+fn parameter_space(base_config: RunConfig, bounds: Option<BTreeMap<String, (f64, f64)>>) -> optimize::ParameterSpace {
+    match bounds {
+        Some(bounds) => bounds
+            .into_iter()
+            .fold(optimize::ParameterSpace::new(base_config), |space, (name, (low, high))| {
+                space.with_bound(&name, low, high)
+            }),
+        None => optimize::ParameterSpace::default_for(base_config),
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, hyperparameters_py, objectives=None, base_config=None, bounds=None, population=20, generations=10, options=None, seed=0, cache=None, csv_path=None, json_path=None))]
+/// Multi-objective tuning of the `RunConfig` with NSGA-II. Instead of a single best config returns the
+/// Pareto front: the configs no other one beats in every objective, with the value of each.
+///
+/// `objectives` are names accepted by `fitness_function` ("rmse", "top4", "log_loss", ...) plus
+/// "volatility", the mean rating change of a team per match. By default rmse, log_loss and volatility.
+/// `bounds`, `base_config` and `cache` work as in `optimize`, and `options` takes "crossover_rate",
+/// "blend_alpha", "mutation_rate" and "mutation_sigma". `csv_path` and `json_path` also write the front
+#[allow(clippy::too_many_arguments)]
+pub fn optimize_pareto(
+    py: Python,
+    filename: &str,
+    hyperparameters_py: Vec<u16>,
+    objectives: Option<Vec<String>>,
+    base_config: Option<RunConfig>,
+    bounds: Option<BTreeMap<String, (f64, f64)>>,
+    population: usize,
+    generations: usize,
+    options: Option<HashMap<String, f64>>,
+    seed: u64,
+    cache: Option<&str>,
+    csv_path: Option<&str>,
+    json_path: Option<&str>,
+) -> PyResult<Py<PyDict>> {
+    let objectives = objectives
+        .unwrap_or_else(|| optimize::DEFAULT_PARETO_OBJECTIVES.map(String::from).to_vec())
+        .iter()
+        .map(|name| name.parse::<optimize::ParetoObjective>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(PyValueError::new_err)?;
+    let options = optimize::NsgaOptions {
+        population,
+        generations,
+        ..optimize::NsgaOptions::default()
+    }
+    .with_options(&options.unwrap_or_default())
+    .map_err(PyValueError::new_err)?;
+    let cache = cache.map(|path| FitnessCache::open(Path::new(path))).transpose()?;
+
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let space = parameter_space(base_config.unwrap_or_default(), bounds);
+    check_config(&space.base, &partidas, &hyperparameters)?;
+
+    let front = py
+        .allow_threads(|| {
+            optimize::optimize_pareto(
+                &partidas,
+                &hyperparameters,
+                &space,
+                &objectives,
+                &options,
+                seed,
+                cache.as_ref(),
+            )
+        })
+        .map_err(PyValueError::new_err)?;
+
+    if let Some(csv_path) = csv_path {
+        front.write_csv(&space, Path::new(csv_path))?;
+    }
+    if let Some(json_path) = json_path {
+        front.write_json(&space, Path::new(json_path))?;
+    }
+
+    front.to_dict(py, &space)
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, hyperparameters_py, base_config=None, parameters=None, max_iterations=100))]
+/// Maximum-likelihood fit of the `RunConfig` on the training seasons: maximizes the log-likelihood of the
+/// observed results under the pre-match probabilities with L-BFGS, using exact gradients.
+///
+/// `parameters` are the field names to fit (`"k_factor"`, `"gamma"`, `"w_division_0"`, ...), by default
+/// `k_factor`, `home_advantage` and `tie_frequency`. The others keep the value of `base_config`, which is
+/// also the starting point. Returns the fitted config, the estimates with their standard errors and
+/// covariance, and the log-likelihood
+pub fn fit_mle(
+    py: Python,
+    filename: &str,
+    hyperparameters_py: Vec<u16>,
+    base_config: Option<RunConfig>,
+    parameters: Option<Vec<String>>,
+    max_iterations: usize,
+) -> PyResult<Py<PyDict>> {
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let options = optimize::LbfgsOptions {
+        max_iterations,
+        ..optimize::LbfgsOptions::default()
+    };
+
+    let base_config = base_config.unwrap_or_default();
+    check_config(&base_config, &partidas, &hyperparameters)?;
+
+    let fit = py
+        .allow_threads(|| {
+            optimize::fit_mle(&partidas, &hyperparameters, base_config, parameters, &options)
+        })
+        .map_err(PyValueError::new_err)?;
+
+    fit.to_dict(py)
+}
+
+#[pyfunction]
+/// Elo ratings of every team as of `date`, trained from `hyperparameters.starting_year` with the matches
+/// played before that day
+pub fn elo_table_at_date(
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+    date: NaiveDate,
+) -> PyResult<HashMap<String, f64>> {
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+
+    let custom_elo = CustomElo::new(run_config, hyperparameters.components());
+
+    let elo_table = construct_elo_table_at_date(
+        &custom_elo,
+        &partidas,
+        &hyperparameters,
+        hyperparameters.starting_year,
+        date,
+    );
+
+    Ok(elo_table
+        .into_iter()
+        .map(|(team, elo)| (team, elo.rating))
+        .collect())
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, run_config_py, hyperparameters_py, output_path, end_year=None))]
+/// Trains from `hyperparameters.starting_year` up to `end_year` (the last season by default) and writes the
+/// dataset back to `output_path` with the pre and post-match elos and the expected H/D/A probabilities
+/// of every match. Returns how many matches were rated
+pub fn export_match_elos(
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+    output_path: &str,
+    end_year: Option<u16>,
+) -> PyResult<usize> {
+    let path = util::dataset::resolve_dataset_path(filename)?;
+    let partidas: Vec<Game> = util::parsing::load_csv(&path)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+
+    let last_year = partidas.iter().map(|game| game.year).max().unwrap_or_default();
+    let end_year = end_year.unwrap_or(last_year);
+    validate_time_series_interval(&partidas, hyperparameters.starting_year, end_year).map_err(PyValueError::new_err)?;
+
+    let custom_elo = CustomElo::new(run_config, hyperparameters.components());
+
+    let (_, rated_games) = construct_rated_games_for_time_series(
+        &custom_elo,
+        &partidas,
+        &hyperparameters,
+        hyperparameters.starting_year,
+        end_year,
+    );
+
+    let written = util::export::write_rated_games_csv(&path, &rated_games, Path::new(output_path))?;
+
+    Ok(written)
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, run_config_py, hyperparameters_py, output_path, end_year=None, form_window=DEFAULT_FORM_WINDOW))]
+/// Builds the per-match feature table used for regression (elo difference with and without the home
+/// advantage, market value difference, form, goal difference averages, rest days, division) with result
+/// and goals as targets.
+/// Written as parquet when `output_path` ends with `.parquet`, csv otherwise. Returns the number of rows
+pub fn build_regression_dataset(
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+    output_path: &str,
+    end_year: Option<u16>,
+    form_window: usize,
+) -> PyResult<usize> {
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+
+    let last_year = partidas.iter().map(|game| game.year).max().unwrap_or_default();
+    let end_year = end_year.unwrap_or(last_year);
+    validate_time_series_interval(&partidas, hyperparameters.starting_year, end_year).map_err(PyValueError::new_err)?;
+
+    let features = regression_dataset::build_regression_dataset(
+        &partidas,
+        &run_config,
+        &hyperparameters,
+        hyperparameters.starting_year,
+        end_year,
+        form_window,
+    );
+
+    let output = Path::new(output_path);
+    regression_dataset::write_features(&features, output, FeatureFormat::from_path(output))?;
+
+    Ok(features.len())
+}
+
+#[pyfunction]
+#[pyo3(signature = (filename, run_config_py, hyperparameters_py, year, simulations=1000, zones=None, output_path=None, seed=0, cutoff_round=None, cutoff_date=None))]
+/// Simulates season `year` `simulations` times from the elos trained since `hyperparameters.starting_year`
+/// and returns, for every team, the distribution of final positions and points and the chances of title,
+/// `top`/`extended_top` spots and relegation. Zones default to the usual ones of the league, see
+/// `Zones.for_league`. When `output_path` is given the forecast is also written there as csv.
+///
+/// With `cutoff_round` (rounds up to it were played) or `cutoff_date` (matches before it were played)
+/// the real results before the cutoff are kept and only the rest of the season is simulated. The
+/// playoffs count as the round after the last one
+#[allow(clippy::too_many_arguments)]
+pub fn forecast_season(
+    py: Python,
+    filename: &str,
+    run_config_py: Vec<f64>,
+    hyperparameters_py: Vec<u16>,
+    year: u16,
+    simulations: u32,
+    zones: Option<Zones>,
+    output_path: Option<&str>,
+    seed: u64,
+    cutoff_round: Option<u16>,
+    cutoff_date: Option<NaiveDate>,
+) -> PyResult<Py<PyDict>> {
+    let cutoff = match (cutoff_round, cutoff_date) {
+        (Some(_), Some(_)) => {
+            return Err(PyValueError::new_err(
+                "Pass either cutoff_round or cutoff_date, not both",
+            ))
+        }
+        (Some(round), None) => Some(Cutoff::Round(round)),
+        (None, Some(date)) => Some(Cutoff::Date(date)),
+        (None, None) => None,
+    };
+
+    let partidas: Vec<Game> = get_data(filename)?;
+    let hyperparameters =
+        RunHyperparameters::from_python_list(hyperparameters_py).map_err(PyValueError::new_err)?;
+    let run_config: RunConfig = run_config_from_list(run_config_py, &partidas, &hyperparameters)?;
+
+    let components = hyperparameters.components();
+    let partidas: Vec<Game> = partidas
+        .into_iter()
+        .filter(|game| components.uses_division(game.division))
+        .collect();
+
+    let seasons_map = elo::util::season::construct_seasons(&partidas);
+    let season = seasons_map
+        .get(&year)
+        .ok_or_else(|| PyValueError::new_err(format!("{} has no season {}", filename, year)))?;
+
+    let custom_elo = CustomElo::new(run_config, components);
+    let zones = zones.unwrap_or_else(|| Zones::for_league(&season.league));
+
+    let forecast = py.allow_threads(|| {
+        let (elo_table, goal_model) =
+            forecast::season_start(&custom_elo, &partidas, &seasons_map, &hyperparameters, year);
+
+        forecast::forecast_season(
+            &custom_elo,
+            season,
+            &elo_table,
+            &goal_model,
+            &hyperparameters,
+            &zones,
+            simulations,
+            seed,
+            cutoff,
+        )
+    });
+
+    if let Some(output_path) = output_path {
+        forecast::write_forecast_csv(&forecast, Path::new(output_path))?;
+    }
+
+    forecast.to_dict(py)
+}
+
 /// This is the python module definition, everything that you want to use
 /// inside python must be declared here
 /// Modulo que vai pro python, necessário adicionar as funções e classes que ele vai usar
@@ -134,10 +790,30 @@ pub fn fitness_function(
 fn elo_compnat(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(run, m)?)?;
     m.add_function(wrap_pyfunction!(fitness_function, m)?)?;
+    m.add_function(wrap_pyfunction!(experiment_summary, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_predictions, m)?)?;
+    m.add_function(wrap_pyfunction!(calibration_report, m)?)?;
+    m.add_function(wrap_pyfunction!(load_dataset, m)?)?;
+    m.add_function(wrap_pyfunction!(elo_table_at_date, m)?)?;
+    m.add_function(wrap_pyfunction!(export_match_elos, m)?)?;
+    m.add_function(wrap_pyfunction!(build_regression_dataset, m)?)?;
+    m.add_function(wrap_pyfunction!(benchmark_rating_systems, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_config, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_pareto, m)?)?;
+    m.add_function(wrap_pyfunction!(resume_optimization, m)?)?;
+    m.add_function(wrap_pyfunction!(list_runs, m)?)?;
+    m.add_function(wrap_pyfunction!(inspect_run, m)?)?;
+    m.add_function(wrap_pyfunction!(fit_mle, m)?)?;
+    m.add_function(wrap_pyfunction!(forecast_season, m)?)?;
+    m.add_class::<Dataset>()?;
     m.add_class::<RunHyperparameters>()?;
     m.add_class::<RunConfig>()?;
     m.add_class::<CustomElo>()?;
+    m.add_class::<ActiveComponents>()?;
+    m.add_class::<Zones>()?;
     m.add_class::<CustomRating>()?;
+    m.add_class::<Game>()?;
+    m.add_class::<GameResult>()?;
 
     Ok(())
 }
@@ -0,0 +1,485 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::cmaes::CmaEsState;
+use super::genetic::GeneticState;
+use super::pso::PsoState;
+use super::{Algorithm, OptimizationResult, ParameterSpace, SearchOutcome};
+use crate::experimentation::evaluation::FitnessObjective;
+use crate::experimentation::fitness_cache::{dataset_digest, MODEL_VERSION};
+use crate::experimentation::run_config::RunHyperparameters;
+use crate::util::game::Game;
+
+/// Layout of [`Checkpoint`], bumped when its fields change
+const CHECKPOINT_FORMAT: u32 = 1;
+
+/// A checkpoint only resumes with the layout and the [`MODEL_VERSION`] that wrote it, otherwise the costs
+/// could change
+fn checkpoint_version() -> String {
+    format!("{}.{}", CHECKPOINT_FORMAT, MODEL_VERSION)
+}
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("Checkpoint {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Checkpoint {}: {source}", path.display())]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The file belongs to another run, `field` is the first setting that differs
+    #[error("Checkpoint {} was written by a different run ({field} differs)", path.display())]
+    Mismatch { path: PathBuf, field: &'static str },
+}
+
+impl From<CheckpointError> for PyErr {
+    fn from(err: CheckpointError) -> PyErr {
+        match err {
+            CheckpointError::Mismatch { .. } => PyValueError::new_err(err.to_string()),
+            _ => PyIOError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// `ChaCha12Rng` (the generator behind `StdRng`) as its seed, stream and position, so a resumed run draws
+/// exactly the numbers the interrupted one would have
+pub(crate) mod rng_state {
+    use rand_chacha::ChaCha12Rng;
+    use rand::SeedableRng;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct RngState {
+        seed: Vec<u8>,
+        stream: u64,
+        /// u128, that json doesn't take as a number
+        word_pos: String,
+    }
+
+    pub fn serialize<S: Serializer>(rng: &ChaCha12Rng, serializer: S) -> Result<S::Ok, S::Error> {
+        RngState {
+            seed: rng.get_seed().to_vec(),
+            stream: rng.get_stream(),
+            word_pos: rng.get_word_pos().to_string(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ChaCha12Rng, D::Error> {
+        let state = RngState::deserialize(deserializer)?;
+        let seed: [u8; 32] = state
+            .seed
+            .try_into()
+            .map_err(|_| D::Error::custom("the rng seed should have 32 bytes"))?;
+        let word_pos: u128 = state.word_pos.parse().map_err(D::Error::custom)?;
+
+        let mut rng = ChaCha12Rng::from_seed(seed);
+        rng.set_stream(state.stream);
+        rng.set_word_pos(word_pos);
+        Ok(rng)
+    }
+}
+
+/// State of an optimizer between two iterations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchState {
+    Pso(PsoState),
+    Genetic(GeneticState),
+    CmaEs(CmaEsState),
+}
+
+impl SearchState {
+    /// Initial state of `algorithm`. The PSO and the GA evaluate their first population here
+    pub(crate) fn start<F>(space: &ParameterSpace, algorithm: &Algorithm, cost: &F, seed: u64) -> Self
+    where
+        F: Fn(&[f64]) -> f64 + Sync,
+    {
+        match algorithm {
+            Algorithm::Pso(options) => SearchState::Pso(PsoState::new(space, options, cost, seed)),
+            Algorithm::Genetic(options) => SearchState::Genetic(GeneticState::new(space, options, cost, seed)),
+            Algorithm::CmaEs(options) => SearchState::CmaEs(CmaEsState::new(space, options, seed)),
+        }
+    }
+
+    /// Iterations done so far
+    pub fn iteration(&self) -> usize {
+        match self {
+            SearchState::Pso(state) => state.iteration(),
+            SearchState::Genetic(state) => state.iteration(),
+            SearchState::CmaEs(state) => state.iteration(),
+        }
+    }
+
+    /// Whether this is a state of `algorithm`
+    pub fn belongs_to(&self, algorithm: &Algorithm) -> bool {
+        matches!(
+            (self, algorithm),
+            (SearchState::Pso(_), Algorithm::Pso(_))
+                | (SearchState::Genetic(_), Algorithm::Genetic(_))
+                | (SearchState::CmaEs(_), Algorithm::CmaEs(_))
+        )
+    }
+
+    pub(crate) fn step<F>(&mut self, algorithm: &Algorithm, cost: &F)
+    where
+        F: Fn(&[f64]) -> f64 + Sync,
+    {
+        match (self, algorithm) {
+            (SearchState::Pso(state), Algorithm::Pso(options)) => state.step(options, cost),
+            (SearchState::Genetic(state), Algorithm::Genetic(options)) => state.step(options, cost),
+            (SearchState::CmaEs(state), Algorithm::CmaEs(options)) => state.step(options, cost),
+            _ => unreachable!("a state only runs with the algorithm that created it"),
+        }
+    }
+
+    pub(crate) fn outcome(&self) -> SearchOutcome {
+        match self {
+            SearchState::Pso(state) => state.outcome(),
+            SearchState::Genetic(state) => state.outcome(),
+            SearchState::CmaEs(state) => state.outcome(),
+        }
+    }
+}
+
+/// What the costs of a run were computed on, so it isn't resumed against other data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunContext {
+    /// League name or csv path, as given
+    pub filename: String,
+    /// [`dataset_digest`] of the matches
+    pub dataset: String,
+    pub hyperparameters: RunHyperparameters,
+    pub objective: String,
+}
+
+impl RunContext {
+    pub fn new(
+        filename: &str,
+        games: &[Game],
+        hyperparameters: &RunHyperparameters,
+        objective: FitnessObjective,
+    ) -> Self {
+        RunContext {
+            filename: filename.to_string(),
+            dataset: dataset_digest(games),
+            hyperparameters: hyperparameters.clone(),
+            objective: objective.name(),
+        }
+    }
+}
+
+/// Contents of a checkpoint file: the settings of the run and the state of the optimizer after the last
+/// saved iteration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: String,
+    /// Unix time, in seconds
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub finished: bool,
+    pub algorithm: Algorithm,
+    pub space: ParameterSpace,
+    pub seed: u64,
+    pub context: RunContext,
+    pub state: SearchState,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        let text = std::fs::read_to_string(path).map_err(|source| CheckpointError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        serde_json::from_str(&text).map_err(|source| CheckpointError::Json {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Writes to a temporary file and renames it over `path`, so a crash while saving keeps the last
+    /// checkpoint whole
+    pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+        let io_error = |source| CheckpointError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let text = serde_json::to_string(self).map_err(|source| CheckpointError::Json {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, text).map_err(io_error)?;
+        std::fs::rename(&temporary, path).map_err(io_error)
+    }
+
+    /// Result of the run up to the saved iteration
+    pub fn result(&self) -> OptimizationResult {
+        OptimizationResult::from_outcome(&self.space, &self.algorithm, self.state.outcome())
+    }
+
+    /// Settings, progress and best config found, plus the history as columns
+    pub fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let dict = self.result().to_dict(py, &self.space)?;
+        let summary = self.summary(py)?;
+        let dict = dict.as_ref(py);
+        for (key, value) in summary.as_ref(py) {
+            dict.set_item(key, value)?;
+        }
+        let options = PyDict::new(py);
+        for (name, value) in self.algorithm.settings() {
+            options.set_item(name, value)?;
+        }
+        dict.set_item("options", options)?;
+        dict.set_item("hyperparameters", Py::new(py, self.context.hyperparameters.clone())?)?;
+
+        let bounds = PyDict::new(py);
+        for bound in &self.space.bounds {
+            bounds.set_item(&bound.name, (bound.low, bound.high))?;
+        }
+        dict.set_item("bounds", bounds)?;
+        dict.set_item("base_config", Py::new(py, self.space.base.clone())?)?;
+        Ok(dict.into())
+    }
+
+    /// What `list_runs` shows of each run
+    pub fn summary(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let (_, best_cost, evaluations, _) = self.state.outcome();
+
+        let dict = PyDict::new(py);
+        dict.set_item("algorithm", self.algorithm.name())?;
+        dict.set_item("filename", &self.context.filename)?;
+        dict.set_item("objective", &self.context.objective)?;
+        dict.set_item("seed", self.seed)?;
+        dict.set_item("iteration", self.state.iteration())?;
+        dict.set_item("iterations", self.algorithm.iterations())?;
+        dict.set_item("evaluations", evaluations)?;
+        dict.set_item("best_cost", best_cost)?;
+        dict.set_item("finished", self.finished)?;
+        dict.set_item("version", &self.version)?;
+        dict.set_item("created_at", self.created_at)?;
+        dict.set_item("updated_at", self.updated_at)?;
+        Ok(dict.into())
+    }
+}
+
+/// Where and how often [`minimize`](super::minimize) saves its state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpointing {
+    pub path: PathBuf,
+    /// Iterations between two saves. The initial state and the last iteration are always saved
+    pub every: usize,
+    pub context: RunContext,
+}
+
+impl Checkpointing {
+    /// The saved run at `path`, if there is one. It must have been started with the same settings
+    pub fn resume(
+        &self,
+        space: &ParameterSpace,
+        algorithm: &Algorithm,
+        seed: u64,
+    ) -> Result<Option<Checkpoint>, CheckpointError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let checkpoint = Checkpoint::load(&self.path)?;
+        let mismatch = if checkpoint.version != checkpoint_version() {
+            Some("version")
+        } else if checkpoint.algorithm != *algorithm || !checkpoint.state.belongs_to(algorithm) {
+            Some("algorithm")
+        } else if checkpoint.space != *space {
+            Some("parameter space")
+        } else if checkpoint.seed != seed {
+            Some("seed")
+        } else if checkpoint.context.dataset != self.context.dataset {
+            Some("dataset")
+        } else if checkpoint.context.hyperparameters != self.context.hyperparameters {
+            Some("hyperparameters")
+        } else if checkpoint.context.objective != self.context.objective {
+            Some("objective")
+        } else {
+            None
+        };
+
+        match mismatch {
+            Some(field) => Err(CheckpointError::Mismatch {
+                path: self.path.clone(),
+                field,
+            }),
+            None => Ok(Some(checkpoint)),
+        }
+    }
+
+    pub(crate) fn save(
+        &self,
+        space: &ParameterSpace,
+        algorithm: &Algorithm,
+        seed: u64,
+        state: &SearchState,
+        created_at: u64,
+        finished: bool,
+    ) -> Result<(), CheckpointError> {
+        Checkpoint {
+            version: checkpoint_version(),
+            created_at,
+            updated_at: unix_time(),
+            finished,
+            algorithm: algorithm.clone(),
+            space: space.clone(),
+            seed,
+            context: self.context.clone(),
+            state: state.clone(),
+        }
+        .save(&self.path)
+    }
+}
+
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Every checkpoint in `directory`, oldest first. Other json files there are ignored
+pub fn list_checkpoints(directory: &Path) -> Result<Vec<(PathBuf, Checkpoint)>, CheckpointError> {
+    let io_error = |source| CheckpointError::Io {
+        path: directory.to_path_buf(),
+        source,
+    };
+
+    let mut checkpoints = Vec::new();
+    for entry in std::fs::read_dir(directory).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
+        }
+        if let Ok(checkpoint) = Checkpoint::load(&path) {
+            checkpoints.push((path, checkpoint));
+        }
+    }
+
+    checkpoints.sort_by(|a, b| a.1.created_at.cmp(&b.1.created_at).then_with(|| a.0.cmp(&b.0)));
+    Ok(checkpoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experimentation::run_config::RunConfig;
+    use crate::optimize::{minimize, INVALID_COST};
+
+    /// Quadratic with its minimum at (0.3, 0.6), invalid on a third of the cube
+    fn cost(position: &[f64]) -> f64 {
+        if position[0] > 0.7 {
+            f64::NAN
+        } else {
+            (position[0] - 0.3).powi(2) + (position[1] - 0.6).powi(2)
+        }
+    }
+
+    fn space() -> ParameterSpace {
+        ParameterSpace::new(RunConfig::default())
+            .with_bound("k_factor", 0.0, 2.0)
+            .with_bound("gamma", 0.0, 2.0)
+    }
+
+    fn algorithms() -> Vec<Algorithm> {
+        ["pso", "genetic", "cmaes"]
+            .iter()
+            .map(|name| name.parse::<Algorithm>().unwrap().with_budget(8, 6))
+            .collect()
+    }
+
+    fn checkpointing(name: &str) -> Checkpointing {
+        Checkpointing {
+            path: std::env::temp_dir().join(format!("checkpoint_test_{}_{}.json", name, std::process::id())),
+            every: 1,
+            context: RunContext::new("teste", &[], &RunHyperparameters::default(), FitnessObjective::default()),
+        }
+    }
+
+    /// Writes the checkpoint a run stopped after `steps` iterations would leave
+    fn interrupt(checkpointing: &Checkpointing, algorithm: &Algorithm, steps: usize, seed: u64) {
+        let mut state = SearchState::start(&space(), algorithm, &cost, seed);
+        for _ in 0..steps {
+            state.step(algorithm, &cost);
+        }
+        checkpointing
+            .save(&space(), algorithm, seed, &state, unix_time(), false)
+            .unwrap();
+    }
+
+    #[test]
+    fn a_resumed_run_ends_like_an_uninterrupted_one() {
+        for algorithm in algorithms() {
+            let uninterrupted = minimize(&space(), &algorithm, cost, 7, None).unwrap();
+
+            let checkpointing = checkpointing(&format!("resume_{}", algorithm.name()));
+            interrupt(&checkpointing, &algorithm, 3, 7);
+            let resumed = minimize(&space(), &algorithm, cost, 7, Some(&checkpointing));
+
+            let saved = Checkpoint::load(&checkpointing.path);
+            let _ = std::fs::remove_file(&checkpointing.path);
+
+            assert_eq!(resumed.unwrap(), uninterrupted, "{}", algorithm.name());
+            assert!(saved.unwrap().finished);
+        }
+    }
+
+    #[test]
+    fn resumes_after_invalid_evaluations() {
+        for algorithm in algorithms() {
+            let checkpointing = checkpointing(&format!("invalid_{}", algorithm.name()));
+            interrupt(&checkpointing, &algorithm, 2, 11);
+
+            // os custos inválidos da população salva não podem deixar o arquivo ilegível
+            let saved = Checkpoint::load(&checkpointing.path);
+            let resumed = minimize(&space(), &algorithm, cost, 11, Some(&checkpointing));
+            let _ = std::fs::remove_file(&checkpointing.path);
+
+            let history = saved.unwrap().state.outcome().3;
+            assert!(history.iter().all(|record| record.mean_cost.is_finite()));
+            let resumed = resumed.unwrap();
+            assert!(resumed.best_cost < INVALID_COST);
+            assert!(resumed.history.iter().all(|record| record.mean_cost <= INVALID_COST));
+        }
+    }
+
+    #[test]
+    fn a_checkpoint_of_another_run_is_refused() {
+        let algorithm = &algorithms()[0];
+        let checkpointing = checkpointing("mismatch");
+        interrupt(&checkpointing, algorithm, 1, 3);
+
+        let other_seed = checkpointing.resume(&space(), algorithm, 4);
+        let other_space = checkpointing.resume(&space().with_bound("tie_frequency", 0.1, 0.5), algorithm, 3);
+        let same = checkpointing.resume(&space(), algorithm, 3);
+        let _ = std::fs::remove_file(&checkpointing.path);
+
+        assert!(matches!(other_seed, Err(CheckpointError::Mismatch { field: "seed", .. })));
+        assert!(matches!(other_space, Err(CheckpointError::Mismatch { field: "parameter space", .. })));
+        assert!(same.unwrap().is_some());
+    }
+}
@@ -0,0 +1,220 @@
+use nalgebra::{DMatrix, DVector, SymmetricEigen};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{evaluate_population, iteration_record, standard_normal, IterationRecord, ParameterSpace, SearchOutcome};
+
+/// (μ/μ_w, λ) CMA-ES with the default weights and learning rates of Hansen's tutorial. It starts at the
+/// base config of the space, and samples outside the unit cube are clamped back before being evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CmaEsOptions {
+    /// λ, 0 uses the default `4 + 3 ln(n)`
+    pub population: usize,
+    pub generations: usize,
+    /// Initial step size, as a fraction of the unit cube
+    pub sigma: f64,
+}
+
+impl Default for CmaEsOptions {
+    fn default() -> Self {
+        CmaEsOptions {
+            population: 0,
+            generations: 20,
+            sigma: 0.3,
+        }
+    }
+}
+
+/// Population size, weights and learning rates, which only depend on the options and the dimension
+struct Strategy {
+    lambda: usize,
+    mu: usize,
+    weights: Vec<f64>,
+    mu_eff: f64,
+    cc: f64,
+    cs: f64,
+    c1: f64,
+    cmu: f64,
+    damps: f64,
+    chi_n: f64,
+}
+
+impl Strategy {
+    fn new(n: usize, options: &CmaEsOptions) -> Self {
+        let nf = n as f64;
+        let lambda = match options.population {
+            0 => 4 + (3.0 * nf.ln()).floor() as usize,
+            population => population.max(2),
+        };
+        let mu = lambda / 2;
+
+        let raw_weights: Vec<f64> = (0..mu)
+            .map(|i| (mu as f64 + 0.5).ln() - ((i + 1) as f64).ln())
+            .collect();
+        let weight_sum: f64 = raw_weights.iter().sum();
+        let weights: Vec<f64> = raw_weights.iter().map(|w| w / weight_sum).collect();
+        let mu_eff = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+
+        let cs = (mu_eff + 2.0) / (nf + mu_eff + 5.0);
+        let c1 = 2.0 / ((nf + 1.3).powi(2) + mu_eff);
+        Strategy {
+            lambda,
+            mu,
+            cc: (4.0 + mu_eff / nf) / (nf + 4.0 + 2.0 * mu_eff / nf),
+            cs,
+            c1,
+            cmu: (1.0 - c1).min(2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((nf + 2.0).powi(2) + mu_eff)),
+            damps: 1.0 + 2.0 * (((mu_eff - 1.0) / (nf + 1.0)).sqrt() - 1.0).max(0.0) + cs,
+            chi_n: nf.sqrt() * (1.0 - 1.0 / (4.0 * nf) + 1.0 / (21.0 * nf * nf)),
+            weights,
+            mu_eff,
+        }
+    }
+}
+
+/// Distribution and evolution paths between generations, kept in the checkpoints. The matrices are
+/// stored column by column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CmaEsState {
+    #[serde(with = "super::checkpoint::rng_state")]
+    rng: ChaCha12Rng,
+    generation: usize,
+    mean: Vec<f64>,
+    sigma: f64,
+    covariance: Vec<f64>,
+    path_sigma: Vec<f64>,
+    path_c: Vec<f64>,
+    /// Best position and cost so far, none before the first generation
+    best: Option<(Vec<f64>, f64)>,
+    evaluations: usize,
+    history: Vec<IterationRecord>,
+}
+
+impl CmaEsState {
+    /// Distribution centered on the base config. Nothing is evaluated until the first generation
+    pub(crate) fn new(space: &ParameterSpace, options: &CmaEsOptions, seed: u64) -> Self {
+        let n = space.dimensions();
+        CmaEsState {
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            generation: 0,
+            mean: space.to_unit(&space.base),
+            sigma: options.sigma,
+            covariance: DMatrix::<f64>::identity(n, n).as_slice().to_vec(),
+            path_sigma: vec![0.0; n],
+            path_c: vec![0.0; n],
+            best: None,
+            evaluations: 0,
+            history: Vec::new(),
+        }
+    }
+
+    pub(crate) fn iteration(&self) -> usize {
+        self.generation
+    }
+
+    /// Samples, evaluates and updates the distribution once
+    pub(crate) fn step<F>(&mut self, options: &CmaEsOptions, cost: &F)
+    where
+        F: Fn(&[f64]) -> f64 + Sync,
+    {
+        let n = self.mean.len();
+        let nf = n as f64;
+        let Strategy {
+            lambda,
+            mu,
+            weights,
+            mu_eff,
+            cc,
+            cs,
+            c1,
+            cmu,
+            damps,
+            chi_n,
+        } = Strategy::new(n, options);
+
+        let mut mean = DVector::from_vec(self.mean.clone());
+        let mut covariance = DMatrix::from_vec(n, n, self.covariance.clone());
+        let sigma = self.sigma;
+        let generation = self.generation + 1;
+
+        // C = B D² Bᵀ
+        let eigen = SymmetricEigen::new(covariance.clone());
+        let basis = eigen.eigenvectors;
+        let scales = eigen.eigenvalues.map(|value| value.max(1e-20).sqrt());
+
+        let rng = &mut self.rng;
+        let samples: Vec<DVector<f64>> = (0..lambda)
+            .map(|_| {
+                let z = DVector::from_fn(n, |_, _| standard_normal(rng));
+                let y = &basis * z.component_mul(&scales);
+                (&mean + sigma * y).map(|x| x.clamp(0.0, 1.0))
+            })
+            .collect();
+
+        let positions: Vec<Vec<f64>> = samples.iter().map(|x| x.as_slice().to_vec()).collect();
+        let costs = evaluate_population(cost, &positions);
+        self.evaluations += costs.len();
+
+        let mut ranked: Vec<usize> = (0..lambda).collect();
+        ranked.sort_by(|&a, &b| costs[a].total_cmp(&costs[b]));
+
+        let best_cost = self.best.as_ref().map(|(_, cost)| *cost).unwrap_or(f64::INFINITY);
+        if costs[ranked[0]] < best_cost {
+            self.best = Some((positions[ranked[0]].clone(), costs[ranked[0]]));
+        }
+
+        let best_so_far = self.history.last().map(|record| record.best_so_far).unwrap_or(f64::INFINITY);
+        self.history.push(iteration_record(generation, &costs, best_so_far));
+
+        // os passos são medidos a partir das amostras já corrigidas para dentro do cubo
+        let old_mean = mean.clone();
+        let steps: Vec<DVector<f64>> = ranked
+            .iter()
+            .take(mu)
+            .map(|&i| (&samples[i] - &old_mean) / sigma)
+            .collect();
+        let mean_step = steps
+            .iter()
+            .zip(&weights)
+            .fold(DVector::<f64>::zeros(n), |acc, (step, w)| acc + step * *w);
+        mean = &old_mean + sigma * &mean_step;
+
+        let path_sigma = DVector::from_vec(self.path_sigma.clone());
+        let path_c = DVector::from_vec(self.path_c.clone());
+
+        let inverse_sqrt = &basis * DMatrix::from_diagonal(&scales.map(|s| 1.0 / s)) * basis.transpose();
+        let path_sigma =
+            (1.0 - cs) * &path_sigma + (cs * (2.0 - cs) * mu_eff).sqrt() * (&inverse_sqrt * &mean_step);
+
+        let norm_sigma = path_sigma.norm();
+        let h_sigma = norm_sigma / (1.0 - (1.0 - cs).powi(2 * generation as i32)).sqrt() / chi_n
+            < 1.4 + 2.0 / (nf + 1.0);
+        let h = if h_sigma { 1.0 } else { 0.0 };
+
+        let path_c = (1.0 - cc) * &path_c + h * (cc * (2.0 - cc) * mu_eff).sqrt() * &mean_step;
+
+        let rank_mu = steps
+            .iter()
+            .zip(&weights)
+            .fold(DMatrix::<f64>::zeros(n, n), |acc, (step, w)| acc + *w * step * step.transpose());
+
+        covariance = (1.0 - c1 - cmu) * &covariance
+            + c1 * (&path_c * path_c.transpose() + (1.0 - h) * cc * (2.0 - cc) * &covariance)
+            + cmu * rank_mu;
+        // mantém simétrica apesar dos arredondamentos
+        covariance = (&covariance + covariance.transpose()) * 0.5;
+
+        self.sigma = (sigma * ((cs / damps) * (norm_sigma / chi_n - 1.0)).exp()).min(1.0);
+        self.mean = mean.as_slice().to_vec();
+        self.covariance = covariance.as_slice().to_vec();
+        self.path_sigma = path_sigma.as_slice().to_vec();
+        self.path_c = path_c.as_slice().to_vec();
+        self.generation = generation;
+    }
+
+    pub(crate) fn outcome(&self) -> SearchOutcome {
+        let (best, best_cost) = self.best.clone().unwrap_or_else(|| (self.mean.clone(), f64::INFINITY));
+        (best, best_cost, self.evaluations, self.history.clone())
+    }
+}
@@ -0,0 +1,181 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// Most parameters a [`Dual`] can carry derivatives for
+pub const MAX_PARAMETERS: usize = 12;
+
+/// Forward-mode dual number: a value and its partial derivatives with respect to up to
+/// [`MAX_PARAMETERS`] parameters. Fixed size, so it is `Copy` and the rating loop doesn't allocate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dual {
+    pub value: f64,
+    pub gradient: [f64; MAX_PARAMETERS],
+}
+
+impl Dual {
+    pub fn constant(value: f64) -> Self {
+        Dual {
+            value,
+            gradient: [0.0; MAX_PARAMETERS],
+        }
+    }
+
+    /// The `index`-th parameter, with derivative 1 with respect to itself
+    pub fn variable(value: f64, index: usize) -> Self {
+        let mut gradient = [0.0; MAX_PARAMETERS];
+        gradient[index] = 1.0;
+        Dual { value, gradient }
+    }
+
+    /// Chain rule for a function of one variable, given its value and derivative at `self.value`
+    fn chain(&self, value: f64, derivative: f64) -> Self {
+        Dual {
+            value,
+            gradient: self.gradient.map(|d| d * derivative),
+        }
+    }
+
+    pub fn exp(self) -> Self {
+        let value = self.value.exp();
+        self.chain(value, value)
+    }
+
+    pub fn ln(self) -> Self {
+        self.chain(self.value.ln(), 1.0 / self.value)
+    }
+
+    /// `base^self` for a constant base
+    pub fn exp_base(self, base: f64) -> Self {
+        (self * base.ln()).exp()
+    }
+
+    /// `base^self` for a constant base, as in `(1 + diff).powf(weight)`
+    pub fn pow_of(base: f64, exponent: Dual) -> Self {
+        exponent.exp_base(base)
+    }
+}
+
+fn combine(a: &Dual, b: &Dual, value: f64, da: f64, db: f64) -> Dual {
+    let mut gradient = [0.0; MAX_PARAMETERS];
+    for (i, g) in gradient.iter_mut().enumerate() {
+        *g = a.gradient[i] * da + b.gradient[i] * db;
+    }
+    Dual { value, gradient }
+}
+
+impl Add for Dual {
+    type Output = Dual;
+    fn add(self, rhs: Dual) -> Dual {
+        combine(&self, &rhs, self.value + rhs.value, 1.0, 1.0)
+    }
+}
+
+impl Sub for Dual {
+    type Output = Dual;
+    fn sub(self, rhs: Dual) -> Dual {
+        combine(&self, &rhs, self.value - rhs.value, 1.0, -1.0)
+    }
+}
+
+impl Mul for Dual {
+    type Output = Dual;
+    fn mul(self, rhs: Dual) -> Dual {
+        combine(&self, &rhs, self.value * rhs.value, rhs.value, self.value)
+    }
+}
+
+impl Div for Dual {
+    type Output = Dual;
+    fn div(self, rhs: Dual) -> Dual {
+        let value = self.value / rhs.value;
+        combine(&self, &rhs, value, 1.0 / rhs.value, -value / rhs.value)
+    }
+}
+
+impl Neg for Dual {
+    type Output = Dual;
+    fn neg(self) -> Dual {
+        self.chain(-self.value, -1.0)
+    }
+}
+
+impl Add<f64> for Dual {
+    type Output = Dual;
+    fn add(self, rhs: f64) -> Dual {
+        Dual {
+            value: self.value + rhs,
+            ..self
+        }
+    }
+}
+
+impl Sub<f64> for Dual {
+    type Output = Dual;
+    fn sub(self, rhs: f64) -> Dual {
+        Dual {
+            value: self.value - rhs,
+            ..self
+        }
+    }
+}
+
+impl Mul<f64> for Dual {
+    type Output = Dual;
+    fn mul(self, rhs: f64) -> Dual {
+        self.chain(self.value * rhs, rhs)
+    }
+}
+
+impl Div<f64> for Dual {
+    type Output = Dual;
+    fn div(self, rhs: f64) -> Dual {
+        self.chain(self.value / rhs, 1.0 / rhs)
+    }
+}
+
+impl Sub<Dual> for f64 {
+    type Output = Dual;
+    fn sub(self, rhs: Dual) -> Dual {
+        (-rhs) + self
+    }
+}
+
+impl Mul<Dual> for f64 {
+    type Output = Dual;
+    fn mul(self, rhs: Dual) -> Dual {
+        rhs * self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Uses every operation, with `x` and `y` as parameters 0 and 1
+    fn f(x: Dual, y: Dual) -> Dual {
+        (x * y).exp() / (1.0 - y) + x.ln() * 3.0 - Dual::pow_of(2.0, y) - x / 4.0 + 2.0 * (x - y) + (-y + 1.0)
+    }
+
+    fn f_plain(x: f64, y: f64) -> f64 {
+        (x * y).exp() / (1.0 - y) + x.ln() * 3.0 - 2.0_f64.powf(y) - x / 4.0 + 2.0 * (x - y) + (-y + 1.0)
+    }
+
+    #[test]
+    fn the_gradient_matches_finite_differences() {
+        let (x, y) = (0.7, 0.3);
+        let result = f(Dual::variable(x, 0), Dual::variable(y, 1));
+        assert!((result.value - f_plain(x, y)).abs() < 1e-12);
+
+        let step = 1e-6;
+        let dx = (f_plain(x + step, y) - f_plain(x - step, y)) / (2.0 * step);
+        let dy = (f_plain(x, y + step) - f_plain(x, y - step)) / (2.0 * step);
+        assert!((result.gradient[0] - dx).abs() < 1e-7, "{} != {}", result.gradient[0], dx);
+        assert!((result.gradient[1] - dy).abs() < 1e-7, "{} != {}", result.gradient[1], dy);
+        assert!(result.gradient[2..].iter().all(|&g| g == 0.0));
+    }
+
+    #[test]
+    fn constants_have_no_gradient() {
+        let result = f(Dual::constant(0.7), Dual::constant(0.3));
+        assert!(result.gradient.iter().all(|&g| g == 0.0));
+    }
+}
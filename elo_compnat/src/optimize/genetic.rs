@@ -0,0 +1,173 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    best_of, evaluate_population, iteration_record, standard_normal, IterationRecord, ParameterSpace, SearchOutcome,
+};
+
+/// Real-coded genetic algorithm: tournament selection, blend crossover (BLX-α) and gaussian mutation,
+/// with the best individuals carried over untouched
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeneticOptions {
+    pub population: usize,
+    pub generations: usize,
+    pub tournament_size: usize,
+    pub crossover_rate: f64,
+    /// How far outside the interval between the parents a child gene may land
+    pub blend_alpha: f64,
+    /// Chance of each gene being mutated
+    pub mutation_rate: f64,
+    /// Standard deviation of a mutation, as a fraction of the unit cube
+    pub mutation_sigma: f64,
+    /// Individuals copied to the next generation as they are
+    pub elitism: usize,
+}
+
+impl Default for GeneticOptions {
+    fn default() -> Self {
+        GeneticOptions {
+            population: 20,
+            generations: 10,
+            tournament_size: 3,
+            crossover_rate: 0.9,
+            blend_alpha: 0.5,
+            mutation_rate: 0.1,
+            mutation_sigma: 0.1,
+            elitism: 1,
+        }
+    }
+}
+
+/// Population and best individual between generations, kept in the checkpoints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneticState {
+    #[serde(with = "super::checkpoint::rng_state")]
+    rng: ChaCha12Rng,
+    generation: usize,
+    population: Vec<Vec<f64>>,
+    costs: Vec<f64>,
+    best: Vec<f64>,
+    best_cost: f64,
+    evaluations: usize,
+    history: Vec<IterationRecord>,
+}
+
+impl GeneticState {
+    /// Initial population, already evaluated
+    pub(crate) fn new<F>(space: &ParameterSpace, options: &GeneticOptions, cost: &F, seed: u64) -> Self
+    where
+        F: Fn(&[f64]) -> f64 + Sync,
+    {
+        let dimensions = space.dimensions();
+        let population_size = options.population.max(2);
+        let mut rng = ChaCha12Rng::seed_from_u64(seed);
+
+        let population: Vec<Vec<f64>> = (0..population_size)
+            .map(|i| match i {
+                0 => space.to_unit(&space.base),
+                _ => (0..dimensions).map(|_| rng.gen::<f64>()).collect(),
+            })
+            .collect();
+
+        let costs = evaluate_population(cost, &population);
+        let (best, best_cost) = best_of(&population, &costs);
+
+        GeneticState {
+            rng,
+            generation: 0,
+            evaluations: costs.len(),
+            history: vec![iteration_record(0, &costs, f64::INFINITY)],
+            population,
+            costs,
+            best,
+            best_cost,
+        }
+    }
+
+    pub(crate) fn iteration(&self) -> usize {
+        self.generation
+    }
+
+    /// Breeds and evaluates the next generation
+    pub(crate) fn step<F>(&mut self, options: &GeneticOptions, cost: &F)
+    where
+        F: Fn(&[f64]) -> f64 + Sync,
+    {
+        let population_size = self.population.len();
+        let mut ranked: Vec<usize> = (0..population_size).collect();
+        ranked.sort_by(|&a, &b| self.costs[a].total_cmp(&self.costs[b]));
+
+        let mut next: Vec<Vec<f64>> = ranked
+            .iter()
+            .take(options.elitism.min(population_size))
+            .map(|&i| self.population[i].clone())
+            .collect();
+
+        while next.len() < population_size {
+            let first = tournament(&mut self.rng, &self.costs, options.tournament_size);
+            let second = tournament(&mut self.rng, &self.costs, options.tournament_size);
+
+            let mut child = if self.rng.gen::<f64>() < options.crossover_rate {
+                blend(&mut self.rng, &self.population[first], &self.population[second], options.blend_alpha)
+            } else {
+                self.population[first].clone()
+            };
+
+            mutate(&mut self.rng, &mut child, options.mutation_rate, options.mutation_sigma);
+            next.push(child);
+        }
+
+        self.population = next;
+        self.costs = evaluate_population(cost, &self.population);
+        self.evaluations += self.costs.len();
+
+        let (generation_best, generation_cost) = best_of(&self.population, &self.costs);
+        if generation_cost < self.best_cost {
+            self.best = generation_best;
+            self.best_cost = generation_cost;
+        }
+
+        self.generation += 1;
+        let best_so_far = self.history.last().map(|record| record.best_so_far).unwrap_or(f64::INFINITY);
+        self.history.push(iteration_record(self.generation, &self.costs, best_so_far));
+    }
+
+    pub(crate) fn outcome(&self) -> SearchOutcome {
+        (self.best.clone(), self.best_cost, self.evaluations, self.history.clone())
+    }
+}
+
+/// Index of the lowest cost among `size` individuals drawn at random
+fn tournament<R: Rng>(rng: &mut R, costs: &[f64], size: usize) -> usize {
+    (0..size.max(1))
+        .map(|_| rng.gen_range(0..costs.len()))
+        .min_by(|&a, &b| costs[a].total_cmp(&costs[b]))
+        .unwrap()
+}
+
+/// Gaussian mutation of each gene with probability `rate`, keeping the child inside the unit cube
+pub(crate) fn mutate<R: Rng>(rng: &mut R, child: &mut [f64], rate: f64, sigma: f64) {
+    for gene in child.iter_mut() {
+        if rng.gen::<f64>() < rate {
+            *gene += sigma * standard_normal(rng);
+        }
+        *gene = gene.clamp(0.0, 1.0);
+    }
+}
+
+/// BLX-α: each gene is drawn from the interval between the parents, widened by `alpha` on each side
+pub(crate) fn blend<R: Rng>(rng: &mut R, first: &[f64], second: &[f64], alpha: f64) -> Vec<f64> {
+    first
+        .iter()
+        .zip(second)
+        .map(|(&a, &b)| {
+            let (low, high) = (a.min(b), a.max(b));
+            let spread = (high - low) * alpha;
+            if high - low + 2.0 * spread <= 0.0 {
+                return low;
+            }
+            rng.gen_range((low - spread)..=(high + spread))
+        })
+        .collect()
+}
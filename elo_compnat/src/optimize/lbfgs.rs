@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+/// Limited-memory BFGS with a backtracking (Armijo) line search. Points where the function is not finite
+/// are treated as outside the domain and the step is shortened until it lands back inside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LbfgsOptions {
+    /// Number of `(s, y)` pairs kept to approximate the inverse Hessian
+    pub memory: usize,
+    pub max_iterations: usize,
+    /// Stops when the largest component of the gradient is below this
+    pub gradient_tolerance: f64,
+    /// Stops when an iteration improves the value by less than this fraction of it
+    pub value_tolerance: f64,
+}
+
+impl Default for LbfgsOptions {
+    fn default() -> Self {
+        LbfgsOptions {
+            memory: 10,
+            max_iterations: 100,
+            gradient_tolerance: 1e-4,
+            value_tolerance: 1e-12,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LbfgsOutcome {
+    pub position: Vec<f64>,
+    pub value: f64,
+    pub gradient: Vec<f64>,
+    pub iterations: usize,
+    pub evaluations: usize,
+    pub converged: bool,
+    /// Value after each iteration, starting with the value at the initial point
+    pub history: Vec<f64>,
+}
+
+const ARMIJO: f64 = 1e-4;
+const MAX_BACKTRACKS: usize = 50;
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn max_abs(values: &[f64]) -> f64 {
+    values.iter().fold(0.0, |acc, v| acc.max(v.abs()))
+}
+
+/// Minimizes `f`, which returns the value and the gradient at a point, starting at `start`
+pub fn lbfgs<F>(f: F, start: &[f64], options: &LbfgsOptions) -> LbfgsOutcome
+where
+    F: Fn(&[f64]) -> (f64, Vec<f64>),
+{
+    let mut position = start.to_vec();
+    let (mut value, mut gradient) = f(&position);
+    let mut evaluations = 1;
+    let mut history = vec![value];
+    let mut pairs: VecDeque<(Vec<f64>, Vec<f64>, f64)> = VecDeque::with_capacity(options.memory);
+
+    let mut iterations = 0;
+    let mut converged = value.is_finite() && max_abs(&gradient) < options.gradient_tolerance;
+
+    while !converged && value.is_finite() && iterations < options.max_iterations {
+        // two-loop recursion: direction = -H·g
+        let mut q = gradient.clone();
+        let mut alphas = Vec::with_capacity(pairs.len());
+        for (s, y, rho) in pairs.iter().rev() {
+            let alpha = rho * dot(s, &q);
+            q.iter_mut().zip(y).for_each(|(qi, yi)| *qi -= alpha * yi);
+            alphas.push(alpha);
+        }
+
+        // sem histórico o primeiro passo anda no máximo uma unidade
+        let scale = match pairs.back() {
+            Some((s, y, _)) => dot(s, y) / dot(y, y),
+            None => 1.0 / max_abs(&gradient).max(1.0),
+        };
+        q.iter_mut().for_each(|qi| *qi *= scale);
+
+        for ((s, y, rho), alpha) in pairs.iter().zip(alphas.iter().rev()) {
+            let beta = rho * dot(y, &q);
+            q.iter_mut().zip(s).for_each(|(qi, si)| *qi += (alpha - beta) * si);
+        }
+        let mut direction: Vec<f64> = q.iter().map(|qi| -qi).collect();
+
+        let mut slope = dot(&gradient, &direction);
+        if slope >= 0.0 {
+            // a aproximação deixou de ser positiva definida, recomeça pelo gradiente
+            pairs.clear();
+            let scale = 1.0 / max_abs(&gradient).max(1.0);
+            direction = gradient.iter().map(|g| -g * scale).collect();
+            slope = dot(&gradient, &direction);
+        }
+
+        let mut step = 1.0;
+        let mut accepted = None;
+        for _ in 0..MAX_BACKTRACKS {
+            let candidate: Vec<f64> = position.iter().zip(&direction).map(|(x, d)| x + step * d).collect();
+            let (candidate_value, candidate_gradient) = f(&candidate);
+            evaluations += 1;
+
+            let inside = candidate_value.is_finite() && candidate_gradient.iter().all(|g| g.is_finite());
+            if inside && candidate_value <= value + ARMIJO * step * slope {
+                accepted = Some((candidate, candidate_value, candidate_gradient));
+                break;
+            }
+            step *= 0.5;
+        }
+
+        let Some((next, next_value, next_gradient)) = accepted else {
+            break;
+        };
+
+        let s: Vec<f64> = next.iter().zip(&position).map(|(a, b)| a - b).collect();
+        let y: Vec<f64> = next_gradient.iter().zip(&gradient).map(|(a, b)| a - b).collect();
+        let curvature = dot(&s, &y);
+        if curvature > 1e-12 {
+            if pairs.len() == options.memory.max(1) {
+                pairs.pop_front();
+            }
+            pairs.push_back((s, y, 1.0 / curvature));
+        }
+
+        let improvement = value - next_value;
+        position = next;
+        value = next_value;
+        gradient = next_gradient;
+        iterations += 1;
+        history.push(value);
+
+        converged = max_abs(&gradient) < options.gradient_tolerance
+            || improvement <= options.value_tolerance * value.abs().max(1.0);
+    }
+
+    LbfgsOutcome {
+        position,
+        value,
+        gradient,
+        iterations,
+        evaluations,
+        converged,
+        history,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `0.5 xᵀAx - bᵀx` with a diagonal-dominant `A`, minimized at `A⁻¹b`
+    fn quadratic(x: &[f64]) -> (f64, Vec<f64>) {
+        let a = [[4.0, 1.0, 0.0], [1.0, 3.0, 0.5], [0.0, 0.5, 2.0]];
+        let b = [1.0, 2.0, -1.0];
+
+        let ax: Vec<f64> = a.iter().map(|row| dot(row, x)).collect();
+        let value = 0.5 * dot(x, &ax) - dot(&b, x);
+        let gradient = ax.iter().zip(b).map(|(axi, bi)| axi - bi).collect();
+        (value, gradient)
+    }
+
+    #[test]
+    fn finds_the_minimum_of_a_quadratic() {
+        let options = LbfgsOptions {
+            gradient_tolerance: 1e-10,
+            ..LbfgsOptions::default()
+        };
+        let outcome = lbfgs(quadratic, &[5.0, -3.0, 10.0], &options);
+
+        assert!(outcome.converged);
+        let (_, gradient) = quadratic(&outcome.position);
+        assert!(max_abs(&gradient) < 1e-8);
+        // A·x = b resolvido à mão
+        let expected = [5.0 / 84.0, 16.0 / 21.0, -29.0 / 42.0];
+        for (x, e) in outcome.position.iter().zip(expected) {
+            assert!((x - e).abs() < 1e-8, "{} != {}", x, e);
+        }
+        assert!(outcome.history.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(outcome.history.len(), outcome.iterations + 1);
+    }
+
+    #[test]
+    fn stays_inside_the_domain() {
+        // x - ln(x), infinito fora de x > 0, com mínimo em 1
+        let f = |x: &[f64]| {
+            if x[0] <= 0.0 {
+                (f64::INFINITY, vec![f64::NAN])
+            } else {
+                (x[0] - x[0].ln(), vec![1.0 - 1.0 / x[0]])
+            }
+        };
+        let outcome = lbfgs(f, &[0.01], &LbfgsOptions::default());
+
+        assert!(outcome.converged);
+        assert!((outcome.position[0] - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn a_start_at_the_minimum_needs_no_iteration() {
+        let outcome = lbfgs(|x: &[f64]| (x[0] * x[0], vec![2.0 * x[0]]), &[0.0], &LbfgsOptions::default());
+
+        assert!(outcome.converged);
+        assert_eq!(outcome.iterations, 0);
+        assert_eq!(outcome.evaluations, 1);
+    }
+}
@@ -0,0 +1,644 @@
+use std::collections::{BTreeMap, HashMap};
+
+use nalgebra::DMatrix;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use super::dual::{Dual, MAX_PARAMETERS};
+use super::lbfgs::{lbfgs, LbfgsOptions};
+use super::space::{get_field, set_field};
+use crate::elo::util::season::{construct_seasons, Season};
+use crate::experimentation::run_config::{ActiveComponents, RunConfig, RunHyperparameters};
+use crate::util::game::{Game, GameResult};
+
+/// Fields fitted when none are given: the scale of the updates, the home advantage (if it is on) and
+/// the draw rate
+pub fn default_parameters(components: &ActiveComponents) -> Vec<String> {
+    let mut parameters = vec![String::from("k_factor")];
+    if components.home_advantage {
+        parameters.push(String::from("home_advantage"));
+    }
+    parameters.push(String::from("tie_frequency"));
+    parameters
+}
+
+/// The fields of [`RunConfig`] the training depends on, as dual numbers. Only the fitted ones carry
+/// derivatives
+struct DualConfig {
+    k_factor: Dual,
+    gamma: Dual,
+    home_advantage: Dual,
+    market_value_weight: Dual,
+    tie_frequency: Dual,
+    season_regression: Dual,
+    w_division: Vec<Dual>,
+}
+
+/// Log-likelihood of the observed results (H/D/A) under the pre-match probabilities of the elo, over the
+/// training seasons `starting_year..=starting_year + backtest_years`.
+///
+/// The walk is the same as [`construct_rated_games_for_time_series`](crate::elo::train::construct_rated_games_for_time_series),
+/// season boundaries included, but done with [`Dual`] numbers so the gradient with respect to the fitted
+/// fields comes out exact along with the value. Like the training, it doesn't apply `after_season`, so
+/// `home_field_advantage_weight` can't be fitted
+pub struct LikelihoodProblem {
+    base: RunConfig,
+    parameters: Vec<String>,
+    components: ActiveComponents,
+    starting_elo: f64,
+    seasons: Vec<Season>,
+    matches: usize,
+}
+
+impl LikelihoodProblem {
+    pub fn new(
+        all_games: &[Game],
+        experiment_config: &RunHyperparameters,
+        base: RunConfig,
+        parameters: Vec<String>,
+    ) -> Result<Self, String> {
+        let components = experiment_config.components();
+        let used_games: Vec<Game> = all_games
+            .iter()
+            .filter(|game| components.uses_division(game.division))
+            .cloned()
+            .collect();
+
+        let mut seasons_map = construct_seasons(&used_games);
+        let end_year = experiment_config.starting_year + experiment_config.backtest_years;
+        let seasons = (experiment_config.starting_year..=end_year)
+            .map(|year| {
+                seasons_map
+                    .remove(&year)
+                    .ok_or_else(|| format!("Season {} is not in the dataset", year))
+            })
+            .collect::<Result<Vec<Season>, String>>()?;
+
+        let matches = seasons.iter().map(|season| season.matches.len()).sum();
+        let problem = LikelihoodProblem {
+            base,
+            parameters,
+            components,
+            starting_elo: experiment_config.starting_elo as f64,
+            seasons,
+            matches,
+        };
+        problem.validate()?;
+        Ok(problem)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.parameters.is_empty() {
+            return Err(String::from("No parameter to fit"));
+        }
+        if self.parameters.len() > MAX_PARAMETERS {
+            return Err(format!("At most {} parameters can be fitted at once", MAX_PARAMETERS));
+        }
+
+        let divisions: Vec<u8> = {
+            let mut divisions: Vec<u8> = self
+                .seasons
+                .iter()
+                .flat_map(|season| season.divisions.keys().copied())
+                .collect();
+            divisions.sort_unstable();
+            divisions.dedup();
+            divisions
+        };
+        if let Some(&division) = divisions.iter().find(|&&d| d as usize > self.base.w_division.len()) {
+            return Err(format!(
+                "The training seasons have division {}, but w_division only has {} entries",
+                division,
+                self.base.w_division.len()
+            ));
+        }
+
+        for (i, name) in self.parameters.iter().enumerate() {
+            if self.parameters[..i].contains(name) {
+                return Err(format!("Parameter '{}' is listed twice", name));
+            }
+
+            let mut config = self.base.clone();
+            set_field(&mut config, name, 0.0)?;
+
+            // campos que não mexem na verossimilhança dariam uma hessiana singular
+            let unused = match name.as_str() {
+                "home_field_advantage_weight" => Some("it only acts between simulated seasons"),
+                "gamma" if !self.components.goals_diff => Some("use_goals_diff is off"),
+                "home_advantage" if !self.components.home_advantage => Some("use_home_advantage is off"),
+                "market_value_weight" if !self.components.market_values => Some("use_market_values is off"),
+                other => other
+                    .strip_prefix("w_division_")
+                    .and_then(|index| index.parse::<u8>().ok())
+                    .filter(|index| !divisions.contains(&(index + 1)))
+                    .map(|_| "the training seasons have no match in that division"),
+            };
+            if let Some(reason) = unused {
+                return Err(format!("'{}' can't be fitted: {}", name, reason));
+            }
+        }
+
+        let all_weights_fitted = (0..divisions.len()).all(|i| self.parameters.contains(&format!("w_division_{}", i)));
+        if self.parameters.iter().any(|name| name == "k_factor") && all_weights_fitted {
+            return Err(String::from(
+                "k_factor and every w_division can't be fitted together, only their product matters",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn parameters(&self) -> &[String] {
+        &self.parameters
+    }
+
+    /// Number of matches in the training seasons
+    pub fn matches(&self) -> usize {
+        self.matches
+    }
+
+    /// Values of the fitted fields in `config`, in the order of [`Self::parameters`]
+    pub fn values_of(&self, config: &RunConfig) -> Vec<f64> {
+        self.parameters
+            .iter()
+            .map(|name| get_field(config, name).unwrap_or(f64::NAN))
+            .collect()
+    }
+
+    /// The base config with the fitted fields set to `values`
+    pub fn config_at(&self, values: &[f64]) -> RunConfig {
+        let mut config = self.base.clone();
+        for (name, &value) in self.parameters.iter().zip(values) {
+            // os nomes foram checados em validate
+            let _ = set_field(&mut config, name, value);
+        }
+        config
+    }
+
+    fn dual_config(&self, values: &[f64]) -> DualConfig {
+        let config = self.config_at(values);
+        let field = |name: &str, value: f64| match self.parameters.iter().position(|p| p == name) {
+            Some(index) => Dual::variable(value, index),
+            None => Dual::constant(value),
+        };
+
+        DualConfig {
+            k_factor: field("k_factor", config.k_factor),
+            gamma: field("gamma", config.gamma),
+            home_advantage: field("home_advantage", config.home_advantage),
+            market_value_weight: field("market_value_weight", config.market_value_weight),
+            tie_frequency: field("tie_frequency", config.tie_frequency),
+            season_regression: field("season_regression", config.season_regression),
+            w_division: config
+                .w_division
+                .iter()
+                .enumerate()
+                .map(|(i, &w)| field(&format!("w_division_{}", i), w))
+                .collect(),
+        }
+    }
+
+    /// Log-likelihood at `values` of the fitted fields, with its gradient in the first
+    /// `parameters().len()` entries of `Dual::gradient`
+    pub fn log_likelihood(&self, values: &[f64]) -> Dual {
+        let config = self.dual_config(values);
+        let starting_rating = Dual::constant(self.starting_elo);
+
+        let mut ratings: HashMap<String, Dual> = HashMap::new();
+        let mut total = Dual::constant(0.0);
+        let mut previous: Option<&Season> = None;
+
+        for season in &self.seasons {
+            if let Some(finished) = previous {
+                ratings = self.start_next_season(ratings, finished, season, &config);
+            }
+            previous = Some(season);
+
+            for game in &season.matches {
+                let home = ratings.get(&game.home).copied().unwrap_or(starting_rating);
+                let away = ratings.get(&game.away).copied().unwrap_or(starting_rating);
+
+                let (prob_draw, prob_home, prob_away) = self.probabilities(home, away, &config);
+                let (observed, home_score) = match game.result {
+                    GameResult::H => (prob_home, 1.0),
+                    GameResult::D => (prob_draw, 0.5),
+                    GameResult::A => (prob_away, 0.0),
+                };
+                total = total + observed.ln();
+
+                let change = self.change_factor(game, &config);
+                ratings.insert(game.home.clone(), home + change * (home_score - prob_home));
+                ratings.insert(game.away.clone(), away + change * ((1.0 - home_score) - prob_away));
+            }
+        }
+
+        total
+    }
+
+    /// Draw, home and away probabilities, as in [`expected_score`](crate::experimentation::run_config::expected_score)
+    fn probabilities(&self, home: Dual, away: Dual, config: &DualConfig) -> (Dual, Dual, Dual) {
+        let home_advantage = if self.components.home_advantage {
+            config.home_advantage
+        } else {
+            Dual::constant(0.0)
+        };
+
+        let tie = config.tie_frequency;
+        let kappa = tie * 2.0 / (1.0 - tie);
+        let exponent = (home + home_advantage - away) / 400.0;
+        let one = exponent.exp_base(10.0);
+        let two = (-exponent).exp_base(10.0);
+        let denominator = one + two + kappa;
+
+        (kappa / denominator, one / denominator, two / denominator)
+    }
+
+    /// Everything that multiplies `score - expected` in [`CustomElo::rate`](crate::experimentation::run_config::CustomElo::rate)
+    fn change_factor(&self, game: &Game, config: &DualConfig) -> Dual {
+        let mut factor = config.k_factor * config.w_division[game.division as usize - 1];
+
+        if self.components.market_values {
+            let market_value_diff = (game.home_value - game.away_value).abs();
+            factor = factor * Dual::pow_of(1.0 + market_value_diff, config.market_value_weight);
+        }
+        if self.components.goals_diff {
+            let normalized_goal_diff = match (game.home_score as i16 - game.away_score as i16).unsigned_abs() {
+                1 => 0.25,
+                2 => 0.5,
+                3 => 0.75,
+                _ => 1.0,
+            };
+            factor = factor * Dual::pow_of(1.0 + normalized_goal_diff, config.gamma);
+        }
+
+        factor
+    }
+
+    /// Same as [`start_next_season`](crate::elo::train::start_next_season) for the elo
+    fn start_next_season(
+        &self,
+        mut ratings: HashMap<String, Dual>,
+        finished: &Season,
+        next: &Season,
+        config: &DualConfig,
+    ) -> HashMap<String, Dual> {
+        let before: BTreeMap<String, u8> = finished.team_divisions().into_iter().collect();
+        let after = next.team_divisions();
+
+        let division_mean = |ratings: &HashMap<String, Dual>, keep: &dyn Fn(&str, u8) -> bool| {
+            let mut sums: HashMap<u8, (Dual, usize)> = HashMap::new();
+            for (team, &division) in &before {
+                if let Some(&rating) = ratings.get(team).filter(|_| keep(team, division)) {
+                    let (sum, count) = sums.entry(division).or_insert((Dual::constant(0.0), 0));
+                    *sum = *sum + rating;
+                    *count += 1;
+                }
+            }
+            sums.into_iter()
+                .map(|(division, (sum, count))| (division, sum / count as f64))
+                .collect::<HashMap<u8, Dual>>()
+        };
+
+        // mesmo com regressão zero, a derivativa em relação a ela conta
+        let regression = config.season_regression;
+        let means = division_mean(&ratings, &|_, _| true);
+        for (team, division) in &before {
+            if let (Some(rating), Some(&target)) = (ratings.get_mut(team), means.get(division)) {
+                *rating = *rating + regression * (target - *rating);
+            }
+        }
+
+        let dropped_mean = division_mean(&ratings, &|team, division| {
+            after.get(team).is_none_or(|&next_division| next_division > division)
+        });
+
+        let starting_rating = Dual::constant(self.starting_elo);
+        for (team, division) in &after {
+            if before.contains_key(team) {
+                continue;
+            }
+            match dropped_mean.get(division) {
+                Some(&value) => {
+                    ratings.insert(team.clone(), value);
+                }
+                None => {
+                    ratings.entry(team.clone()).or_insert(starting_rating);
+                }
+            }
+        }
+
+        ratings
+    }
+
+    /// Negative log-likelihood and its gradient, what L-BFGS minimizes
+    fn cost(&self, values: &[f64]) -> (f64, Vec<f64>) {
+        let log_likelihood = self.log_likelihood(values);
+        let gradient = log_likelihood.gradient[..self.parameters.len()]
+            .iter()
+            .map(|g| -g)
+            .collect();
+        (-log_likelihood.value, gradient)
+    }
+
+    /// Observed information (Hessian of the negative log-likelihood) at `values`, by central differences
+    /// of the exact gradient
+    pub fn observed_information(&self, values: &[f64]) -> DMatrix<f64> {
+        let n = values.len();
+        let mut hessian = DMatrix::<f64>::zeros(n, n);
+
+        for j in 0..n {
+            let step = f64::EPSILON.cbrt() * values[j].abs().max(1.0);
+            let mut forward = values.to_vec();
+            let mut backward = values.to_vec();
+            forward[j] += step;
+            backward[j] -= step;
+
+            let (_, gradient_forward) = self.cost(&forward);
+            let (_, gradient_backward) = self.cost(&backward);
+            for i in 0..n {
+                hessian[(i, j)] = (gradient_forward[i] - gradient_backward[i]) / (2.0 * step);
+            }
+        }
+
+        (&hessian + hessian.transpose()) * 0.5
+    }
+}
+
+/// One fitted field of the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct FittedParameter {
+    pub name: String,
+    pub estimate: f64,
+    /// From the inverse of the observed information. NaN when it isn't positive definite
+    pub standard_error: f64,
+    /// Derivative of the log-likelihood at the estimate, close to zero when the fit converged
+    pub gradient: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MleFit {
+    pub config: RunConfig,
+    pub parameters: Vec<FittedParameter>,
+    pub log_likelihood: f64,
+    pub matches: usize,
+    pub iterations: usize,
+    pub evaluations: usize,
+    pub converged: bool,
+    /// Asymptotic covariance of the estimates, in the order of `parameters`. `None` when the observed
+    /// information can't be inverted, usually because some fields are not identified
+    pub covariance: Option<DMatrix<f64>>,
+    /// Log-likelihood after each iteration
+    pub history: Vec<f64>,
+}
+
+impl MleFit {
+    /// Mean negative log-likelihood per match, the log loss of the training seasons
+    pub fn mean_log_loss(&self) -> f64 {
+        -self.log_likelihood / self.matches.max(1) as f64
+    }
+
+    pub fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("best_config", Py::new(py, self.config.clone())?)?;
+        dict.set_item("log_likelihood", self.log_likelihood)?;
+        dict.set_item("mean_log_loss", self.mean_log_loss())?;
+        dict.set_item("matches", self.matches)?;
+        dict.set_item("iterations", self.iterations)?;
+        dict.set_item("evaluations", self.evaluations)?;
+        dict.set_item("converged", self.converged)?;
+
+        let estimates = PyDict::new(py);
+        let standard_errors = PyDict::new(py);
+        let gradient = PyDict::new(py);
+        for parameter in &self.parameters {
+            estimates.set_item(&parameter.name, parameter.estimate)?;
+            standard_errors.set_item(&parameter.name, parameter.standard_error)?;
+            gradient.set_item(&parameter.name, parameter.gradient)?;
+        }
+        dict.set_item("estimates", estimates)?;
+        dict.set_item("standard_errors", standard_errors)?;
+        dict.set_item("gradient", gradient)?;
+
+        let covariance = self.covariance.as_ref().map(|covariance| {
+            covariance
+                .row_iter()
+                .map(|row| row.iter().copied().collect::<Vec<f64>>())
+                .collect::<Vec<_>>()
+        });
+        dict.set_item("covariance", covariance)?;
+        dict.set_item("history", self.history.clone())?;
+
+        Ok(dict.into())
+    }
+}
+
+/// Maximum-likelihood estimate of the `parameters` of `base` over the training seasons, starting from the
+/// values in `base`. Standard errors come from the inverse of the observed information at the estimate
+pub fn fit_mle(
+    all_games: &[Game],
+    experiment_config: &RunHyperparameters,
+    base: RunConfig,
+    parameters: Option<Vec<String>>,
+    options: &LbfgsOptions,
+) -> Result<MleFit, String> {
+    let parameters = parameters.unwrap_or_else(|| default_parameters(&experiment_config.components()));
+    let problem = LikelihoodProblem::new(all_games, experiment_config, base, parameters)?;
+
+    let start = problem.values_of(&problem.base);
+    let (start_value, _) = problem.cost(&start);
+    if !start_value.is_finite() {
+        return Err(String::from(
+            "The log-likelihood is not finite at the starting config, check tie_frequency is in (0, 1)",
+        ));
+    }
+
+    let outcome = lbfgs(|values| problem.cost(values), &start, options);
+
+    let covariance = problem.observed_information(&outcome.position).cholesky().map(|c| c.inverse());
+
+    let fitted = problem
+        .parameters()
+        .iter()
+        .enumerate()
+        .map(|(i, name)| FittedParameter {
+            name: name.clone(),
+            estimate: outcome.position[i],
+            standard_error: covariance
+                .as_ref()
+                .map(|covariance| covariance[(i, i)].sqrt())
+                .unwrap_or(f64::NAN),
+            gradient: -outcome.gradient[i],
+        })
+        .collect();
+
+    Ok(MleFit {
+        config: problem.config_at(&outcome.position),
+        parameters: fitted,
+        log_likelihood: -outcome.value,
+        matches: problem.matches(),
+        iterations: outcome.iterations,
+        evaluations: outcome.evaluations,
+        converged: outcome.converged,
+        covariance,
+        history: outcome.history.iter().map(|value| -value).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::elo::train::construct_rated_games_for_time_series;
+    use crate::experimentation::run_config::CustomElo;
+
+    /// Every team plays the others of its division twice. Scores and market values follow a fixed pattern
+    /// so every result and every goal difference shows up
+    fn season(year: u16, divisions: [[&str; 4]; 2]) -> Vec<Game> {
+        let value = |team: &str| (team.as_bytes()[0] - b'A') as f64 * 0.1;
+        let mut games = Vec::new();
+        for (division, teams) in divisions.iter().enumerate() {
+            let mut round = 0;
+            for home in teams {
+                for away in teams.iter().filter(|&away| away != home) {
+                    round += 1;
+                    let (home_score, away_score) = ((round * 7 + year) % 4, (round * 5 + division as u16) % 3);
+                    let date = NaiveDate::from_ymd_opt(year as i32, 5, 1).unwrap() + chrono::Duration::days(7 * round as i64);
+                    games.push(Game {
+                        week: round as f32,
+                        home_value: value(home),
+                        away_value: value(away),
+                        ..Game::fixture(date, home, away, division as u8 + 1).with_score(home_score, away_score)
+                    });
+                }
+            }
+        }
+        games
+    }
+
+    /// Two seasons with D relegated, E promoted and I coming from outside the dataset
+    fn two_seasons() -> Vec<Game> {
+        let mut games = season(2020, [["A", "B", "C", "D"], ["E", "F", "G", "H"]]);
+        games.extend(season(2021, [["A", "B", "C", "E"], ["D", "F", "G", "I"]]));
+        games
+    }
+
+    fn experiment_config() -> RunHyperparameters {
+        RunHyperparameters {
+            starting_elo: 1000,
+            starting_year: 2020,
+            backtest_years: 1,
+            random_variations: 1,
+            use_goals_diff: true,
+            use_home_advantage: true,
+            use_market_values: true,
+            leagues_to_use: 0,
+        }
+    }
+
+    fn base() -> RunConfig {
+        RunConfig {
+            k_factor: 1.2,
+            gamma: 0.8,
+            home_advantage: 30.0,
+            market_value_weight: 0.6,
+            tie_frequency: 0.3,
+            season_regression: 0.25,
+            w_division: vec![30.0, 20.0],
+            ..RunConfig::default()
+        }
+    }
+
+    #[test]
+    fn the_gradient_matches_finite_differences() {
+        let parameters: Vec<String> = [
+            "k_factor",
+            "gamma",
+            "home_advantage",
+            "market_value_weight",
+            "tie_frequency",
+            "season_regression",
+            "w_division_1",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+        let problem = LikelihoodProblem::new(&two_seasons(), &experiment_config(), base(), parameters).unwrap();
+
+        let values = problem.values_of(&base());
+        let exact = problem.log_likelihood(&values);
+
+        for (i, name) in problem.parameters().iter().enumerate() {
+            let step = 1e-6 * values[i].abs().max(1.0);
+            let at = |delta: f64| {
+                let mut moved = values.clone();
+                moved[i] += delta;
+                problem.log_likelihood(&moved).value
+            };
+            let numeric = (at(step) - at(-step)) / (2.0 * step);
+
+            let tolerance = 1e-5 * numeric.abs().max(1.0);
+            assert!(
+                (exact.gradient[i] - numeric).abs() < tolerance,
+                "{}: {} != {}",
+                name,
+                exact.gradient[i],
+                numeric
+            );
+        }
+    }
+
+    #[test]
+    fn the_value_matches_the_training() {
+        let games = two_seasons();
+        let experiment_config = experiment_config();
+        let problem =
+            LikelihoodProblem::new(&games, &experiment_config, base(), vec![String::from("k_factor")]).unwrap();
+
+        let rating_system = CustomElo::new(base(), experiment_config.components());
+        let (_, rated_games) = construct_rated_games_for_time_series(&rating_system, &games, &experiment_config, 2020, 2021);
+        let expected: f64 = rated_games
+            .iter()
+            .map(|rated| match rated.game.result {
+                GameResult::H => rated.prob_home.ln(),
+                GameResult::D => rated.prob_draw.ln(),
+                GameResult::A => rated.prob_away.ln(),
+            })
+            .sum();
+
+        assert_eq!(problem.matches(), games.len());
+        let value = problem.log_likelihood(&problem.values_of(&base())).value;
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn the_fit_improves_the_likelihood() {
+        let fit = fit_mle(&two_seasons(), &experiment_config(), base(), None, &LbfgsOptions::default()).unwrap();
+
+        assert_eq!(fit.parameters.len(), 3);
+        assert!(fit.log_likelihood >= fit.history[0]);
+        assert!(fit.history.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(fit.converged);
+        assert!(fit.parameters.iter().all(|parameter| parameter.gradient.abs() < 1e-3));
+        assert!(fit.parameters.iter().all(|parameter| parameter.standard_error.is_finite()));
+    }
+
+    #[test]
+    fn rejects_parameters_that_dont_move_the_likelihood() {
+        let games = two_seasons();
+        let experiment_config = RunHyperparameters {
+            use_home_advantage: false,
+            ..experiment_config()
+        };
+        let fit = |names: &[&str]| {
+            let parameters = names.iter().map(|name| name.to_string()).collect();
+            LikelihoodProblem::new(&games, &experiment_config, base(), parameters).map(|_| ())
+        };
+
+        assert!(fit(&["home_advantage"]).is_err());
+        assert!(fit(&["home_field_advantage_weight"]).is_err());
+        assert!(fit(&["k_factor", "w_division_0", "w_division_1"]).is_err());
+        assert!(fit(&["k_factor", "k_factor"]).is_err());
+        assert!(fit(&["k_factor", "w_division_0"]).is_ok());
+    }
+}
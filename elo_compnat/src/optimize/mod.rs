@@ -0,0 +1,507 @@
+pub mod checkpoint;
+pub mod cmaes;
+pub mod dual;
+pub mod genetic;
+pub mod lbfgs;
+pub mod likelihood;
+pub mod nsga2;
+pub mod pso;
+pub mod space;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::experimentation::evaluation::{backtest_rated_games, rating_volatility, FitnessObjective};
+use crate::experimentation::fitness_cache::{dataset_digest, FitnessCache, FitnessKey};
+use crate::experimentation::run_all_experiments::PreparedExperiment;
+use crate::experimentation::run_config::{CustomElo, RunConfig, RunHyperparameters};
+use crate::util::game::Game;
+
+pub use checkpoint::{list_checkpoints, Checkpoint, CheckpointError, Checkpointing, RunContext, SearchState};
+pub use cmaes::CmaEsOptions;
+pub use genetic::GeneticOptions;
+pub use lbfgs::LbfgsOptions;
+pub use likelihood::{fit_mle, FittedParameter, MleFit};
+pub use nsga2::{NsgaOptions, ParetoFront, ParetoMember, ParetoObjective, DEFAULT_PARETO_OBJECTIVES};
+pub use pso::PsoOptions;
+pub use space::{Bound, ParameterSpace};
+
+/// Cost given to configs whose backtest returns something that can't be compared (NaN or infinite)
+pub const INVALID_COST: f64 = f64::MAX;
+
+/// Best and mean cost of one iteration (PSO step or GA/CMA-ES generation)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IterationRecord {
+    pub iteration: usize,
+    pub best_cost: f64,
+    /// Mean of the valid costs, [`INVALID_COST`] when there is none
+    pub mean_cost: f64,
+    /// Best cost found up to this iteration
+    pub best_so_far: f64,
+}
+
+/// What every optimizer returns. `best_position` is in the unit cube of the [`ParameterSpace`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizationResult {
+    pub algorithm: &'static str,
+    pub best_config: RunConfig,
+    pub best_position: Vec<f64>,
+    pub best_cost: f64,
+    pub evaluations: usize,
+    pub history: Vec<IterationRecord>,
+}
+
+impl OptimizationResult {
+    pub(crate) fn from_outcome(space: &ParameterSpace, algorithm: &Algorithm, outcome: SearchOutcome) -> Self {
+        let (best_position, best_cost, evaluations, history) = outcome;
+        OptimizationResult {
+            algorithm: algorithm.name(),
+            best_config: space.to_run_config(&best_position),
+            best_position,
+            best_cost,
+            evaluations,
+            history,
+        }
+    }
+
+    /// `best_config` as a `RunConfig`, the searched values by name and the history as columns
+    pub fn to_dict(&self, py: Python, space: &ParameterSpace) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("algorithm", self.algorithm)?;
+        dict.set_item("best_config", Py::new(py, self.best_config.clone())?)?;
+        dict.set_item("best_cost", self.best_cost)?;
+        dict.set_item("evaluations", self.evaluations)?;
+
+        let values = PyDict::new(py);
+        for (bound, value) in space.bounds.iter().zip(space.values(&self.best_position)) {
+            values.set_item(&bound.name, value)?;
+        }
+        dict.set_item("best_values", values)?;
+
+        let history = PyDict::new(py);
+        history.set_item("iteration", self.history.iter().map(|r| r.iteration).collect::<Vec<_>>())?;
+        history.set_item("best_cost", self.history.iter().map(|r| r.best_cost).collect::<Vec<_>>())?;
+        history.set_item("mean_cost", self.history.iter().map(|r| r.mean_cost).collect::<Vec<_>>())?;
+        history.set_item("best_so_far", self.history.iter().map(|r| r.best_so_far).collect::<Vec<_>>())?;
+        dict.set_item("history", history)?;
+
+        Ok(dict.into())
+    }
+}
+
+/// Optimizer and its settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    Pso(PsoOptions),
+    Genetic(GeneticOptions),
+    CmaEs(CmaEsOptions),
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Pso(_) => "pso",
+            Algorithm::Genetic(_) => "genetic",
+            Algorithm::CmaEs(_) => "cmaes",
+        }
+    }
+
+    /// Population (or swarm) size and number of iterations
+    pub fn with_budget(self, population: usize, iterations: usize) -> Self {
+        match self {
+            Algorithm::Pso(options) => Algorithm::Pso(PsoOptions {
+                particles: population,
+                iterations,
+                ..options
+            }),
+            Algorithm::Genetic(options) => Algorithm::Genetic(GeneticOptions {
+                population,
+                generations: iterations,
+                ..options
+            }),
+            Algorithm::CmaEs(options) => Algorithm::CmaEs(CmaEsOptions {
+                population,
+                generations: iterations,
+                ..options
+            }),
+        }
+    }
+
+    /// Number of iterations (PSO steps or generations) of a run
+    pub fn iterations(&self) -> usize {
+        match self {
+            Algorithm::Pso(options) => options.iterations,
+            Algorithm::Genetic(options) => options.generations,
+            Algorithm::CmaEs(options) => options.generations,
+        }
+    }
+
+    /// Every setting by the name `with_options` takes, plus the population and the iterations
+    pub fn settings(&self) -> Vec<(&'static str, f64)> {
+        match self {
+            Algorithm::Pso(o) => vec![
+                ("particles", o.particles as f64),
+                ("iterations", o.iterations as f64),
+                ("w", o.inertia),
+                ("c1", o.cognitive),
+                ("c2", o.social),
+                ("max_velocity", o.max_velocity),
+            ],
+            Algorithm::Genetic(o) => vec![
+                ("population", o.population as f64),
+                ("generations", o.generations as f64),
+                ("crossover_rate", o.crossover_rate),
+                ("mutation_rate", o.mutation_rate),
+                ("mutation_sigma", o.mutation_sigma),
+                ("blend_alpha", o.blend_alpha),
+                ("elitism", o.elitism as f64),
+                ("tournament_size", o.tournament_size as f64),
+            ],
+            Algorithm::CmaEs(o) => vec![
+                ("population", o.population as f64),
+                ("generations", o.generations as f64),
+                ("sigma", o.sigma),
+            ],
+        }
+    }
+
+    /// Overrides settings by name, e.g. `{"w": 0.7}` for the PSO. Unknown names and values the algorithm
+    /// can't run with, like a rate outside [0, 1] or a negative step, are an error
+    pub fn with_options(self, options: &HashMap<String, f64>) -> Result<Self, String> {
+        let mut algorithm = self;
+        for (name, &value) in options {
+            let (requirement, valid) = option_requirement(name);
+            if !valid(value) {
+                return Err(format!(
+                    "Option '{}' of {} must be {}, got {}",
+                    name,
+                    algorithm.name(),
+                    requirement,
+                    value
+                ));
+            }
+            match (&mut algorithm, name.as_str()) {
+                (Algorithm::Pso(o), "w") => o.inertia = value,
+                (Algorithm::Pso(o), "c1") => o.cognitive = value,
+                (Algorithm::Pso(o), "c2") => o.social = value,
+                (Algorithm::Pso(o), "max_velocity") => o.max_velocity = value,
+                (Algorithm::Genetic(o), "crossover_rate") => o.crossover_rate = value,
+                (Algorithm::Genetic(o), "mutation_rate") => o.mutation_rate = value,
+                (Algorithm::Genetic(o), "mutation_sigma") => o.mutation_sigma = value,
+                (Algorithm::Genetic(o), "blend_alpha") => o.blend_alpha = value,
+                (Algorithm::Genetic(o), "elitism") => o.elitism = value as usize,
+                (Algorithm::Genetic(o), "tournament_size") => o.tournament_size = (value as usize).max(1),
+                (Algorithm::CmaEs(o), "sigma") => o.sigma = value,
+                (other, _) => return Err(format!("Unknown option '{}' for {}", name, other.name())),
+            }
+        }
+        Ok(algorithm)
+    }
+}
+
+/// What a value of the option `name` must be, and the check
+pub(crate) fn option_requirement(name: &str) -> (&'static str, fn(f64) -> bool) {
+    match name {
+        "crossover_rate" | "mutation_rate" => ("between 0 and 1", |value| (0.0..=1.0).contains(&value)),
+        // gen_range e clamp entram em pânico com passo negativo ou NaN
+        "max_velocity" | "sigma" => ("positive", |value| value.is_finite() && value > 0.0),
+        "c1" | "c2" | "mutation_sigma" | "blend_alpha" => ("non-negative", |value| value.is_finite() && value >= 0.0),
+        "elitism" | "tournament_size" => ("a non-negative whole number", |value| {
+            value.is_finite() && value >= 0.0 && value.fract() == 0.0
+        }),
+        _ => ("finite", f64::is_finite),
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pso" => Ok(Algorithm::Pso(PsoOptions::default())),
+            "ga" | "genetic" => Ok(Algorithm::Genetic(GeneticOptions::default())),
+            "cmaes" | "cma-es" | "cma_es" => Ok(Algorithm::CmaEs(CmaEsOptions::default())),
+            _ => Err(format!("Unknown algorithm '{}'. Options are pso, genetic and cmaes", s)),
+        }
+    }
+}
+
+/// Best position, its cost, number of evaluations and history, what each algorithm returns to [`minimize`]
+pub(crate) type SearchOutcome = (Vec<f64>, f64, usize, Vec<IterationRecord>);
+
+/// Costs of a population, evaluated in parallel. The order of the result is the order of `positions`
+pub(crate) fn evaluate_population<F>(cost: &F, positions: &[Vec<f64>]) -> Vec<f64>
+where
+    F: Fn(&[f64]) -> f64 + Sync,
+{
+    positions
+        .par_iter()
+        .map(|position| {
+            let value = cost(position);
+            if value.is_finite() {
+                value
+            } else {
+                INVALID_COST
+            }
+        })
+        .collect()
+}
+
+/// Position with the lowest cost. Ties keep the first one, so the result doesn't depend on the threads
+pub(crate) fn best_of(positions: &[Vec<f64>], costs: &[f64]) -> (Vec<f64>, f64) {
+    let (index, &value) = costs
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(b.1))
+        .expect("the population is never empty");
+    (positions[index].clone(), value)
+}
+
+/// Box-Muller, so we don't need rand_distr just for this
+pub(crate) fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    let u1: f64 = rng.gen::<f64>().max(f64::MIN_POSITIVE);
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+pub(crate) fn iteration_record(iteration: usize, costs: &[f64], best_so_far: f64) -> IterationRecord {
+    let best_cost = costs.iter().copied().fold(f64::INFINITY, f64::min);
+    // somar INVALID_COST estoura para inf, que o json grava como null e o checkpoint não carrega mais
+    let valid: Vec<f64> = costs.iter().copied().filter(|&cost| cost < INVALID_COST).collect();
+    let mean_cost = if valid.is_empty() {
+        INVALID_COST
+    } else {
+        valid.iter().sum::<f64>() / valid.len() as f64
+    };
+
+    IterationRecord {
+        iteration,
+        best_cost,
+        mean_cost,
+        best_so_far: best_so_far.min(best_cost),
+    }
+}
+
+/// Minimizes `cost` over the unit cube of `space`. Each evaluation of a population runs in parallel, and
+/// the same `seed` gives the same result as long as `cost` is deterministic.
+///
+/// With `checkpoint`, the state of the optimizer is saved to its file as the run goes, and a run whose
+/// file already exists picks up from the last saved iteration, ending with the result the uninterrupted
+/// run would have had. A finished run is returned without evaluating anything
+pub fn minimize<F>(
+    space: &ParameterSpace,
+    algorithm: &Algorithm,
+    cost: F,
+    seed: u64,
+    checkpoint: Option<&Checkpointing>,
+) -> Result<OptimizationResult, String>
+where
+    F: Fn(&[f64]) -> f64 + Sync,
+{
+    space.validate()?;
+
+    let resumed = match checkpoint {
+        Some(checkpointing) => checkpointing
+            .resume(space, algorithm, seed)
+            .map_err(|e| e.to_string())?,
+        None => None,
+    };
+
+    let save = |state: &SearchState, created_at: u64, finished: bool| match checkpoint {
+        Some(checkpointing) => checkpointing
+            .save(space, algorithm, seed, state, created_at, finished)
+            .map_err(|e| e.to_string()),
+        None => Ok(()),
+    };
+
+    let (mut state, created_at) = match resumed {
+        Some(saved) => (saved.state, saved.created_at),
+        None => {
+            let created_at = checkpoint::unix_time();
+            let state = SearchState::start(space, algorithm, &cost, seed);
+            save(&state, created_at, false)?;
+            (state, created_at)
+        }
+    };
+
+    let every = checkpoint.map(|checkpointing| checkpointing.every.max(1)).unwrap_or(1);
+    while state.iteration() < algorithm.iterations() {
+        state.step(algorithm, &cost);
+        if state.iteration() % every == 0 && state.iteration() < algorithm.iterations() {
+            save(&state, created_at, false)?;
+        }
+    }
+    save(&state, created_at, true)?;
+
+    Ok(OptimizationResult::from_outcome(space, algorithm, state.outcome()))
+}
+
+/// Mean of the errors `fitness_function` returns for `config`, read from the `cache` when it's there.
+/// `dataset` is the digest of all the matches, needed only with a cache
+fn mean_fitness(
+    experiment: &PreparedExperiment,
+    experiment_config: &RunHyperparameters,
+    config: &RunConfig,
+    objective: FitnessObjective,
+    seed: u64,
+    cache: Option<(&FitnessCache, &str)>,
+) -> f64 {
+    let evaluate = || {
+        let custom_elo = CustomElo::new(config.clone(), experiment_config.components());
+        experiment.errors(&custom_elo, experiment_config, objective, seed)
+    };
+
+    let errors = match cache {
+        Some((cache, dataset)) => {
+            let key = FitnessKey::new(dataset, config, experiment_config, objective, seed);
+            // o cache é só um atalho: se o sqlite falhar, a avaliação segue sem ele
+            match cache.get(&key) {
+                Ok(Some(errors)) => errors,
+                _ => {
+                    let errors = evaluate();
+                    let _ = cache.insert(&key, &errors);
+                    errors
+                }
+            }
+        }
+        None => evaluate(),
+    };
+    errors.iter().sum::<f64>() / errors.len().max(1) as f64
+}
+
+/// Tunes the elo on the backtest: the cost of a config is the mean over the simulated seasons of
+/// `objective`, the same errors `fitness_function` returns. The games are loaded once and shared by
+/// every evaluation. With a `cache`, configs already evaluated (in this run or an earlier one) are read
+/// back instead of simulated again, and with a `checkpoint` the run can be resumed, see [`minimize`].
+/// Fails before the search when the hyperparameters don't fit the data
+#[allow(clippy::too_many_arguments)]
+pub fn optimize_run_config(
+    all_games: &[Game],
+    experiment_config: &RunHyperparameters,
+    space: &ParameterSpace,
+    algorithm: &Algorithm,
+    objective: FitnessObjective,
+    seed: u64,
+    cache: Option<&FitnessCache>,
+    checkpoint: Option<&Checkpointing>,
+) -> Result<OptimizationResult, String> {
+    let experiment = PreparedExperiment::new(all_games, experiment_config)?;
+    let dataset = cache.map(|_| dataset_digest(all_games));
+    let cache = cache.zip(dataset.as_deref());
+
+    let cost = |position: &[f64]| {
+        let config = space.to_run_config(position);
+        mean_fitness(&experiment, experiment_config, &config, objective, seed, cache)
+    };
+
+    minimize(space, algorithm, cost, seed, checkpoint)
+}
+
+/// Searches the trade-off between several objectives at once with NSGA-II and returns the configs no
+/// other one beats in all of them. Fitness objectives cost what [`optimize_run_config`] would (and use the
+/// `cache` the same way); the volatility is measured on the backtest matches of `evaluate_predictions`
+pub fn optimize_pareto(
+    all_games: &[Game],
+    experiment_config: &RunHyperparameters,
+    space: &ParameterSpace,
+    objectives: &[ParetoObjective],
+    options: &NsgaOptions,
+    seed: u64,
+    cache: Option<&FitnessCache>,
+) -> Result<ParetoFront, String> {
+    let experiment = PreparedExperiment::new(all_games, experiment_config)?;
+    let dataset = cache.map(|_| dataset_digest(all_games));
+    let cache = cache.zip(dataset.as_deref());
+
+    let cost = |position: &[f64]| {
+        let config = space.to_run_config(position);
+        objectives
+            .iter()
+            .map(|objective| match *objective {
+                ParetoObjective::Fitness(objective) => {
+                    mean_fitness(&experiment, experiment_config, &config, objective, seed, cache)
+                }
+                ParetoObjective::RatingVolatility => {
+                    let custom_elo = CustomElo::new(config.clone(), experiment_config.components());
+                    rating_volatility(&backtest_rated_games(&custom_elo, all_games, experiment_config))
+                }
+            })
+            .collect::<Vec<f64>>()
+    };
+
+    let names = objectives.iter().map(ParetoObjective::name).collect();
+    nsga2::minimize_pareto(space, options, names, cost, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_mean_cost_leaves_invalid_costs_out() {
+        let record = iteration_record(3, &[1.0, INVALID_COST, 3.0, INVALID_COST], 2.0);
+        assert_eq!(record.best_cost, 1.0);
+        assert_eq!(record.mean_cost, 2.0);
+        assert_eq!(record.best_so_far, 1.0);
+
+        let record = iteration_record(0, &[INVALID_COST, INVALID_COST], f64::INFINITY);
+        assert_eq!(record.mean_cost, INVALID_COST);
+        assert_eq!(record.best_so_far, INVALID_COST);
+    }
+
+    #[test]
+    fn options_the_algorithms_cant_run_with_are_rejected() {
+        let with = |algorithm: &str, name: &str, value: f64| {
+            let options = HashMap::from([(name.to_string(), value)]);
+            algorithm.parse::<Algorithm>().unwrap().with_options(&options)
+        };
+
+        for (algorithm, name, value) in [
+            ("pso", "max_velocity", -0.1),
+            ("pso", "max_velocity", f64::NAN),
+            ("pso", "w", f64::INFINITY),
+            ("pso", "c1", -1.0),
+            ("ga", "crossover_rate", 1.5),
+            ("ga", "mutation_rate", -0.1),
+            ("ga", "mutation_rate", f64::NAN),
+            ("ga", "mutation_sigma", -0.2),
+            ("ga", "blend_alpha", f64::NAN),
+            ("ga", "elitism", 1.5),
+            ("cmaes", "sigma", -0.3),
+            ("cmaes", "sigma", 0.0),
+        ] {
+            assert!(with(algorithm, name, value).is_err(), "{} {} = {}", algorithm, name, value);
+        }
+
+        assert_eq!(with("pso", "max_velocity", 0.2).unwrap().settings()[5], ("max_velocity", 0.2));
+        assert!(with("ga", "crossover_rate", 1.0).is_ok());
+        assert!(with("ga", "mutation_rate", 0.0).is_ok());
+        assert!(with("cmaes", "w", 0.7).is_err());
+    }
+
+    #[test]
+    fn every_algorithm_finds_the_minimum_of_a_quadratic() {
+        let space = ParameterSpace::new(RunConfig::default())
+            .with_bound("home_field_advantage_weight", 0.0, 1.0)
+            .with_bound("market_value_weight", 0.0, 1.0);
+        let target = [0.3, 0.7];
+        let quadratic = |x: &[f64]| x.iter().zip(target).map(|(x, t)| (x - t).powi(2)).sum::<f64>();
+
+        for name in ["pso", "ga", "cmaes"] {
+            let algorithm = name.parse::<Algorithm>().unwrap().with_budget(20, 60);
+            let result = minimize(&space, &algorithm, quadratic, 11, None).unwrap();
+
+            assert!(result.best_cost < 1e-4, "{}: {}", name, result.best_cost);
+            for (x, t) in result.best_position.iter().zip(target) {
+                assert!((x - t).abs() < 1e-2, "{}: {:?}", name, result.best_position);
+            }
+        }
+    }
+}
@@ -0,0 +1,568 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+use super::genetic::{blend, mutate};
+use super::{option_requirement, ParameterSpace, INVALID_COST};
+use crate::experimentation::evaluation::FitnessObjective;
+use crate::experimentation::run_config::RunConfig;
+use crate::util::parsing::DatasetError;
+
+/// Objectives used when none are given: standings error, match log-loss and how much the ratings move
+pub const DEFAULT_PARETO_OBJECTIVES: [&str; 3] = ["rmse", "log_loss", "volatility"];
+
+/// One of the things traded off by [`minimize_pareto`]. All of them are minimized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParetoObjective {
+    /// Mean over the simulated seasons of what `fitness_function` returns for it
+    Fitness(FitnessObjective),
+    /// Mean absolute rating change per team and match over the backtest, see
+    /// [`rating_volatility`](crate::experimentation::evaluation::rating_volatility)
+    RatingVolatility,
+}
+
+impl ParetoObjective {
+    pub fn name(&self) -> String {
+        match self {
+            ParetoObjective::Fitness(objective) => objective.name(),
+            ParetoObjective::RatingVolatility => String::from("volatility"),
+        }
+    }
+}
+
+impl FromStr for ParetoObjective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "volatility" | "stability" => Ok(ParetoObjective::RatingVolatility),
+            _ => s
+                .parse()
+                .map(ParetoObjective::Fitness)
+                .map_err(|message| format!("{}, or volatility", message)),
+        }
+    }
+}
+
+/// NSGA-II: non-dominated sorting with crowding distance, binary tournaments on (rank, crowding) and
+/// the same blend crossover and gaussian mutation as the [`GeneticOptions`](super::GeneticOptions)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NsgaOptions {
+    pub population: usize,
+    pub generations: usize,
+    pub crossover_rate: f64,
+    pub blend_alpha: f64,
+    pub mutation_rate: f64,
+    pub mutation_sigma: f64,
+}
+
+impl Default for NsgaOptions {
+    fn default() -> Self {
+        NsgaOptions {
+            population: 20,
+            generations: 10,
+            crossover_rate: 0.9,
+            blend_alpha: 0.5,
+            mutation_rate: 0.1,
+            mutation_sigma: 0.1,
+        }
+    }
+}
+
+impl NsgaOptions {
+    /// Overrides settings by name, as in [`Algorithm::with_options`](super::Algorithm::with_options)
+    pub fn with_options(mut self, options: &HashMap<String, f64>) -> Result<Self, String> {
+        for (name, &value) in options {
+            let (requirement, valid) = option_requirement(name);
+            if !valid(value) {
+                return Err(format!("Option '{}' of nsga2 must be {}, got {}", name, requirement, value));
+            }
+            match name.as_str() {
+                "crossover_rate" => self.crossover_rate = value,
+                "blend_alpha" => self.blend_alpha = value,
+                "mutation_rate" => self.mutation_rate = value,
+                "mutation_sigma" => self.mutation_sigma = value,
+                _ => return Err(format!("Unknown option '{}' for nsga2", name)),
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// A non-dominated config and its value for each objective
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParetoMember {
+    pub config: RunConfig,
+    /// Point of the unit cube of the [`ParameterSpace`]
+    pub position: Vec<f64>,
+    pub values: Vec<f64>,
+}
+
+/// State of the population after a generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParetoGeneration {
+    pub generation: usize,
+    /// Members of the first front
+    pub front_size: usize,
+    /// Lowest value of each objective in the population
+    pub best: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParetoFront {
+    pub objectives: Vec<String>,
+    /// Sorted by the first objective
+    pub members: Vec<ParetoMember>,
+    pub evaluations: usize,
+    pub history: Vec<ParetoGeneration>,
+}
+
+impl ParetoFront {
+    pub fn to_dict(&self, py: Python, space: &ParameterSpace) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("objectives", self.objectives.clone())?;
+        dict.set_item("evaluations", self.evaluations)?;
+
+        let front = self
+            .members
+            .iter()
+            .map(|member| {
+                let entry = PyDict::new(py);
+                entry.set_item("config", Py::new(py, member.config.clone())?)?;
+
+                let values = PyDict::new(py);
+                for (name, value) in self.objectives.iter().zip(&member.values) {
+                    values.set_item(name, value)?;
+                }
+                entry.set_item("values", values)?;
+
+                let parameters = PyDict::new(py);
+                for (bound, value) in space.bounds.iter().zip(space.values(&member.position)) {
+                    parameters.set_item(&bound.name, value)?;
+                }
+                entry.set_item("parameters", parameters)?;
+                Ok(entry.into())
+            })
+            .collect::<PyResult<Vec<Py<PyDict>>>>()?;
+        dict.set_item("front", front)?;
+
+        let history = PyDict::new(py);
+        history.set_item("generation", self.history.iter().map(|g| g.generation).collect::<Vec<_>>())?;
+        history.set_item("front_size", self.history.iter().map(|g| g.front_size).collect::<Vec<_>>())?;
+        for (i, name) in self.objectives.iter().enumerate() {
+            history.set_item(name, self.history.iter().map(|g| g.best[i]).collect::<Vec<_>>())?;
+        }
+        dict.set_item("history", history)?;
+
+        Ok(dict.into())
+    }
+
+    /// One row per member: the objectives, then the searched fields
+    pub fn write_csv(&self, space: &ParameterSpace, output: &Path) -> Result<(), DatasetError> {
+        let write_error = |source: csv::Error| DatasetError::Io {
+            path: output.to_path_buf(),
+            source,
+        };
+
+        let mut writer = csv::Writer::from_path(output).map_err(write_error)?;
+        let header = self
+            .objectives
+            .iter()
+            .cloned()
+            .chain(space.bounds.iter().map(|bound| bound.name.clone()));
+        writer.write_record(header).map_err(write_error)?;
+
+        for member in &self.members {
+            let row = member
+                .values
+                .iter()
+                .chain(space.values(&member.position).iter())
+                .map(|value| value.to_string())
+                .collect::<Vec<String>>();
+            writer.write_record(row).map_err(write_error)?;
+        }
+
+        writer.flush().map_err(|e| write_error(e.into()))?;
+        Ok(())
+    }
+
+    /// `{"objectives": [...], "front": [{"values": {...}, "parameters": {...}, "config": {...}}]}`
+    pub fn write_json(&self, space: &ParameterSpace, output: &Path) -> Result<(), DatasetError> {
+        let write_error = |source: std::io::Error| DatasetError::Io {
+            path: output.to_path_buf(),
+            source: source.into(),
+        };
+
+        let front: Vec<serde_json::Value> = self
+            .members
+            .iter()
+            .map(|member| {
+                let values: serde_json::Map<String, serde_json::Value> = self
+                    .objectives
+                    .iter()
+                    .cloned()
+                    .zip(member.values.iter().map(|&value| serde_json::json!(value)))
+                    .collect();
+                let parameters: serde_json::Map<String, serde_json::Value> = space
+                    .bounds
+                    .iter()
+                    .map(|bound| bound.name.clone())
+                    .zip(space.values(&member.position).into_iter().map(|value| serde_json::json!(value)))
+                    .collect();
+                serde_json::json!({
+                    "values": values,
+                    "parameters": parameters,
+                    "config": member.config,
+                })
+            })
+            .collect();
+
+        let document = serde_json::json!({
+            "objectives": self.objectives,
+            "evaluations": self.evaluations,
+            "front": front,
+        });
+
+        let text = serde_json::to_string_pretty(&document).map_err(|e| write_error(e.into()))?;
+        std::fs::write(output, text).map_err(write_error)
+    }
+}
+
+/// Whether `a` is at least as good as `b` in every objective and better in one
+fn dominates(a: &[f64], b: &[f64]) -> bool {
+    a.iter().zip(b).all(|(x, y)| x <= y) && a.iter().zip(b).any(|(x, y)| x < y)
+}
+
+/// Fast non-dominated sort: indices of each front, the first one being the non-dominated set
+fn non_dominated_fronts(costs: &[Vec<f64>]) -> Vec<Vec<usize>> {
+    let n = costs.len();
+    let mut dominated_by: Vec<Vec<usize>> = vec![Vec::new(); n];
+    let mut domination_count = vec![0usize; n];
+
+    for i in 0..n {
+        for j in (i + 1)..n {
+            if dominates(&costs[i], &costs[j]) {
+                dominated_by[i].push(j);
+                domination_count[j] += 1;
+            } else if dominates(&costs[j], &costs[i]) {
+                dominated_by[j].push(i);
+                domination_count[i] += 1;
+            }
+        }
+    }
+
+    let mut fronts = Vec::new();
+    let mut current: Vec<usize> = (0..n).filter(|&i| domination_count[i] == 0).collect();
+    while !current.is_empty() {
+        let mut next = Vec::new();
+        for &i in &current {
+            for &j in &dominated_by[i] {
+                domination_count[j] -= 1;
+                if domination_count[j] == 0 {
+                    next.push(j);
+                }
+            }
+        }
+        next.sort_unstable();
+        fronts.push(current);
+        current = next;
+    }
+    fronts
+}
+
+/// Crowding distance of each member of `front`, in the same order. The extremes of every objective get
+/// infinity so they are always kept
+fn crowding_distances(front: &[usize], costs: &[Vec<f64>]) -> Vec<f64> {
+    let mut distances = vec![0.0; front.len()];
+    if front.is_empty() {
+        return distances;
+    }
+
+    let objectives = costs[front[0]].len();
+    let columns = (0..objectives).map(|m| front.iter().map(|&i| costs[i][m]).collect::<Vec<f64>>());
+
+    for column in columns {
+        let mut order: Vec<usize> = (0..front.len()).collect();
+        order.sort_by(|&a, &b| column[a].total_cmp(&column[b]).then(a.cmp(&b)));
+
+        let low = column[order[0]];
+        let high = column[order[order.len() - 1]];
+        distances[order[0]] = f64::INFINITY;
+        distances[order[order.len() - 1]] = f64::INFINITY;
+        if high > low {
+            for window in order.windows(3) {
+                distances[window[1]] += (column[window[2]] - column[window[0]]) / (high - low);
+            }
+        }
+    }
+    distances
+}
+
+/// Rank (index of the front) and crowding distance of every individual
+fn rank_and_crowding(costs: &[Vec<f64>]) -> (Vec<usize>, Vec<f64>, Vec<Vec<usize>>) {
+    let fronts = non_dominated_fronts(costs);
+    let mut rank = vec![0; costs.len()];
+    let mut crowding = vec![0.0; costs.len()];
+    for (r, front) in fronts.iter().enumerate() {
+        for (&i, distance) in front.iter().zip(crowding_distances(front, costs)) {
+            rank[i] = r;
+            crowding[i] = distance;
+        }
+    }
+    (rank, crowding, fronts)
+}
+
+/// Lower rank wins, then larger crowding distance
+fn crowded_tournament(rng: &mut StdRng, rank: &[usize], crowding: &[f64]) -> usize {
+    let a = rng.gen_range(0..rank.len());
+    let b = rng.gen_range(0..rank.len());
+    match rank[a].cmp(&rank[b]).then(crowding[b].total_cmp(&crowding[a])) {
+        Ordering::Greater => b,
+        _ => a,
+    }
+}
+
+fn evaluate_all<F>(cost: &F, positions: &[Vec<f64>]) -> Vec<Vec<f64>>
+where
+    F: Fn(&[f64]) -> Vec<f64> + Sync,
+{
+    positions
+        .par_iter()
+        .map(|position| {
+            cost(position)
+                .into_iter()
+                .map(|value| if value.is_finite() { value } else { INVALID_COST })
+                .collect()
+        })
+        .collect()
+}
+
+fn generation_record(generation: usize, costs: &[Vec<f64>], front_size: usize) -> ParetoGeneration {
+    let objectives = costs.first().map(|c| c.len()).unwrap_or(0);
+    ParetoGeneration {
+        generation,
+        front_size,
+        best: (0..objectives)
+            .map(|m| costs.iter().map(|c| c[m]).fold(f64::INFINITY, f64::min))
+            .collect(),
+    }
+}
+
+/// Minimizes every output of `cost` at once over the unit cube of `space` and returns the non-dominated
+/// configs of the last generation. `objectives` only names the outputs. Each population is evaluated in
+/// parallel, and the same `seed` gives the same front
+pub fn minimize_pareto<F>(
+    space: &ParameterSpace,
+    options: &NsgaOptions,
+    objectives: Vec<String>,
+    cost: F,
+    seed: u64,
+) -> Result<ParetoFront, String>
+where
+    F: Fn(&[f64]) -> Vec<f64> + Sync,
+{
+    space.validate()?;
+    if objectives.is_empty() {
+        return Err(String::from("No objective to optimize"));
+    }
+
+    let dimensions = space.dimensions();
+    let population_size = options.population.max(2);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut population: Vec<Vec<f64>> = (0..population_size)
+        .map(|i| match i {
+            0 => space.to_unit(&space.base),
+            _ => (0..dimensions).map(|_| rng.gen::<f64>()).collect(),
+        })
+        .collect();
+    let mut costs = evaluate_all(&cost, &population);
+    let mut evaluations = costs.len();
+
+    let (mut rank, mut crowding, fronts) = rank_and_crowding(&costs);
+    let mut history = vec![generation_record(0, &costs, fronts[0].len())];
+
+    for generation in 1..=options.generations {
+        let offspring: Vec<Vec<f64>> = (0..population_size)
+            .map(|_| {
+                let first = crowded_tournament(&mut rng, &rank, &crowding);
+                let second = crowded_tournament(&mut rng, &rank, &crowding);
+
+                let mut child = if rng.gen::<f64>() < options.crossover_rate {
+                    blend(&mut rng, &population[first], &population[second], options.blend_alpha)
+                } else {
+                    population[first].clone()
+                };
+                mutate(&mut rng, &mut child, options.mutation_rate, options.mutation_sigma);
+                child
+            })
+            .collect();
+
+        let offspring_costs = evaluate_all(&cost, &offspring);
+        evaluations += offspring_costs.len();
+
+        // (μ + λ): pais e filhos disputam as vagas, front por front
+        let combined: Vec<Vec<f64>> = population.into_iter().chain(offspring).collect();
+        let combined_costs: Vec<Vec<f64>> = costs.into_iter().chain(offspring_costs).collect();
+
+        let mut survivors: Vec<usize> = Vec::with_capacity(population_size);
+        for front in non_dominated_fronts(&combined_costs) {
+            if survivors.len() + front.len() <= population_size {
+                survivors.extend(front);
+                continue;
+            }
+
+            let distances = crowding_distances(&front, &combined_costs);
+            let mut order: Vec<usize> = (0..front.len()).collect();
+            order.sort_by(|&a, &b| distances[b].total_cmp(&distances[a]).then(a.cmp(&b)));
+            survivors.extend(order.into_iter().take(population_size - survivors.len()).map(|k| front[k]));
+            break;
+        }
+
+        population = survivors.iter().map(|&i| combined[i].clone()).collect();
+        costs = survivors.iter().map(|&i| combined_costs[i].clone()).collect();
+
+        let (next_rank, next_crowding, fronts) = rank_and_crowding(&costs);
+        rank = next_rank;
+        crowding = next_crowding;
+        history.push(generation_record(generation, &costs, fronts[0].len()));
+    }
+
+    let mut members: Vec<ParetoMember> = Vec::new();
+    for &i in &non_dominated_fronts(&costs)[0] {
+        // o elitismo deixa cópias do mesmo indivíduo na população
+        if members.iter().any(|member| member.position == population[i]) {
+            continue;
+        }
+        members.push(ParetoMember {
+            config: space.to_run_config(&population[i]),
+            position: population[i].clone(),
+            values: costs[i].clone(),
+        });
+    }
+    members.sort_by(|a, b| a.values[0].total_cmp(&b.values[0]));
+
+    Ok(ParetoFront {
+        objectives,
+        members,
+        evaluations,
+        history,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(values: &[[f64; 2]]) -> Vec<Vec<f64>> {
+        values.iter().map(|point| point.to_vec()).collect()
+    }
+
+    #[test]
+    fn fronts_of_a_known_point_set() {
+        assert!(dominates(&[1.0, 2.0], &[1.0, 3.0]));
+        assert!(!dominates(&[1.0, 3.0], &[1.0, 3.0]));
+        assert!(!dominates(&[1.0, 5.0], &[2.0, 3.0]));
+
+        // 5 repete 1: nenhum domina o outro e os dois ficam no primeiro front
+        let costs = points(&[[1.0, 5.0], [2.0, 3.0], [4.0, 1.0], [3.0, 4.0], [5.0, 5.0], [2.0, 3.0], [4.0, 4.0]]);
+
+        assert_eq!(non_dominated_fronts(&costs), vec![vec![0, 1, 2, 5], vec![3], vec![6], vec![4]]);
+    }
+
+    #[test]
+    fn the_extremes_of_a_front_are_infinitely_far() {
+        let costs = points(&[[1.0, 5.0], [2.0, 3.0], [4.0, 1.0], [9.0, 9.0]]);
+
+        let distances = crowding_distances(&[0, 1, 2], &costs);
+        assert_eq!(distances[0], f64::INFINITY);
+        assert_eq!(distances[2], f64::INFINITY);
+        // (4 - 1) / (4 - 1) + (5 - 1) / (5 - 1)
+        assert!((distances[1] - 2.0).abs() < 1e-12);
+
+        assert!(crowding_distances(&[1, 3], &costs).iter().all(|d| d.is_infinite()));
+        assert!(crowding_distances(&[], &costs).is_empty());
+    }
+
+    fn toy_space() -> ParameterSpace {
+        ParameterSpace::new(RunConfig::default())
+            .with_bound("home_field_advantage_weight", 0.0, 1.0)
+            .with_bound("market_value_weight", 0.0, 1.0)
+    }
+
+    /// Distância ao quadrado até (0, 0) e até (1, 0): o front é o segmento entre os dois
+    fn toy_cost(x: &[f64]) -> Vec<f64> {
+        vec![x[0].powi(2) + x[1].powi(2), (x[0] - 1.0).powi(2) + x[1].powi(2)]
+    }
+
+    fn toy_front(seed: u64) -> ParetoFront {
+        let options = NsgaOptions {
+            generations: 30,
+            ..NsgaOptions::default()
+        };
+        let objectives = vec![String::from("first"), String::from("second")];
+        minimize_pareto(&toy_space(), &options, objectives, toy_cost, seed).unwrap()
+    }
+
+    #[test]
+    fn the_front_of_a_toy_problem_has_no_dominated_or_repeated_member() {
+        let front = toy_front(5);
+
+        assert!(front.members.len() > 1);
+        assert_eq!(front.evaluations, 20 * 31);
+        assert_eq!(front.history.len(), 31);
+        for (i, a) in front.members.iter().enumerate() {
+            assert_eq!(a.values, toy_cost(&a.position));
+            // perto do segmento y = 0
+            assert!(a.position[1] < 0.1, "{:?}", a.position);
+            for b in &front.members[i + 1..] {
+                assert!(!dominates(&a.values, &b.values) && !dominates(&b.values, &a.values));
+                assert_ne!(a.position, b.position);
+            }
+        }
+        assert!(front.members.windows(2).all(|pair| pair[0].values[0] <= pair[1].values[0]));
+        assert_eq!(front, toy_front(5));
+    }
+
+    #[test]
+    fn the_front_is_written_with_its_objectives_and_parameters() {
+        let front = toy_front(2);
+        let space = toy_space();
+        let path = std::env::temp_dir().join(format!("nsga2_test_{}", std::process::id()));
+        let (csv_path, json_path) = (path.with_extension("csv"), path.with_extension("json"));
+
+        front.write_csv(&space, &csv_path).unwrap();
+        let text = std::fs::read_to_string(&csv_path).unwrap();
+        std::fs::remove_file(&csv_path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "first,second,home_field_advantage_weight,market_value_weight");
+        assert_eq!(lines.len(), front.members.len() + 1);
+
+        front.write_json(&space, &json_path).unwrap();
+        let text = std::fs::read_to_string(&json_path).unwrap();
+        std::fs::remove_file(&json_path).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(document["objectives"], serde_json::json!(["first", "second"]));
+        assert_eq!(document["front"].as_array().unwrap().len(), front.members.len());
+        assert_eq!(document["front"][0]["values"]["first"], serde_json::json!(front.members[0].values[0]));
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        let with = |name: &str, value: f64| {
+            NsgaOptions::default().with_options(&HashMap::from([(name.to_string(), value)]))
+        };
+
+        assert!(with("mutation_rate", 1.2).is_err());
+        assert!(with("blend_alpha", f64::NAN).is_err());
+        assert!(with("mutation_sigma", 0.2).is_ok());
+        assert!(with("sigma", 0.2).is_err());
+    }
+}
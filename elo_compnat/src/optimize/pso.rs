@@ -0,0 +1,143 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{best_of, evaluate_population, iteration_record, IterationRecord, ParameterSpace, SearchOutcome};
+
+/// Global-best particle swarm. The defaults are the ones `python/main.py` used with pyswarms
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PsoOptions {
+    pub particles: usize,
+    pub iterations: usize,
+    /// `w`, how much of its velocity a particle keeps
+    pub inertia: f64,
+    /// `c1`, pull towards the best position of the particle
+    pub cognitive: f64,
+    /// `c2`, pull towards the best position of the swarm
+    pub social: f64,
+    /// Largest step per coordinate, as a fraction of the unit cube
+    pub max_velocity: f64,
+}
+
+impl Default for PsoOptions {
+    fn default() -> Self {
+        PsoOptions {
+            particles: 10,
+            iterations: 10,
+            inertia: 0.9,
+            cognitive: 0.5,
+            social: 0.3,
+            max_velocity: 0.5,
+        }
+    }
+}
+
+/// Everything the swarm needs to go on from where it stopped, kept in the checkpoints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PsoState {
+    #[serde(with = "super::checkpoint::rng_state")]
+    rng: ChaCha12Rng,
+    iteration: usize,
+    positions: Vec<Vec<f64>>,
+    velocities: Vec<Vec<f64>>,
+    personal_best: Vec<Vec<f64>>,
+    personal_cost: Vec<f64>,
+    global_best: Vec<f64>,
+    global_cost: f64,
+    evaluations: usize,
+    history: Vec<IterationRecord>,
+}
+
+impl PsoState {
+    /// Initial swarm, already evaluated
+    pub(crate) fn new<F>(space: &ParameterSpace, options: &PsoOptions, cost: &F, seed: u64) -> Self
+    where
+        F: Fn(&[f64]) -> f64 + Sync,
+    {
+        let dimensions = space.dimensions();
+        let particles = options.particles.max(1);
+        let mut rng = ChaCha12Rng::seed_from_u64(seed);
+
+        // a primeira partícula começa na config base, as outras espalhadas pelo cubo
+        let positions: Vec<Vec<f64>> = (0..particles)
+            .map(|i| match i {
+                0 => space.to_unit(&space.base),
+                _ => (0..dimensions).map(|_| rng.gen::<f64>()).collect(),
+            })
+            .collect();
+        let velocities: Vec<Vec<f64>> = (0..particles)
+            .map(|_| {
+                (0..dimensions)
+                    .map(|_| rng.gen_range(-options.max_velocity..=options.max_velocity) / 2.0)
+                    .collect()
+            })
+            .collect();
+
+        let costs = evaluate_population(cost, &positions);
+        let (global_best, global_cost) = best_of(&positions, &costs);
+
+        PsoState {
+            rng,
+            iteration: 0,
+            personal_best: positions.clone(),
+            personal_cost: costs.clone(),
+            evaluations: costs.len(),
+            history: vec![iteration_record(0, &costs, f64::INFINITY)],
+            positions,
+            velocities,
+            global_best,
+            global_cost,
+        }
+    }
+
+    pub(crate) fn iteration(&self) -> usize {
+        self.iteration
+    }
+
+    /// Moves every particle once and evaluates the swarm
+    pub(crate) fn step<F>(&mut self, options: &PsoOptions, cost: &F)
+    where
+        F: Fn(&[f64]) -> f64 + Sync,
+    {
+        let rng = &mut self.rng;
+        let global_best = &self.global_best;
+        for ((position, velocity), best) in self.positions.iter_mut().zip(&mut self.velocities).zip(&self.personal_best) {
+            for d in 0..position.len() {
+                let (r1, r2): (f64, f64) = (rng.gen(), rng.gen());
+                velocity[d] = options.inertia * velocity[d]
+                    + options.cognitive * r1 * (best[d] - position[d])
+                    + options.social * r2 * (global_best[d] - position[d]);
+                velocity[d] = velocity[d].clamp(-options.max_velocity, options.max_velocity);
+
+                position[d] += velocity[d];
+                // quem bate na parede para ali
+                if !(0.0..=1.0).contains(&position[d]) {
+                    position[d] = position[d].clamp(0.0, 1.0);
+                    velocity[d] = 0.0;
+                }
+            }
+        }
+
+        let costs = evaluate_population(cost, &self.positions);
+        self.evaluations += costs.len();
+
+        for (i, &value) in costs.iter().enumerate() {
+            if value < self.personal_cost[i] {
+                self.personal_cost[i] = value;
+                self.personal_best[i] = self.positions[i].clone();
+            }
+            if value < self.global_cost {
+                self.global_cost = value;
+                self.global_best = self.positions[i].clone();
+            }
+        }
+
+        self.iteration += 1;
+        let best_so_far = self.history.last().map(|record| record.best_so_far).unwrap_or(f64::INFINITY);
+        self.history.push(iteration_record(self.iteration, &costs, best_so_far));
+    }
+
+    pub(crate) fn outcome(&self) -> SearchOutcome {
+        (self.global_best.clone(), self.global_cost, self.evaluations, self.history.clone())
+    }
+}
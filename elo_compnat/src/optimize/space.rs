@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+use crate::experimentation::run_config::RunConfig;
+
+/// Search interval of one [`RunConfig`] field. The entries of `w_division` are named `w_division_0`,
+/// `w_division_1`, ...
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bound {
+    pub name: String,
+    pub low: f64,
+    pub high: f64,
+}
+
+/// The fields of [`RunConfig`] being searched and their bounds. Fields without a bound keep the value
+/// of `base`.
+///
+/// The optimizers move in the unit cube, one coordinate per bound, and this maps it to configs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParameterSpace {
+    pub base: RunConfig,
+    pub bounds: Vec<Bound>,
+}
+
+impl ParameterSpace {
+    /// No field searched yet, everything comes from `base`
+    pub fn new(base: RunConfig) -> Self {
+        ParameterSpace {
+            base,
+            bounds: Vec::new(),
+        }
+    }
+
+    /// Same space as `python/main.py`: every gene but `tie_frequency`, which is set by hand, with one
+    /// `w_division` entry per division of `base`
+    pub fn default_for(base: RunConfig) -> Self {
+        let divisions = base.w_division.len();
+        let mut space = ParameterSpace::new(base)
+            .with_bound("k_factor", 1.0, 2.0)
+            .with_bound("gamma", 0.3, 2.0)
+            .with_bound("home_advantage", 0.0, 2.0)
+            .with_bound("home_field_advantage_weight", 0.0, 1.0)
+            .with_bound("market_value_weight", 0.0, 1.0)
+            .with_bound("season_regression", 0.0, 1.0);
+
+        for division in 0..divisions {
+            space = space.with_bound(&format!("w_division_{}", division), 10.0, 80.0);
+        }
+        space
+    }
+
+    /// Searches `name` in `[low, high]`, replacing its bound if it already had one
+    pub fn with_bound(mut self, name: &str, low: f64, high: f64) -> Self {
+        let bound = Bound {
+            name: name.to_string(),
+            low: low.min(high),
+            high: low.max(high),
+        };
+
+        match self.bounds.iter_mut().find(|b| b.name == name) {
+            Some(existing) => *existing = bound,
+            None => self.bounds.push(bound),
+        }
+        self
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.bounds.len()
+    }
+
+    /// Checks that every bound names a field of the config, that the `w_division` entries exist and that
+    /// both ends of every bound are valid values of their field
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bounds.is_empty() {
+            return Err(String::from("The parameter space has no bounds"));
+        }
+
+        let (mut lows, mut highs) = (self.base.clone(), self.base.clone());
+        for bound in &self.bounds {
+            set_field(&mut lows, &bound.name, bound.low)?;
+            set_field(&mut highs, &bound.name, bound.high)?;
+        }
+        self.base.validate()?;
+        lows.validate()?;
+        highs.validate()
+    }
+
+    /// Config at a point of the unit cube. Coordinates outside [0, 1] are clamped
+    pub fn to_run_config(&self, unit: &[f64]) -> RunConfig {
+        let mut config = self.base.clone();
+        for (bound, x) in self.bounds.iter().zip(unit) {
+            let value = bound.low + x.clamp(0.0, 1.0) * (bound.high - bound.low);
+            // os nomes foram checados em validate
+            let _ = set_field(&mut config, &bound.name, value);
+        }
+        config
+    }
+
+    /// Point of the unit cube closest to `config`
+    pub fn to_unit(&self, config: &RunConfig) -> Vec<f64> {
+        self.bounds
+            .iter()
+            .map(|bound| {
+                let value = get_field(config, &bound.name).unwrap_or(bound.low);
+                if bound.high > bound.low {
+                    ((value - bound.low) / (bound.high - bound.low)).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    /// Values of the searched fields at a point of the unit cube, in the order of `bounds`
+    pub fn values(&self, unit: &[f64]) -> Vec<f64> {
+        let config = self.to_run_config(unit);
+        self.bounds
+            .iter()
+            .map(|bound| get_field(&config, &bound.name).unwrap_or(f64::NAN))
+            .collect()
+    }
+}
+
+fn w_division_index(name: &str) -> Option<usize> {
+    name.strip_prefix("w_division_").and_then(|index| index.parse().ok())
+}
+
+pub fn get_field(config: &RunConfig, name: &str) -> Option<f64> {
+    match name {
+        "k_factor" => Some(config.k_factor),
+        "gamma" => Some(config.gamma),
+        "home_advantage" => Some(config.home_advantage),
+        "home_field_advantage_weight" => Some(config.home_field_advantage_weight),
+        "market_value_weight" => Some(config.market_value_weight),
+        "tie_frequency" => Some(config.tie_frequency),
+        "season_regression" => Some(config.season_regression),
+        other => w_division_index(other).and_then(|i| config.w_division.get(i).copied()),
+    }
+}
+
+pub fn set_field(config: &mut RunConfig, name: &str, value: f64) -> Result<(), String> {
+    let field = match name {
+        "k_factor" => &mut config.k_factor,
+        "gamma" => &mut config.gamma,
+        "home_advantage" => &mut config.home_advantage,
+        "home_field_advantage_weight" => &mut config.home_field_advantage_weight,
+        "market_value_weight" => &mut config.market_value_weight,
+        "tie_frequency" => &mut config.tie_frequency,
+        "season_regression" => &mut config.season_regression,
+        other => {
+            let divisions = config.w_division.len();
+            w_division_index(other)
+                .and_then(|i| config.w_division.get_mut(i))
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown parameter '{}'. The config has w_division_0 to w_division_{}",
+                        other,
+                        divisions.saturating_sub(1)
+                    )
+                })?
+        }
+    };
+
+    *field = value;
+    Ok(())
+}
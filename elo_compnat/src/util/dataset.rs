@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use super::parsing::DatasetError;
+
+/// Environment variable that, when set, points to the directory holding the league csvs
+pub const DATA_DIR_ENV: &str = "ELO_COMPNAT_DATA_DIR";
+
+/// Leagues bundled in `elo_compnat/data`
+pub const BUNDLED_LEAGUES: [&str; 6] = [
+    "brasileirao",
+    "inglaterra",
+    "espanha",
+    "italia",
+    "alemanha",
+    "franca",
+];
+
+/// Display name of a league, taken from its csv file name. Bundled leagues get their proper spelling,
+/// anything else keeps the file stem
+pub fn league_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    match stem.as_str() {
+        "brasileirao" => String::from("Brasileirão"),
+        "inglaterra" => String::from("Inglaterra"),
+        "espanha" => String::from("Espanha"),
+        "italia" => String::from("Itália"),
+        "alemanha" => String::from("Alemanha"),
+        "franca" => String::from("França"),
+        _ => stem,
+    }
+}
+
+/// Directories searched for a league csv, in order of priority.
+///
+/// The crate's own `data` folder is resolved at compile time, so the lookup works the same
+/// from the workspace root, `elo_compnat/`, `python/` or `test_elo`. The relative entries are
+/// kept as a fallback for binaries moved away from the source tree.
+fn data_directories() -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+
+    if let Ok(dir) = std::env::var(DATA_DIR_ENV) {
+        directories.push(PathBuf::from(dir));
+    }
+
+    directories.push(Path::new(env!("CARGO_MANIFEST_DIR")).join("data"));
+
+    for relative in ["data", "elo_compnat/data", "../data", "../elo_compnat/data"] {
+        directories.push(PathBuf::from(relative));
+    }
+
+    directories
+}
+
+/// Maps a league name (`"brasileirao"`, `"inglaterra.csv"`) or an explicit path to the csv file
+/// holding its matches.
+///
+/// Explicit paths win over league names, so `"data/espanha.csv"` and `"espanha"` both work.
+/// When nothing is found a [`DatasetError::NotFound`] is returned, which becomes a python
+/// `FileNotFoundError`.
+pub fn resolve_dataset_path(name_or_path: &str) -> Result<PathBuf, DatasetError> {
+    let explicit = Path::new(name_or_path);
+    if explicit.is_file() {
+        return Ok(explicit.to_path_buf());
+    }
+
+    let file_name = if name_or_path.ends_with(".csv") {
+        name_or_path.to_string()
+    } else {
+        format!("{}.csv", name_or_path)
+    };
+
+    let searched: Vec<PathBuf> = data_directories()
+        .into_iter()
+        .map(|dir| dir.join(&file_name))
+        .collect();
+
+    if let Some(found) = searched.iter().find(|candidate| candidate.is_file()) {
+        return Ok(found.clone());
+    }
+
+    let searched_str = searched
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    Err(DatasetError::NotFound {
+        name: name_or_path.to_string(),
+        searched: searched_str,
+        bundled: BUNDLED_LEAGUES.iter().map(|l| l.to_string()).collect(),
+    })
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::elo::train::RatedGame;
+
+use super::parsing::{data_row, DatasetError};
+
+/// Columns appended to the source csv. Existing columns with these names are replaced
+pub const RATING_COLUMNS: [&str; 7] = [
+    "home_elo",
+    "away_elo",
+    "home_elo_after",
+    "away_elo_after",
+    "prob_home",
+    "prob_draw",
+    "prob_away",
+];
+
+/// Writes the source csv back with the ratings of each match appended, keeping the original columns and
+/// row order so it can be loaded straight into pandas. Matches that weren't rated (other divisions, seasons
+/// outside the training window) get empty cells. Returns how many rows got ratings
+pub fn write_rated_games_csv(
+    source: &Path,
+    rated_games: &[RatedGame],
+    output: &Path,
+) -> Result<usize, DatasetError> {
+    let read_error = |source_err: csv::Error| DatasetError::Io {
+        path: source.to_path_buf(),
+        source: source_err,
+    };
+    let write_error = |source_err: csv::Error| DatasetError::Io {
+        path: output.to_path_buf(),
+        source: source_err,
+    };
+
+    let by_row: HashMap<usize, &RatedGame> = rated_games
+        .iter()
+        .filter_map(|rated| rated.game.row.map(|row| (row, rated)))
+        .collect();
+
+    let mut reader = csv::Reader::from_path(source).map_err(read_error)?;
+    let headers = reader.headers().map_err(read_error)?.clone();
+
+    let kept_columns: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !RATING_COLUMNS.contains(name))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut writer = csv::Writer::from_path(output).map_err(write_error)?;
+
+    let mut header_row: Vec<&str> = kept_columns.iter().map(|&i| &headers[i]).collect();
+    header_row.extend(RATING_COLUMNS);
+    writer.write_record(&header_row).map_err(write_error)?;
+
+    let mut written = 0;
+    for record in reader.records() {
+        let record = record.map_err(read_error)?;
+        let row = data_row(&record);
+
+        let mut fields: Vec<String> = kept_columns.iter().map(|&i| record[i].to_string()).collect();
+
+        match row.and_then(|row| by_row.get(&row)) {
+            Some(rated) => {
+                let rating = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+                fields.push(rating(rated.game.home_elo));
+                fields.push(rating(rated.game.away_elo));
+                fields.push(rated.home_elo_after.to_string());
+                fields.push(rated.away_elo_after.to_string());
+                fields.push(rated.prob_home.to_string());
+                fields.push(rated.prob_draw.to_string());
+                fields.push(rated.prob_away.to_string());
+                written += 1;
+            }
+            None => fields.extend(std::iter::repeat_n(String::new(), RATING_COLUMNS.len())),
+        }
+
+        writer.write_record(&fields).map_err(write_error)?;
+    }
+
+    writer.flush().map_err(|e| write_error(e.into()))?;
+
+    Ok(written)
+}
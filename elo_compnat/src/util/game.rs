@@ -1,7 +1,10 @@
+use chrono::NaiveDate;
 use pyo3::prelude::*;
 use serde::{Deserialize, Serialize};
 use skillratings::Outcomes;
 
+use super::parsing::deserialize_season;
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[pyclass]
 pub enum GameResult {
@@ -17,7 +20,7 @@ pub struct Game {
     #[serde(rename = "Week")]
     pub week: f32,
     #[serde(rename = "Date")]
-    pub date: String,
+    pub date: NaiveDate,
     #[serde(rename = "HomeTeam")]
     pub home: String,
     #[serde(rename = "AwayTeam")]
@@ -28,7 +31,8 @@ pub struct Game {
     pub away_score: u16,
     #[serde(rename = "Result")]
     pub result: GameResult,
-    #[serde(rename = "Season")]
+    /// Year the season starts in, `1999` for the 1999-2000 european season
+    #[serde(rename = "Season", deserialize_with = "deserialize_season")]
     pub year: u16,
     #[serde(rename = "Divisao")]
     pub division: u8,
@@ -38,6 +42,13 @@ pub struct Game {
     pub away_value: f64,
     pub home_elo: Option<f64>,
     pub away_elo: Option<f64>,
+    /// Name of the league, filled from the dataset when the csv has no `League` column
+    #[serde(rename = "League", default)]
+    pub league: String,
+    /// Position of the match among the data rows of its csv, unreadable rows included, used to write results
+    /// back next to the original columns
+    #[serde(skip)]
+    pub row: Option<usize>,
 }
 
 impl Game {
@@ -53,9 +64,11 @@ impl Game {
 #[pymethods]
 impl Game {
     #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (week, date, home, away, home_score, away_score, result, year, division, home_value, away_value, home_elo, away_elo, league=String::new()))]
     fn new(
         week: f32,
-        date: String,
+        date: NaiveDate,
         home: String,
         away: String,
         home_score: u16,
@@ -66,7 +79,8 @@ impl Game {
         home_value: f64,
         away_value: f64,
         home_elo: Option<f64>,
-        away_elo: Option<f64>
+        away_elo: Option<f64>,
+        league: String,
     ) -> Game {
         Game {
             week,
@@ -81,7 +95,49 @@ impl Game {
             home_value,
             away_value,
             home_elo,
-            away_elo
+            away_elo,
+            league,
+            row: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Game {
+    /// A 1-0 home win on `date`, in the season of the year of `date` and without market values. Tests
+    /// change the rest with the struct update syntax
+    pub(crate) fn fixture(date: NaiveDate, home: &str, away: &str, division: u8) -> Game {
+        Game {
+            week: 1.0,
+            date,
+            home: home.to_string(),
+            away: away.to_string(),
+            home_score: 1,
+            away_score: 0,
+            result: GameResult::H,
+            year: chrono::Datelike::year(&date) as u16,
+            division,
+            home_value: 0.0,
+            away_value: 0.0,
+            home_elo: None,
+            away_elo: None,
+            league: String::from("Teste"),
+            row: None,
+        }
+    }
+
+    /// The same match with another score, and the result that goes with it
+    pub(crate) fn with_score(self, home_score: u16, away_score: u16) -> Game {
+        let result = match home_score.cmp(&away_score) {
+            std::cmp::Ordering::Greater => GameResult::H,
+            std::cmp::Ordering::Equal => GameResult::D,
+            std::cmp::Ordering::Less => GameResult::A,
+        };
+        Game {
+            home_score,
+            away_score,
+            result,
+            ..self
         }
     }
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-pub fn mean(numbers: &Vec<f64>) -> Option<f64> {
+pub fn mean(numbers: &[f64]) -> Option<f64> {
     if numbers.is_empty() {
         None
     } else {
@@ -33,16 +33,20 @@ pub fn calculate_rmse(elo_diffs: &HashMap<String, f64>, season_match_count: Opti
         None => elo_diffs.len() as u32,
     };
 
+    // soma na ordem dos nomes, para o resultado não depender da ordem aleatória do HashMap
+    let mut diffs: Vec<(&String, &f64)> = elo_diffs.iter().collect();
+    diffs.sort_by_key(|(team, _)| *team);
+
     let mut sum = 0.0;
     let mut sum_squared = 0.0;
 
-    for (_, diff) in elo_diffs {
+    for &(_, diff) in &diffs {
         sum += diff;
     }
 
     let mean = sum / n as f64;
 
-    for (_, diff) in elo_diffs {
+    for &(_, diff) in &diffs {
         let diff_squared = (diff - mean).powi(2);
         if diff_squared.is_infinite() || diff_squared.is_nan() {
             return f64::MAX;
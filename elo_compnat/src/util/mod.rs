@@ -1,3 +1,7 @@
+pub mod dataset;
+pub mod export;
 pub mod game;
 pub mod parsing;
 pub mod math;
+pub mod random;
+pub mod stable_hash;
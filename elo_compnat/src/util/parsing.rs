@@ -1,16 +1,394 @@
+use crate::elo::util::season::regroup_carried_over_matches;
+use crate::util::dataset::league_name;
 use crate::util::game::Game;
-use std::error::Error;
+use chrono::NaiveDate;
+use pyo3::exceptions::{PyFileNotFoundError, PyIOError, PyValueError};
+use pyo3::PyErr;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
 
-pub fn load_csv(path: &str) -> Result<Vec<Game>, Box<dyn Error>> {
+/// Everything that can go wrong while locating or reading a matches csv
+#[derive(Debug, Error)]
+pub enum DatasetError {
+    #[error("Dataset '{name}' not found. Searched: {searched}. Bundled leagues are {bundled:?}")]
+    NotFound {
+        name: String,
+        searched: String,
+        bundled: Vec<String>,
+    },
+    #[error("Could not read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: csv::Error,
+    },
+    #[error("{}:{line}: invalid value '{value}' in column '{column}': {message}", path.display())]
+    InvalidRow {
+        path: PathBuf,
+        line: u64,
+        column: String,
+        value: String,
+        message: String,
+    },
+    #[error("{} has no matches", path.display())]
+    Empty { path: PathBuf },
+    #[error("Could not write parquet file {}: {source}", path.display())]
+    Parquet {
+        path: PathBuf,
+        #[source]
+        source: parquet::errors::ParquetError,
+    },
+}
+
+impl From<DatasetError> for PyErr {
+    fn from(err: DatasetError) -> PyErr {
+        match err {
+            DatasetError::NotFound { .. } => PyFileNotFoundError::new_err(err.to_string()),
+            DatasetError::Io { .. } | DatasetError::Parquet { .. } => {
+                PyIOError::new_err(err.to_string())
+            }
+            DatasetError::InvalidRow { .. } | DatasetError::Empty { .. } => {
+                PyValueError::new_err(err.to_string())
+            }
+        }
+    }
+}
+
+/// How to react to a row that can't be deserialized into a [`Game`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// The first bad row aborts the load
+    #[default]
+    Strict,
+    /// Bad rows are skipped and reported back as warnings
+    Lenient,
+}
+
+/// Date layouts found in the datasets: `29/3/2003` in brasileirao.csv and ISO `1999-08-13` in the
+/// European files. The layout is detected once per file, see [`DateFormat::detect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateFormat {
+    Iso,
+    DayMonthYear,
+    MonthDayYear,
+}
+
+impl DateFormat {
+    const CANDIDATES: [DateFormat; 3] = [
+        DateFormat::Iso,
+        DateFormat::DayMonthYear,
+        DateFormat::MonthDayYear,
+    ];
+
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            DateFormat::Iso => "%Y-%m-%d",
+            DateFormat::DayMonthYear => "%d/%m/%Y",
+            DateFormat::MonthDayYear => "%m/%d/%Y",
+        }
+    }
+
+    pub fn parse(&self, value: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(value.trim(), self.pattern()).ok()
+    }
+
+    /// Parses with the detected layout, accepting ISO dates as well. The brasileirão file mixes
+    /// `29/3/2003` with ISO dates on the second division rows, and ISO can't be misread anyway
+    pub fn parse_or_iso(&self, value: &str) -> Option<NaiveDate> {
+        self.parse(value).or_else(|| DateFormat::Iso.parse(value))
+    }
+
+    /// Picks the layout that parses the most of the given dates. Day first wins ties over month
+    /// first, since that is what the Brazilian data uses
+    pub fn detect<'a>(values: impl Iterator<Item = &'a str> + Clone) -> Option<DateFormat> {
+        let mut best: Option<(DateFormat, usize)> = None;
+
+        for format in DateFormat::CANDIDATES {
+            let parsed = values.clone().filter(|v| format.parse(v).is_some()).count();
+            if parsed > 0 && best.is_none_or(|(_, count)| parsed > count) {
+                best = Some((format, parsed));
+            }
+        }
+
+        best.map(|(format, _)| format)
+    }
+}
+
+/// Matches read from a csv, plus the rows skipped in lenient mode
+#[derive(Debug, Default)]
+pub struct LoadedCsv {
+    pub games: Vec<Game>,
+    pub warnings: Vec<DatasetError>,
+    pub date_format: Option<DateFormat>,
+}
+
+/// Strict load of a matches csv, the first invalid row is returned as the error
+pub fn load_csv(path: &Path) -> Result<Vec<Game>, DatasetError> {
+    load_csv_with_mode(path, ParseMode::Strict).map(|loaded| loaded.games)
+}
+
+pub fn load_csv_with_mode(path: &Path, mode: ParseMode) -> Result<LoadedCsv, DatasetError> {
+    let io_error = |source: csv::Error| DatasetError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut reader = csv::Reader::from_path(path).map_err(io_error)?;
+    let headers = reader.headers().map_err(io_error)?.clone();
+
+    // um registro com o número errado de campos é só mais uma linha inválida no modo tolerante
+    let mut records = Vec::new();
+    let mut unreadable = Vec::new();
+    for record in reader.records() {
+        match (record, mode) {
+            (Ok(record), _) => records.push(record),
+            (Err(e), ParseMode::Strict) => return Err(io_error(e)),
+            (Err(e), ParseMode::Lenient) => unreadable.push(DatasetError::InvalidRow {
+                path: path.to_path_buf(),
+                line: e.position().map(|p| p.line()).unwrap_or(0),
+                column: String::from("?"),
+                value: String::new(),
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    // as datas são normalizadas para ISO antes do serde, que só entende esse formato
+    let date_column = headers.iter().position(|h| h == "Date");
+    let date_format = date_column.and_then(|column| {
+        DateFormat::detect(records.iter().filter_map(move |record| record.get(column)))
+    });
+
+    let league = league_name(path);
+
+    let mut loaded = LoadedCsv {
+        date_format,
+        warnings: unreadable,
+        ..LoadedCsv::default()
+    };
+
+    for record in records {
+        let record = match (date_column, date_format) {
+            (Some(column), Some(format)) => match normalize_date(&record, column, format) {
+                Ok(normalized) => normalized,
+                Err(message) => {
+                    let row_error = DatasetError::InvalidRow {
+                        path: path.to_path_buf(),
+                        line: record.position().map(|p| p.line()).unwrap_or(0),
+                        column: String::from("Date"),
+                        value: record.get(column).unwrap_or("").to_string(),
+                        message,
+                    };
+                    match mode {
+                        ParseMode::Strict => return Err(row_error),
+                        ParseMode::Lenient => {
+                            loaded.warnings.push(row_error);
+                            continue;
+                        }
+                    }
+                }
+            },
+            _ => record,
+        };
+
+        match record.deserialize::<Game>(Some(&headers)) {
+            Ok(mut game) => {
+                if game.league.is_empty() {
+                    game.league = league.clone();
+                }
+                game.row = data_row(&record);
+                loaded.games.push(game)
+            }
+            Err(e) => {
+                let line = record.position().map(|p| p.line()).unwrap_or(0);
+
+                // o csv só informa o índice da coluna, buscamos o nome e o valor no próprio registro
+                let (column, value, message) = match e.kind() {
+                    csv::ErrorKind::Deserialize { err, .. } => {
+                        let index = err
+                            .field()
+                            .map(|f| f as usize)
+                            .or_else(|| locate_quoted_value(&err.kind().to_string(), &record));
+                        let column = index
+                            .and_then(|i| headers.get(i))
+                            .unwrap_or("?")
+                            .to_string();
+                        let value = index.and_then(|i| record.get(i)).unwrap_or("").to_string();
+                        (column, value, err.kind().to_string())
+                    }
+                    other => (String::from("?"), String::new(), format!("{:?}", other)),
+                };
+
+                let row_error = DatasetError::InvalidRow {
+                    path: path.to_path_buf(),
+                    line,
+                    column,
+                    value,
+                    message,
+                };
 
-    let mut reader = csv::Reader::from_path(path)?;
-    let result: Vec<Game> = reader
-        .deserialize()
-        .map(|r: Result<Game, csv::Error>| r.unwrap())
+                match mode {
+                    ParseMode::Strict => return Err(row_error),
+                    ParseMode::Lenient => loaded.warnings.push(row_error),
+                }
+            }
+        }
+    }
+
+    if loaded.games.is_empty() {
+        return Err(DatasetError::Empty {
+            path: path.to_path_buf(),
+        });
+    }
+
+    // o fim do Brasileirão 2020 foi jogado (e rotulado) em 2021
+    regroup_carried_over_matches(&mut loaded.games);
+
+    Ok(loaded)
+}
+
+/// Index of `record` among the data rows of its file, counting the unreadable ones, so it points at the
+/// same row whatever was skipped before it
+pub(crate) fn data_row(record: &csv::StringRecord) -> Option<usize> {
+    // o cabeçalho é o registro 0
+    record.position().map(|position| position.record().saturating_sub(1) as usize)
+}
+
+/// Rewrites the date column of a record in ISO format, keeping its position for error reporting
+fn normalize_date(
+    record: &csv::StringRecord,
+    column: usize,
+    format: DateFormat,
+) -> Result<csv::StringRecord, String> {
+    let raw = record.get(column).unwrap_or("");
+    let date = format
+        .parse_or_iso(raw)
+        .ok_or_else(|| format!("expected a date formatted as {}", format.pattern()))?;
+    let iso = date.format("%Y-%m-%d").to_string();
+
+    let mut normalized: csv::StringRecord = record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| if i == column { iso.as_str() } else { field })
         .collect();
-    Ok(result)
+    normalized.set_position(record.position().cloned());
+
+    Ok(normalized)
+}
+
+/// Reads the `Season` column, which may hold the starting year (`1999`) or a label spanning two years
+/// (`1999-2000`, `1999/2000`, `1999/00`). Seasons are identified by the year they start in
+pub fn parse_season_label(label: &str) -> Option<u16> {
+    let label = label.trim();
+    // o csv às vezes salva o ano como float (1999.0)
+    let label = label.strip_suffix(".0").unwrap_or(label);
+
+    match label.split_once(['-', '/']) {
+        None => label.parse().ok(),
+        Some((start, end)) => {
+            let start: u16 = start.trim().parse().ok()?;
+            let end: u16 = end.trim().parse().ok()?;
+            let end = match end {
+                0..=99 => start - start % 100 + end + if end < start % 100 { 100 } else { 0 },
+                _ => end,
+            };
+            (end == start + 1).then_some(start)
+        }
+    }
+}
+
+pub(crate) fn deserialize_season<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let label = <String as serde::Deserialize>::deserialize(deserializer)?;
+    parse_season_label(&label)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid season `{}`", label)))
+}
+
+/// Erros vindos do serde (ex: variante desconhecida em `Result`) não trazem o índice da coluna,
+/// mas citam o valor entre crases. Procuramos esse valor no registro para recuperar a coluna
+fn locate_quoted_value(message: &str, record: &csv::StringRecord) -> Option<usize> {
+    let quoted = message.split('`').nth(1)?;
+    record.iter().position(|field| field == quoted)
 }
 
 pub fn filter_by_year(games: &[Game], year: u16) -> Vec<Game> {
     games.iter().filter(|p| p.year == year).cloned().collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "Week,Date,HomeTeam,AwayTeam,HomeGoals,AwayGoals,Season,Result,HomeTeam_value,AwayTeam_value,Divisao,HomeTeam_value_norm1,AwayTeam_value_norm1,HomeTeam_value_norm2,AwayTeam_value_norm2";
+
+    fn write_csv(name: &str, rows: &[&str]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("elo_compnat_{}_{}.csv", name, std::process::id()));
+        std::fs::write(&path, format!("{}\n{}\n", HEADER, rows.join("\n"))).unwrap();
+        path
+    }
+
+    #[test]
+    fn lenient_mode_skips_rows_with_the_wrong_field_count() {
+        let path = write_csv(
+            "field_count",
+            &[
+                "1.0,2020-08-08,A,B,1,0,2020,H,0.0,0.0,1,0.0,0.0,0.0,0.0",
+                "1.0,2020-08-08,C,D",
+                "1.0,2020-08-09,D,C,2,2,2020,D,0.0,0.0,1,0.0,0.0,0.0,0.0",
+            ],
+        );
+
+        let loaded = load_csv_with_mode(&path, ParseMode::Lenient).unwrap();
+        assert_eq!(loaded.games.len(), 2);
+        assert_eq!(loaded.warnings.len(), 1);
+        match &loaded.warnings[0] {
+            DatasetError::InvalidRow { line, .. } => assert_eq!(*line, 3),
+            other => panic!("unexpected warning {:?}", other),
+        }
+
+        assert!(load_csv_with_mode(&path, ParseMode::Strict).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rows_keep_their_position_after_skipped_records() {
+        let path = write_csv(
+            "row_index",
+            &[
+                "1.0,2020-08-08,A,B,1,0,2020,H,0.0,0.0,1,0.0,0.0,0.0,0.0",
+                "1.0,2020-08-08,C,D",
+                "1.0,2020-08-09,D,C,x,2,2020,D,0.0,0.0,1,0.0,0.0,0.0,0.0",
+                "1.0,2020-08-10,B,A,2,2,2020,D,0.0,0.0,1,0.0,0.0,0.0,0.0",
+            ],
+        );
+
+        let loaded = load_csv_with_mode(&path, ParseMode::Lenient).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let rows: Vec<Option<usize>> = loaded.games.iter().map(|game| game.row).collect();
+        assert_eq!(rows, vec![Some(0), Some(3)]);
+        assert_eq!(loaded.warnings.len(), 2);
+    }
+
+    #[test]
+    fn invalid_rows_report_their_line_in_the_file() {
+        let path = write_csv(
+            "line_number",
+            &[
+                "1.0,2020-08-08,A,B,x,0,2020,H,0.0,0.0,1,0.0,0.0,0.0,0.0",
+                "1.0,2020-08-09,B,A,1,0,2020,H,0.0,0.0,1,0.0,0.0,0.0,0.0",
+            ],
+        );
+
+        match load_csv_with_mode(&path, ParseMode::Strict) {
+            Err(DatasetError::InvalidRow { line, column, .. }) => {
+                assert_eq!(line, 2);
+                assert_eq!(column, "HomeGoals");
+            }
+            other => panic!("expected an invalid row, got {:?}", other),
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
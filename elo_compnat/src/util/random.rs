@@ -0,0 +1,15 @@
+/// Seed of one random stream, derived from a master seed and the position of the stream (e.g. variation
+/// and season). Each part goes through the SplitMix64 finalizer, so neighbouring streams are unrelated and
+/// the result depends only on the inputs, never on which thread asks for it
+pub fn derive_seed(master_seed: u64, stream: &[u64]) -> u64 {
+    stream
+        .iter()
+        .fold(splitmix64(master_seed), |seed, &part| splitmix64(seed ^ splitmix64(part)))
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
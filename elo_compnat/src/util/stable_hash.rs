@@ -0,0 +1,277 @@
+use sha2::{Digest, Sha256};
+
+use super::game::{Game, GameResult};
+use crate::experimentation::run_config::{RunConfig, RunHyperparameters};
+
+/// SHA-256 over an explicit encoding of the values written to it, so the result is the same across runs,
+/// platforms and compiler versions (unlike `std::hash`). Floats are hashed by their bits, and strings
+/// and sequences carry their length so `("ab", "c")` and `("a", "bc")` differ
+pub struct StableHasher {
+    digest: Sha256,
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher::new()
+    }
+}
+
+impl StableHasher {
+    pub fn new() -> Self {
+        StableHasher { digest: Sha256::new() }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.digest.update(value.to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.write_u64(value.to_bits());
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.digest.update([value as u8]);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.digest.update(value.as_bytes());
+    }
+
+    pub fn write<T: StableHash + ?Sized>(&mut self, value: &T) {
+        value.stable_hash(self);
+    }
+
+    /// Lowercase hex of the digest
+    pub fn finish(self) -> String {
+        self.digest
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Values that can be written to a [`StableHasher`]. Every field takes part, so two values hash the
+/// same only when they are equal
+pub trait StableHash {
+    fn stable_hash(&self, hasher: &mut StableHasher);
+
+    fn stable_digest(&self) -> String {
+        let mut hasher = StableHasher::new();
+        self.stable_hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl<T: StableHash> StableHash for [T] {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_u64(self.len() as u64);
+        for item in self {
+            item.stable_hash(hasher);
+        }
+    }
+}
+
+impl StableHash for f64 {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_f64(*self);
+    }
+}
+
+impl<T: StableHash> StableHash for Option<T> {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_bool(self.is_some());
+        if let Some(value) = self {
+            value.stable_hash(hasher);
+        }
+    }
+}
+
+impl StableHash for RunConfig {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_f64(self.k_factor);
+        hasher.write_f64(self.gamma);
+        hasher.write_f64(self.home_advantage);
+        hasher.write_f64(self.home_field_advantage_weight);
+        hasher.write_f64(self.market_value_weight);
+        hasher.write_f64(self.tie_frequency);
+        hasher.write_f64(self.season_regression);
+        hasher.write(self.w_division.as_slice());
+    }
+}
+
+impl StableHash for RunHyperparameters {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_u64(self.starting_elo as u64);
+        hasher.write_u64(self.starting_year as u64);
+        hasher.write_u64(self.backtest_years as u64);
+        hasher.write_u64(self.random_variations as u64);
+        hasher.write_bool(self.use_goals_diff);
+        hasher.write_bool(self.use_home_advantage);
+        hasher.write_bool(self.use_market_values);
+        hasher.write_u64(self.leagues_to_use as u64);
+    }
+}
+
+/// Leaves `row` out: it says where the match was read from, not what happened in it
+impl StableHash for Game {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_u64(self.week.to_bits() as u64);
+        hasher.write_str(&self.date.to_string());
+        hasher.write_str(&self.home);
+        hasher.write_str(&self.away);
+        hasher.write_u64(self.home_score as u64);
+        hasher.write_u64(self.away_score as u64);
+        hasher.write_u64(match self.result {
+            GameResult::H => 0,
+            GameResult::D => 1,
+            GameResult::A => 2,
+        });
+        hasher.write_u64(self.year as u64);
+        hasher.write_u64(self.division as u64);
+        hasher.write_f64(self.home_value);
+        hasher.write_f64(self.away_value);
+        hasher.write(&self.home_elo);
+        hasher.write(&self.away_elo);
+        hasher.write_str(&self.league);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn digest(write: impl FnOnce(&mut StableHasher)) -> String {
+        let mut hasher = StableHasher::new();
+        write(&mut hasher);
+        hasher.finish()
+    }
+
+    fn game() -> Game {
+        let date = NaiveDate::from_ymd_opt(2021, 6, 13).unwrap();
+        Game {
+            week: 3.0,
+            home_value: 0.8,
+            away_value: 0.75,
+            league: String::from("Brasileirao"),
+            row: Some(7),
+            ..Game::fixture(date, "Flamengo", "Palmeiras", 1).with_score(2, 1)
+        }
+    }
+
+    #[test]
+    fn the_digest_doesnt_change_between_runs() {
+        // valores fixos: se mudarem, todo cache e checkpoint em disco deixa de valer
+        assert_eq!(
+            digest(|hasher| {
+                hasher.write_u64(1);
+                hasher.write_str("ab");
+                hasher.write_f64(0.5);
+                hasher.write_bool(true);
+            }),
+            "1abfe659dfc546cf8bbd790b7efb093854be3737bf1d8ff4d90d39f5833a3a46"
+        );
+        assert_eq!(RunConfig::default().stable_digest(), "7237e7f124b169ff47e8039c2461f98456c89a05ecfcca1c840db43bcfa74b2e");
+        assert_eq!(game().stable_digest(), "33d41fd07c44be8c83697245145755d8f446828faf98619c1257edb56ca55826");
+    }
+
+    #[test]
+    fn lengths_keep_neighbouring_values_apart() {
+        let split = |a: &str, b: &str| {
+            digest(|hasher| {
+                hasher.write_str(a);
+                hasher.write_str(b);
+            })
+        };
+        assert_ne!(split("ab", "c"), split("a", "bc"));
+
+        let slices = |a: &[f64], b: &[f64]| {
+            digest(|hasher| {
+                hasher.write(a);
+                hasher.write(b);
+            })
+        };
+        assert_ne!(slices(&[1.0, 2.0], &[3.0]), slices(&[1.0], &[2.0, 3.0]));
+        assert_ne!(slices(&[], &[1.0]), slices(&[1.0], &[]));
+
+        assert_ne!(None::<f64>.stable_digest(), Some(0.0).stable_digest());
+    }
+
+    #[test]
+    fn floats_are_hashed_by_their_bits() {
+        assert_ne!(0.0.stable_digest(), (-0.0).stable_digest());
+        assert_eq!(f64::NAN.stable_digest(), f64::NAN.stable_digest());
+    }
+
+    #[test]
+    fn every_field_takes_part() {
+        let base = RunConfig::default();
+        let changes: Vec<fn(&mut RunConfig)> = vec![
+            |config| config.k_factor += 1.0,
+            |config| config.gamma += 1.0,
+            |config| config.home_advantage += 1.0,
+            |config| config.home_field_advantage_weight += 1.0,
+            |config| config.market_value_weight += 1.0,
+            |config| config.tie_frequency += 0.1,
+            |config| config.season_regression += 0.1,
+            |config| config.w_division.push(1.0),
+        ];
+        for change in changes {
+            let mut config = base.clone();
+            change(&mut config);
+            assert_ne!(config.stable_digest(), base.stable_digest(), "{:?}", config);
+        }
+
+        let base = RunHyperparameters::default();
+        let changes: Vec<fn(&mut RunHyperparameters)> = vec![
+            |hyperparameters| hyperparameters.starting_elo += 1,
+            |hyperparameters| hyperparameters.starting_year += 1,
+            |hyperparameters| hyperparameters.backtest_years += 1,
+            |hyperparameters| hyperparameters.random_variations += 1,
+            |hyperparameters| hyperparameters.use_goals_diff ^= true,
+            |hyperparameters| hyperparameters.use_home_advantage ^= true,
+            |hyperparameters| hyperparameters.use_market_values ^= true,
+            |hyperparameters| hyperparameters.leagues_to_use += 1,
+        ];
+        for change in changes {
+            let mut hyperparameters = base.clone();
+            change(&mut hyperparameters);
+            assert_ne!(hyperparameters.stable_digest(), base.stable_digest());
+        }
+
+        let base = game();
+        let changes: Vec<fn(&mut Game)> = vec![
+            |game| game.week += 1.0,
+            |game| game.date = game.date.succ_opt().unwrap(),
+            |game| game.home.push('!'),
+            |game| game.away.push('!'),
+            |game| game.home_score += 1,
+            |game| game.away_score += 1,
+            |game| game.result = GameResult::D,
+            |game| game.year += 1,
+            |game| game.division += 1,
+            |game| game.home_value += 0.1,
+            |game| game.away_value += 0.1,
+            |game| game.home_elo = Some(1000.0),
+            |game| game.away_elo = Some(1000.0),
+            |game| game.league.push('!'),
+        ];
+        for change in changes {
+            let mut changed = base.clone();
+            change(&mut changed);
+            assert_ne!(changed.stable_digest(), base.stable_digest(), "{:?}", changed);
+        }
+    }
+
+    #[test]
+    fn the_row_is_not_part_of_the_digest() {
+        // a mesma partida lida de outra linha, ou construída à mão, é o mesmo dado
+        for row in [None, Some(0), Some(8)] {
+            let moved = Game { row, ..game() };
+            assert_eq!(moved.stable_digest(), game().stable_digest());
+        }
+    }
+}
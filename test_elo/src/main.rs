@@ -1,3 +1,5 @@
+use pyo3::Python;
+
 use elo_compnat::{
     self,
     experimentation::run_config::{RunConfig, RunHyperparameters}
@@ -8,32 +10,38 @@ fn main() {
     let x = vec![  2.49518433,   2.79995614,   2.65563014 ,  1.80708322 ,  1.28271224, 0.38,   125.16330199, 115.40319719];
     let hyper_params_list = vec![1000, 2003, 8, 100, 1, 1, 1, 1];
 
-    let desired_config = RunConfig::from_python_list(x.clone());
+    let desired_config = RunConfig::from_python_list(x.clone()).unwrap();
 
     println!("Generated config: {:?}", desired_config);
 
-    let desired_hyperparams = RunHyperparameters::from_python_list(hyper_params_list.clone());
+    let desired_hyperparams = RunHyperparameters::from_python_list(hyper_params_list.clone()).unwrap();
 
 
-    let default_param = RunHyperparameters::default();
-    let test_config = RunConfig {
+    let _default_param = RunHyperparameters::default();
+    let _test_config = RunConfig {
         k_factor: 1.5,
         gamma: 1.2,
         home_advantage: 50.0,
         home_field_advantage_weight: 1.2,
         market_value_weight: 1.2,
         tie_frequency: 0.30,
+        season_regression: 0.0,
         w_division: vec![20.0, 10.0],
 
     };
-    let fitness = elo_compnat::fitness_function("brasileirao", x, hyper_params_list);
-    //elo_compnat::run(default_param, Some(&test_config)).unwrap();
 
-    match fitness {
-        Ok(fit) => println!("Fitness: {:?}", fit),
-        Err(e) => println!("Error: {}", e),
-    }
+    // as funções do módulo python liberam o GIL, então precisam de um interpretador mesmo rodando daqui
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let fitness = elo_compnat::fitness_function(py, "brasileirao", x, hyper_params_list, 0, "rmse", None);
+        //elo_compnat::run(py, default_param, Some(&test_config), None, 0).unwrap();
+
+        match fitness {
+            Ok(fit) => println!("Fitness: {:?}", fit),
+            Err(e) => println!("Error: {}", e),
+        }
 
-    elo_compnat::run(desired_hyperparams, Some(&desired_config)).unwrap();
+        elo_compnat::run(py, desired_hyperparams, Some(&desired_config), None, 0).unwrap();
+    });
 
 }